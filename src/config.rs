@@ -1,13 +1,48 @@
-use crate::models::Config;
+use crate::file_processor::Layout;
+use crate::grader::context_budget::OverflowStrategy;
+use crate::grader::ProviderKind;
+use crate::models::{Config, ScoreUnit};
 use anyhow::{Context, Result};
 use serde::Deserialize;
+use std::collections::HashSet;
 use std::fs;
+use std::path::Path;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct AiConfig {
+    pub provider: Option<ProviderKind>,
+    pub endpoint: Option<String>,
+    pub model: Option<String>,
     pub timeout_secs: Option<u64>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
+    /// Maximum number of submissions graded at the same time
+    pub concurrency: Option<usize>,
+    /// Tokens the model attends to, prompt and reply together (defaults to the model's window)
+    pub context_tokens: Option<usize>,
+    /// How submissions larger than the context are graded: `map-reduce` or `truncate`
+    pub overflow: Option<OverflowStrategy>,
+    /// Requests sent per minute at most, across all concurrent workers
+    pub requests_per_minute: Option<u32>,
+    /// Estimated prompt and reply tokens per minute at most, across all concurrent workers
+    pub tokens_per_minute: Option<u32>,
+    /// Retries of failed model requests
+    pub retry: Option<RetryConfig>,
+    /// Constrain replies to a JSON schema built from the rubric where the provider supports it
+    /// (OpenAI `response_format`, Ollama `format`); on by default
+    pub structured_output: Option<bool>,
+    /// Follow-up requests asking the model to correct a reply that is not a valid grade
+    pub repair_attempts: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RetryConfig {
+    /// Attempts per request, the first one included
+    pub max_attempts: Option<u32>,
+    /// Wait before the first retry, doubled for each further one
+    pub initial_delay_ms: Option<u64>,
+    /// Longest wait between two attempts, unless the server asks for more
+    pub max_delay_secs: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -15,10 +50,50 @@ pub struct PromptConfig {
     pub template: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CacheConfig {
+    /// Set to false to always query the model (same as --no-cache)
+    pub enabled: Option<bool>,
+    /// Directory holding cached responses
+    pub dir: Option<String>,
+    /// Ignore stored responses and overwrite them (same as --refresh-cache)
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReportConfig {
+    /// Total percentage below which scores are highlighted as failing
+    pub passing_score: Option<f32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct InputConfig {
+    /// How students are identified from submission paths (same as --layout)
+    pub layout: Option<Layout>,
+    /// Class roster CSV submissions are checked against (same as --roster)
+    pub roster: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
     pub ai: Option<AiConfig>,
     pub prompt: Option<PromptConfig>,
+    pub report: Option<ReportConfig>,
+    pub cache: Option<CacheConfig>,
+    pub input: Option<InputConfig>,
+}
+
+/// Settings supplied on the command line that take precedence over `config.toml`
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    pub model_endpoint: Option<String>,
+    pub provider: Option<ProviderKind>,
+    pub model: Option<String>,
+    pub concurrency: Option<usize>,
+    pub no_cache: bool,
+    pub refresh_cache: bool,
+    pub layout: Option<Layout>,
+    pub roster: Option<String>,
 }
 
 impl AppConfig {
@@ -30,8 +105,56 @@ impl AppConfig {
         let app_config: AppConfig = settings
             .try_deserialize()
             .with_context(|| format!("Failed to parse TOML config: {}", path))?;
+        app_config
+            .validate()
+            .with_context(|| format!("Invalid TOML config: {}", path))?;
         Ok(app_config)
     }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(ai) = &self.ai {
+            if ai.requests_per_minute == Some(0) {
+                anyhow::bail!("AI requests_per_minute must be positive");
+            }
+            if ai.tokens_per_minute == Some(0) {
+                anyhow::bail!("AI tokens_per_minute must be positive");
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply command line overrides on top of the values read from TOML
+    pub fn apply_overrides(&mut self, overrides: &Overrides) {
+        let ai = self.ai.get_or_insert_with(AiConfig::default);
+        if let Some(endpoint) = &overrides.model_endpoint {
+            ai.endpoint = Some(endpoint.clone());
+        }
+        if let Some(provider) = overrides.provider {
+            ai.provider = Some(provider);
+        }
+        if let Some(model) = &overrides.model {
+            ai.model = Some(model.clone());
+        }
+        if let Some(concurrency) = overrides.concurrency {
+            ai.concurrency = Some(concurrency);
+        }
+
+        let cache = self.cache.get_or_insert_with(CacheConfig::default);
+        if overrides.no_cache {
+            cache.enabled = Some(false);
+        }
+        if overrides.refresh_cache {
+            cache.refresh = Some(true);
+        }
+
+        let input = self.input.get_or_insert_with(InputConfig::default);
+        if let Some(layout) = overrides.layout {
+            input.layout = Some(layout);
+        }
+        if let Some(roster) = &overrides.roster {
+            input.roster = Some(roster.clone());
+        }
+    }
 }
 
 impl Config {
@@ -39,9 +162,30 @@ impl Config {
         let content = fs::read_to_string(path)
             .with_context(|| format!("Failed to read config file: {}", path))?;
 
-        let config: Config = serde_json::from_str(&content)
+        let mut config: Config = serde_json::from_str(&content)
             .with_context(|| format!("Failed to parse config file: {}", path))?;
 
+        // Older configs only define the fixed three-dimension weights
+        if config.rubric.is_empty() {
+            if let Some(strategy) = &config.grading_strategy {
+                config.rubric = strategy.to_rubric();
+            }
+        }
+
+        // Instructor test files are given relative to the config file
+        if let Some(execution) = &mut config.execution {
+            let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+            for file in execution
+                .test_cases
+                .iter_mut()
+                .flat_map(|case| case.files.iter_mut())
+            {
+                if Path::new(file.as_str()).is_relative() {
+                    *file = base.join(&*file).to_string_lossy().into_owned();
+                }
+            }
+        }
+
         // Validate config
         config.validate()?;
 
@@ -53,18 +197,300 @@ impl Config {
             anyhow::bail!("Task description cannot be empty");
         }
 
-        if self.evaluation_criteria.is_empty() {
-            anyhow::bail!("Evaluation criteria cannot be empty");
+        if self.rubric.is_empty() {
+            anyhow::bail!("Rubric cannot be empty (define `rubric` or `grading_strategy`)");
+        }
+
+        let mut seen_ids = HashSet::new();
+        for criterion in &self.rubric {
+            let id = criterion.id.as_str();
+            if id.is_empty()
+                || !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                anyhow::bail!(
+                    "Rubric criterion id '{}' must be non-empty and contain only letters, digits, '_' or '-'",
+                    id
+                );
+            }
+            if !seen_ids.insert(id) {
+                anyhow::bail!("Duplicate rubric criterion id: {}", id);
+            }
+            if criterion.description.trim().is_empty() {
+                anyhow::bail!("Rubric criterion '{}' has an empty description", id);
+            }
+            if !(criterion.max_points.is_finite() && criterion.max_points > 0.0) {
+                anyhow::bail!(
+                    "Rubric criterion '{}' must have positive max_points, got: {}",
+                    id,
+                    criterion.max_points
+                );
+            }
+            if !(criterion.weight.is_finite() && criterion.weight >= 0.0) {
+                anyhow::bail!(
+                    "Rubric criterion '{}' must have a non-negative weight, got: {}",
+                    id,
+                    criterion.weight
+                );
+            }
         }
 
-        let strategy = &self.grading_strategy;
-        let total_weight =
-            strategy.correctness_weight + strategy.style_weight + strategy.edge_cases_weight;
+        if !(self.scale.total.is_finite() && self.scale.total > 0.0) {
+            anyhow::bail!(
+                "Score scale total must be positive, got: {}",
+                self.scale.total
+            );
+        }
+        if self.scale.unit == ScoreUnit::Percent && self.scale.total != 100.0 {
+            anyhow::bail!(
+                "A percent score scale has a total of 100, got: {}",
+                self.scale.total
+            );
+        }
+        if let Some(step) = self.scale.step {
+            if !(step.is_finite() && step > 0.0) {
+                anyhow::bail!("Score scale step must be positive, got: {}", step);
+            }
+            let steps = self.scale.total / step;
+            if (steps - steps.round()).abs() > 1e-3 {
+                anyhow::bail!(
+                    "Score scale step must divide the total of {}, got: {}",
+                    self.scale.total,
+                    step
+                );
+            }
+        }
 
+        let total_weight: f32 = self.rubric.iter().map(|c| c.weight).sum();
         if (total_weight - 1.0).abs() > 0.01 {
-            anyhow::bail!("Grading weights must sum to 1.0, got: {}", total_weight);
+            anyhow::bail!("Rubric weights must sum to 1.0, got: {}", total_weight);
+        }
+
+        if let Some(execution) = &self.execution {
+            if let Some(id) = &execution.criterion {
+                if !seen_ids.contains(id.as_str()) {
+                    anyhow::bail!("Execution criterion '{}' is not a rubric criterion", id);
+                }
+            }
+            for (name, value) in [
+                ("timeout_secs", execution.timeout_secs),
+                ("build_timeout_secs", execution.build_timeout_secs),
+                ("memory_mb", execution.memory_mb),
+                ("build_memory_mb", execution.build_memory_mb),
+            ] {
+                if value == Some(0) {
+                    anyhow::bail!("Execution {} must be positive", name);
+                }
+            }
+            if execution.test_cases.is_empty() {
+                anyhow::bail!("Execution requires at least one test case");
+            }
+            for case in &execution.test_cases {
+                if case.name.trim().is_empty() {
+                    anyhow::bail!("Execution test case names cannot be empty");
+                }
+                if matches!(&case.command, Some(command) if command.is_empty()) {
+                    anyhow::bail!("Test case '{}' has an empty command", case.name);
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn valid() -> Value {
+        json!({
+            "task_description": "Sum a list",
+            "rubric": [
+                {"id": "functionality", "description": "Works", "max_points": 40, "weight": 0.75},
+                {"id": "style", "description": "Readable", "max_points": 10, "weight": 0.25}
+            ],
+            "execution": {
+                "criterion": "functionality",
+                "test_cases": [{"name": "basic", "stdin": "1 2", "expected_stdout": "3"}]
+            }
+        })
+    }
+
+    /// Change made to a valid config to break it
+    type Mutation = fn(&mut Value);
+
+    fn validate(config: Value) -> Result<()> {
+        serde_json::from_value::<Config>(config).unwrap().validate()
+    }
+
+    #[test]
+    fn accepts_a_valid_config() {
+        validate(valid()).unwrap();
+
+        let mut config = valid();
+        config["scale"] = json!({"total": 20, "step": 0.5});
+        validate(config).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let cases: &[(&str, Mutation, &str)] = &[
+            (
+                "empty task",
+                |c| c["task_description"] = json!("  "),
+                "Task description cannot be empty",
+            ),
+            (
+                "empty rubric",
+                |c| c["rubric"] = json!([]),
+                "Rubric cannot be empty",
+            ),
+            (
+                "invalid id",
+                |c| c["rubric"][0]["id"] = json!("edge cases"),
+                "Rubric criterion id 'edge cases' must be non-empty",
+            ),
+            (
+                "empty id",
+                |c| c["rubric"][0]["id"] = json!(""),
+                "Rubric criterion id '' must be non-empty",
+            ),
+            (
+                "duplicate id",
+                |c| c["rubric"][1]["id"] = json!("functionality"),
+                "Duplicate rubric criterion id: functionality",
+            ),
+            (
+                "empty description",
+                |c| c["rubric"][1]["description"] = json!(""),
+                "Rubric criterion 'style' has an empty description",
+            ),
+            (
+                "zero max_points",
+                |c| c["rubric"][0]["max_points"] = json!(0),
+                "Rubric criterion 'functionality' must have positive max_points, got: 0",
+            ),
+            (
+                "negative max_points",
+                |c| c["rubric"][1]["max_points"] = json!(-5),
+                "Rubric criterion 'style' must have positive max_points, got: -5",
+            ),
+            (
+                "negative weight",
+                |c| {
+                    c["rubric"][0]["weight"] = json!(1.25);
+                    c["rubric"][1]["weight"] = json!(-0.25);
+                },
+                "Rubric criterion 'style' must have a non-negative weight, got: -0.25",
+            ),
+            (
+                "weights not summing to 1",
+                |c| c["rubric"][1]["weight"] = json!(0.5),
+                "Rubric weights must sum to 1.0, got: 1.25",
+            ),
+            (
+                "zero scale total",
+                |c| c["scale"] = json!({"total": 0}),
+                "Score scale total must be positive, got: 0",
+            ),
+            (
+                "percent scale not out of 100",
+                |c| c["scale"] = json!({"total": 20, "unit": "percent"}),
+                "A percent score scale has a total of 100, got: 20",
+            ),
+            (
+                "zero scale step",
+                |c| c["scale"] = json!({"step": 0}),
+                "Score scale step must be positive, got: 0",
+            ),
+            (
+                "scale step not dividing the total",
+                |c| c["scale"] = json!({"total": 20, "step": 3}),
+                "Score scale step must divide the total of 20, got: 3",
+            ),
+            (
+                "unknown execution criterion",
+                |c| c["execution"]["criterion"] = json!("tests"),
+                "Execution criterion 'tests' is not a rubric criterion",
+            ),
+            (
+                "zero timeout",
+                |c| c["execution"]["timeout_secs"] = json!(0),
+                "Execution timeout_secs must be positive",
+            ),
+            (
+                "zero build timeout",
+                |c| c["execution"]["build_timeout_secs"] = json!(0),
+                "Execution build_timeout_secs must be positive",
+            ),
+            (
+                "zero memory",
+                |c| c["execution"]["memory_mb"] = json!(0),
+                "Execution memory_mb must be positive",
+            ),
+            (
+                "zero build memory",
+                |c| c["execution"]["build_memory_mb"] = json!(0),
+                "Execution build_memory_mb must be positive",
+            ),
+            (
+                "no test cases",
+                |c| c["execution"]["test_cases"] = json!([]),
+                "Execution requires at least one test case",
+            ),
+            (
+                "unnamed test case",
+                |c| c["execution"]["test_cases"][0]["name"] = json!(" "),
+                "Execution test case names cannot be empty",
+            ),
+            (
+                "empty test command",
+                |c| c["execution"]["test_cases"][0]["command"] = json!([]),
+                "Test case 'basic' has an empty command",
+            ),
+        ];
+
+        for (name, mutate, expected) in cases {
+            let mut config = valid();
+            mutate(&mut config);
+            let error = validate(config).expect_err(name);
+            assert!(
+                error.to_string().starts_with(expected),
+                "{}: {}",
+                name,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_zero_rate_limits() {
+        let app_config =
+            |ai: Value| -> AppConfig { serde_json::from_value(json!({"ai": ai})).unwrap() };
+        app_config(json!({"requests_per_minute": 60, "tokens_per_minute": 40000}))
+            .validate()
+            .unwrap();
+        app_config(json!({})).validate().unwrap();
+
+        let cases: &[(&str, Value, &str)] = &[
+            (
+                "zero requests per minute",
+                json!({"requests_per_minute": 0}),
+                "AI requests_per_minute must be positive",
+            ),
+            (
+                "zero tokens per minute",
+                json!({"requests_per_minute": 60, "tokens_per_minute": 0}),
+                "AI tokens_per_minute must be positive",
+            ),
+        ];
+
+        for (name, ai, expected) in cases {
+            let error = app_config(ai.clone()).validate().expect_err(name);
+            assert_eq!(error.to_string(), *expected, "{}", name);
+        }
+    }
+}
@@ -1,10 +1,28 @@
-use crate::models::GradingResult;
+use crate::models::{Criterion, GradingResult, GradingStatus, ScoreScale};
 use anyhow::Result;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 use tracing::info;
+use umya_spreadsheet::helper::coordinate::coordinate_from_index;
+use umya_spreadsheet::{
+    ConditionalFormatValues, ConditionalFormatting, ConditionalFormattingRule, Formula, Style,
+    Worksheet,
+};
 
-pub struct ExcelGenerator;
+/// Default percentage below which a total is highlighted as failing
+pub const DEFAULT_PASSING_SCORE: f32 = 60.0;
+
+const SUMMARY_SHEET: &str = "Summary";
+const CRITERIA_SHEET: &str = "Criteria";
+const STATISTICS_SHEET: &str = "Statistics";
+const SCORE_FORMAT: &str = "0.00";
+const HISTOGRAM_BUCKETS: usize = 10;
+
+pub struct ExcelGenerator {
+    passing_score: f32,
+    scale: ScoreScale,
+}
 
 impl Default for ExcelGenerator {
     fn default() -> Self {
@@ -14,36 +32,586 @@ impl Default for ExcelGenerator {
 
 impl ExcelGenerator {
     pub fn new() -> Self {
-        Self
+        Self {
+            passing_score: DEFAULT_PASSING_SCORE,
+            scale: ScoreScale::default(),
+        }
+    }
+
+    /// Scale the totals are reported on, for headers and the passing threshold
+    pub fn with_scale(mut self, scale: ScoreScale) -> Self {
+        self.scale = scale;
+        self
     }
 
-    pub fn generate_report(&self, results: &[GradingResult], output_path: &str) -> Result<()> {
+    /// Use a custom passing threshold (percentage of the total score)
+    pub fn with_passing_score(mut self, passing_score: f32) -> Self {
+        self.passing_score = passing_score;
+        self
+    }
+
+    /// Write the XLSX workbook to `output_path` and a CSV export next to it
+    pub fn generate_report(
+        &self,
+        results: &[GradingResult],
+        rubric: &[Criterion],
+        output_path: &str,
+    ) -> Result<()> {
         info!("Creating report with {} results", results.len());
 
-        // Create CSV file
-        let csv_path = output_path.replace(".xlsx", ".csv");
+        let mut book = umya_spreadsheet::new_file_empty_worksheet();
+        self.write_summary_sheet(add_sheet(&mut book, SUMMARY_SHEET)?, results);
+        self.write_criteria_sheet(add_sheet(&mut book, CRITERIA_SHEET)?, results, rubric);
+        self.write_statistics_sheet(add_sheet(&mut book, STATISTICS_SHEET)?, results, rubric);
+
+        umya_spreadsheet::writer::xlsx::write(&book, output_path)
+            .map_err(|e| anyhow::anyhow!("Failed to write XLSX report {}: {}", output_path, e))?;
+        info!("XLSX report saved to: {}", output_path);
+
+        self.write_csv(results, rubric, output_path)
+    }
+
+    fn total_header(&self) -> String {
+        format!("Total ({})", self.scale.label())
+    }
+
+    /// The passing score, a percentage, on the scale of the totals
+    fn passing_total(&self) -> f32 {
+        self.scale.from_percent(self.passing_score)
+    }
+
+    fn write_summary_sheet(&self, sheet: &mut Worksheet, results: &[GradingResult]) {
+        let total_header = self.total_header();
+        write_header(
+            sheet,
+            &[
+                "Student",
+                "ID",
+                "Section",
+                "Model",
+                total_header.as_str(),
+                "Tests",
+                "Status",
+                "Comment",
+                "Notes",
+            ],
+        );
+
+        for (i, result) in results.iter().enumerate() {
+            let row = i as u32 + 2;
+            sheet
+                .get_cell_mut((1, row))
+                .set_value(result.filename.as_str());
+            sheet
+                .get_cell_mut((2, row))
+                .set_value(result.student_id.as_deref().unwrap_or(""));
+            sheet
+                .get_cell_mut((3, row))
+                .set_value(result.section.as_deref().unwrap_or(""));
+            sheet
+                .get_cell_mut((4, row))
+                .set_value(result.model.as_deref().unwrap_or(""));
+            if result.status.is_scored() {
+                set_score(sheet, 5, row, result.total);
+            }
+            if let Some(tests) = &result.tests {
+                sheet.get_cell_mut((6, row)).set_value(tests.to_string());
+            }
+            sheet
+                .get_cell_mut((7, row))
+                .set_value(result.status.as_str());
+
+            sheet
+                .get_cell_mut((8, row))
+                .set_value(result.comment.as_str());
+            sheet
+                .get_style_mut((8, row))
+                .get_alignment_mut()
+                .set_wrap_text(true);
+
+            if !result.notes.is_empty() {
+                sheet
+                    .get_cell_mut((9, row))
+                    .set_value(result.notes.join("\n"));
+                sheet
+                    .get_style_mut((9, row))
+                    .get_alignment_mut()
+                    .set_wrap_text(true);
+            }
+        }
+
+        if !results.is_empty() {
+            highlight_below(sheet, 5, results.len() as u32 + 1, self.passing_total());
+        }
+
+        sheet.get_column_dimension_mut("A").set_width(24.0);
+        sheet.get_column_dimension_mut("B").set_width(12.0);
+        sheet.get_column_dimension_mut("C").set_width(10.0);
+        sheet.get_column_dimension_mut("D").set_width(18.0);
+        sheet.get_column_dimension_mut("E").set_width(12.0);
+        sheet.get_column_dimension_mut("F").set_width(10.0);
+        sheet.get_column_dimension_mut("G").set_width(14.0);
+        sheet.get_column_dimension_mut("H").set_width(80.0);
+        sheet.get_column_dimension_mut("I").set_width(50.0);
+    }
+
+    fn write_criteria_sheet(
+        &self,
+        sheet: &mut Worksheet,
+        results: &[GradingResult],
+        rubric: &[Criterion],
+    ) {
+        let mut headers = vec!["Student".to_string()];
+        headers.extend(
+            rubric
+                .iter()
+                .map(|criterion| format!("{} (/{})", criterion.id, criterion.max_points)),
+        );
+        headers.push(self.total_header());
+        write_header(sheet, &headers);
+        sheet.get_column_dimension_mut("A").set_width(24.0);
+
+        for (i, result) in results.iter().enumerate() {
+            let row = i as u32 + 2;
+            sheet
+                .get_cell_mut((1, row))
+                .set_value(result.filename.as_str());
+            for (j, criterion) in rubric.iter().enumerate() {
+                if let Some(score) = result.scores.get(&criterion.id) {
+                    set_score(sheet, j as u32 + 2, row, *score);
+                }
+            }
+            if result.status.is_scored() {
+                set_score(sheet, rubric.len() as u32 + 2, row, result.total);
+            }
+        }
+
+        if !results.is_empty() {
+            let last_row = results.len() as u32 + 1;
+            for (j, criterion) in rubric.iter().enumerate() {
+                let threshold = criterion.max_points * self.passing_score / 100.0;
+                highlight_below(sheet, j as u32 + 2, last_row, threshold);
+            }
+            highlight_below(
+                sheet,
+                rubric.len() as u32 + 2,
+                last_row,
+                self.passing_total(),
+            );
+        }
+    }
+
+    fn write_statistics_sheet(
+        &self,
+        sheet: &mut Worksheet,
+        results: &[GradingResult],
+        rubric: &[Criterion],
+    ) {
+        // Only real grades count: failed, skipped and missing rows carry no scores and would
+        // drag every statistic towards zero, and mock grades are not grades at all. Both are
+        // reported as counts of their own below
+        let graded: Vec<&GradingResult> = results
+            .iter()
+            .filter(|r| matches!(r.status, GradingStatus::Graded | GradingStatus::NeedsReview))
+            .collect();
+        let count = |status: GradingStatus| results.iter().filter(|r| r.status == status).count();
+        let totals: Vec<f32> = graded.iter().map(|r| r.total).collect();
+        let stats = Statistics::from_values(&totals);
+        let passing = totals
+            .iter()
+            .filter(|t| **t >= self.passing_total())
+            .count();
+        let label = self.scale.label();
+
+        write_header(sheet, &["Metric", "Value"]);
+        let mut metrics = vec![
+            (
+                "Graded submissions".to_string(),
+                Metric::Count(graded.len()),
+            ),
+            (
+                "Needs review".to_string(),
+                Metric::Count(count(GradingStatus::NeedsReview)),
+            ),
+            (
+                "Mock grades".to_string(),
+                Metric::Count(count(GradingStatus::Mock)),
+            ),
+            (
+                "Failed to grade".to_string(),
+                Metric::Count(count(GradingStatus::Failed)),
+            ),
+            (
+                "Skipped".to_string(),
+                Metric::Count(count(GradingStatus::Skipped)),
+            ),
+            (
+                "Missing submissions".to_string(),
+                Metric::Count(count(GradingStatus::Missing)),
+            ),
+            (format!("Mean ({})", label), Metric::Score(stats.mean)),
+            (format!("Median ({})", label), Metric::Score(stats.median)),
+            (
+                format!("Standard deviation ({})", label),
+                Metric::Score(stats.std_dev),
+            ),
+            (format!("Min ({})", label), Metric::Score(stats.min)),
+            (format!("Max ({})", label), Metric::Score(stats.max)),
+            (
+                "Passing score (%)".to_string(),
+                Metric::Score(self.passing_score),
+            ),
+            ("Passing".to_string(), Metric::Count(passing)),
+        ];
+        for criterion in rubric {
+            let values: Vec<f32> = graded
+                .iter()
+                .filter_map(|r| r.scores.get(&criterion.id).copied())
+                .collect();
+            metrics.push((
+                format!("Mean {} (/{})", criterion.id, criterion.max_points),
+                Metric::Score(Statistics::from_values(&values).mean),
+            ));
+        }
+
+        let mut row = 2;
+        for (label, metric) in &metrics {
+            sheet.get_cell_mut((1, row)).set_value(label.as_str());
+            match metric {
+                Metric::Count(count) => {
+                    sheet.get_cell_mut((2, row)).set_value_number(*count as f64);
+                }
+                Metric::Score(score) => set_score(sheet, 2, row, *score),
+            }
+            row += 1;
+        }
+
+        // Histogram of totals in tenths of the scale, the last bucket includes the maximum
+        let histogram_row = row + 1;
+        sheet
+            .get_cell_mut((1, histogram_row))
+            .set_value(format!("Total range ({})", label));
+        sheet.get_cell_mut((2, histogram_row)).set_value("Count");
+        sheet
+            .get_style_mut((1, histogram_row))
+            .get_font_mut()
+            .set_bold(true);
+        sheet
+            .get_style_mut((2, histogram_row))
+            .get_font_mut()
+            .set_bold(true);
+
+        let width = self.scale.total / HISTOGRAM_BUCKETS as f32;
+        for (i, count) in histogram(&totals, self.scale.total).iter().enumerate() {
+            let bucket_row = histogram_row + 1 + i as u32;
+            let upper = if i == HISTOGRAM_BUCKETS - 1 { "]" } else { ")" };
+            sheet.get_cell_mut((1, bucket_row)).set_value(format!(
+                "[{}, {}{}",
+                i as f32 * width,
+                (i + 1) as f32 * width,
+                upper
+            ));
+            sheet
+                .get_cell_mut((2, bucket_row))
+                .set_value_number(*count as f64);
+        }
+
+        sheet.get_column_dimension_mut("A").set_width(28.0);
+        sheet.get_column_dimension_mut("B").set_width(12.0);
+    }
+
+    fn write_csv(
+        &self,
+        results: &[GradingResult],
+        rubric: &[Criterion],
+        output_path: &str,
+    ) -> Result<()> {
+        let csv_path = Path::new(output_path).with_extension("csv");
         let mut file = File::create(&csv_path)?;
 
-        // Write headers
-        writeln!(file, "Filename,Correctness,Style,EdgeCases,Total,Comment")?;
+        // Write headers, one column per rubric criterion
+        let criterion_headers = rubric
+            .iter()
+            .map(|criterion| {
+                format!(
+                    ",{}",
+                    csv_field(&format!("{} (/{})", criterion.id, criterion.max_points))
+                )
+            })
+            .collect::<String>();
+        writeln!(
+            file,
+            "Filename,Student ID,Section,Model{},{},Tests,Status,Comment,Notes",
+            criterion_headers,
+            csv_field(&self.total_header())
+        )?;
 
         // Write data rows
         for result in results {
-            // Escape quotes in comment field
-            let escaped_comment = result.comment.replace("\"", "\"\"");
+            let criterion_scores = rubric
+                .iter()
+                .map(|criterion| match result.scores.get(&criterion.id) {
+                    Some(score) => format!(",{:.2}", score),
+                    None => ",".to_string(),
+                })
+                .collect::<String>();
+
+            let tests = result
+                .tests
+                .map(|tests| tests.to_string())
+                .unwrap_or_default();
+            let total = if result.status.is_scored() {
+                format!("{:.2}", result.total)
+            } else {
+                String::new()
+            };
             writeln!(
                 file,
-                "\"{}\",{:.2},{:.2},{:.2},{:.2},\"{}\"",
-                result.filename,
-                result.correctness,
-                result.style,
-                result.edge_cases,
-                result.total,
-                escaped_comment
+                "{},{},{},{}{},{},{},{},{},{}",
+                csv_field(&result.filename),
+                csv_field(result.student_id.as_deref().unwrap_or("")),
+                csv_field(result.section.as_deref().unwrap_or("")),
+                csv_field(result.model.as_deref().unwrap_or("")),
+                criterion_scores,
+                total,
+                tests,
+                csv_field(result.status.as_str()),
+                csv_field(&result.comment),
+                csv_field(&result.notes.join("; "))
             )?;
         }
 
-        info!("CSV report saved to: {}", csv_path);
+        info!("CSV report saved to: {}", csv_path.display());
         Ok(())
     }
 }
+
+/// Descriptive statistics over a set of scores
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Statistics {
+    pub mean: f32,
+    pub median: f32,
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Statistics {
+    /// Compute statistics, returning zeros for an empty slice
+    pub fn from_values(values: &[f32]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let count = sorted.len() as f32;
+        let mean = sorted.iter().sum::<f32>() / count;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
+        } else {
+            sorted[sorted.len() / 2]
+        };
+        // Population standard deviation: the class is the whole population
+        let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+
+        Self {
+            mean,
+            median,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// Number of totals in each tenth of the scale `0..=scale_total`; totals outside the scale
+/// are counted in the first or last bucket
+fn histogram(totals: &[f32], scale_total: f32) -> [usize; HISTOGRAM_BUCKETS] {
+    let width = scale_total / HISTOGRAM_BUCKETS as f32;
+    let mut buckets = [0usize; HISTOGRAM_BUCKETS];
+    for total in totals {
+        let bucket = ((total / width).floor().max(0.0) as usize).min(HISTOGRAM_BUCKETS - 1);
+        buckets[bucket] += 1;
+    }
+    buckets
+}
+
+/// A value on the statistics sheet
+enum Metric {
+    Count(usize),
+    Score(f32),
+}
+
+/// Quote a CSV field, doubling the quotes inside it
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn add_sheet<'a>(
+    book: &'a mut umya_spreadsheet::Spreadsheet,
+    name: &str,
+) -> Result<&'a mut Worksheet> {
+    book.new_sheet(name)
+        .map_err(|e| anyhow::anyhow!("Failed to create sheet {}: {}", name, e))
+}
+
+fn write_header<S: AsRef<str>>(sheet: &mut Worksheet, headers: &[S]) {
+    for (i, header) in headers.iter().enumerate() {
+        let col = i as u32 + 1;
+        sheet.get_cell_mut((col, 1)).set_value(header.as_ref());
+        sheet.get_style_mut((col, 1)).get_font_mut().set_bold(true);
+    }
+}
+
+fn set_score(sheet: &mut Worksheet, col: u32, row: u32, value: f32) {
+    sheet
+        .get_cell_mut((col, row))
+        .set_value_number(value as f64);
+    sheet
+        .get_style_mut((col, row))
+        .get_number_format_mut()
+        .set_format_code(SCORE_FORMAT);
+}
+
+/// Highlight cells in rows `2..=last_row` of `col` whose value is below `threshold`. Blank
+/// cells, the totals of ungraded submissions, count as zero in a plain "less than" rule, so
+/// the rule only applies to numbers
+fn highlight_below(sheet: &mut Worksheet, col: u32, last_row: u32, threshold: f32) {
+    let mut style = Style::default();
+    style.set_background_color("FFFFC7CE");
+    style.get_font_mut().get_color_mut().set_argb("FF9C0006");
+
+    // Relative to the first cell of the range, so it applies to each cell in turn
+    let first = coordinate_from_index(&col, &2);
+    let mut formula = Formula::default();
+    formula.set_string_value(format!("AND(ISNUMBER({0}),{0}<{1})", first, threshold));
+
+    let mut rule = ConditionalFormattingRule::default();
+    rule.set_type(ConditionalFormatValues::Expression)
+        .set_priority(1)
+        .set_style(style)
+        .set_formula(formula);
+
+    let mut formatting = ConditionalFormatting::default();
+    formatting
+        .get_sequence_of_references_mut()
+        .set_sqref(format!(
+            "{}:{}",
+            first,
+            coordinate_from_index(&col, &last_row)
+        ));
+    formatting.add_conditional_collection(rule);
+    sheet.add_conditional_formatting_collection(formatting);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "\"plain\"");
+        assert_eq!(csv_field("a \"b\", c"), "\"a \"\"b\"\", c\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn computes_statistics() {
+        let stats = Statistics::from_values(&[4.0, 2.0, 8.0, 6.0]);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.median, 5.0);
+        assert_eq!(stats.min, 2.0);
+        assert_eq!(stats.max, 8.0);
+        assert!((stats.std_dev - 5.0f32.sqrt()).abs() < 1e-6);
+
+        let stats = Statistics::from_values(&[70.0, 10.0, 40.0]);
+        assert_eq!(stats.median, 40.0);
+        assert_eq!(stats.mean, 40.0);
+
+        let stats = Statistics::from_values(&[]);
+        assert_eq!((stats.mean, stats.median, stats.std_dev), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn buckets_totals_by_tenths_of_the_scale() {
+        let buckets = histogram(&[0.0, 9.99, 10.0, 55.0, 99.9, 100.0], 100.0);
+        assert_eq!(buckets, [2, 1, 0, 0, 0, 1, 0, 0, 0, 2]);
+
+        // Points scales and clamped outliers
+        let buckets = histogram(&[-1.0, 2.0, 19.0, 25.0], 20.0);
+        assert_eq!(buckets, [1, 1, 0, 0, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn leaves_mock_grades_out_of_the_statistics() {
+        let result = |filename: &str, status: &str, total: f32| -> GradingResult {
+            serde_json::from_value(serde_json::json!({
+                "filename": filename,
+                "status": status,
+                "scores": {"functionality": total / 10.0},
+                "total": total,
+                "comment": "",
+            }))
+            .unwrap()
+        };
+        let results = [
+            result("alice/", "graded", 80.0),
+            result("bob/", "needs_review", 60.0),
+            result("carol/", "mock", 0.0),
+            result("dave/", "mock", 0.0),
+        ];
+        let rubric = [Criterion {
+            id: "functionality".to_string(),
+            description: "Works".to_string(),
+            max_points: 10.0,
+            weight: 1.0,
+        }];
+        let mut book = umya_spreadsheet::new_file();
+        let sheet = book.get_sheet_mut(&0).unwrap();
+        ExcelGenerator::new().write_statistics_sheet(sheet, &results, &rubric);
+
+        let metric = |name: &str| -> String {
+            let row = (2..40)
+                .find(|row| sheet.get_value((1, *row)) == name)
+                .unwrap_or_else(|| panic!("no metric {}", name));
+            sheet.get_value((2, row))
+        };
+        assert_eq!(metric("Graded submissions"), "2");
+        assert_eq!(metric("Needs review"), "1");
+        assert_eq!(metric("Mock grades"), "2");
+        assert_eq!(metric("Mean (%)"), "70");
+        assert_eq!(metric("Min (%)"), "60");
+        assert_eq!(metric("Mean functionality (/10)"), "7");
+    }
+
+    #[test]
+    fn writes_the_csv_next_to_the_workbook() {
+        let dir = tempfile::tempdir().unwrap();
+        let rubric = [Criterion {
+            id: "functionality".to_string(),
+            description: "Works".to_string(),
+            max_points: 10.0,
+            weight: 1.0,
+        }];
+        let generator = ExcelGenerator::new();
+
+        for (output, csv) in [
+            ("report.xlsx", "report.csv"),
+            ("Report.XLSX", "Report.csv"),
+            ("grades.xlsx.d/report.xlsx", "grades.xlsx.d/report.csv"),
+        ] {
+            let output = dir.path().join(output);
+            std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+            generator
+                .generate_report(&[], &rubric, output.to_str().unwrap())
+                .unwrap();
+
+            let workbook = std::fs::read(&output).unwrap();
+            assert!(workbook.starts_with(b"PK"), "{}", output.display());
+            let csv = std::fs::read_to_string(dir.path().join(csv)).unwrap();
+            assert!(csv.starts_with("Filename,"), "{}", output.display());
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Name of the binary produced by compiled languages inside the workspace
+const BINARY_NAME: &str = "submission_bin";
+
+/// Languages the executor knows how to build and run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Python,
+    Rust,
+    Java,
+    C,
+    Cpp,
+    JavaScript,
+}
+
+/// Commands needed to build and run a submission
+#[derive(Debug, Clone)]
+pub struct ExecutionPlan {
+    pub build: Option<Vec<String>>,
+    pub run: Vec<String>,
+    /// Whether an address-space limit can be applied to the build and the run (managed
+    /// runtimes reserve large virtual ranges and cap their heap through flags instead)
+    pub limit_address_space: bool,
+}
+
+impl Language {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "py" => Some(Language::Python),
+            "rs" => Some(Language::Rust),
+            "java" => Some(Language::Java),
+            "c" => Some(Language::C),
+            "cpp" => Some(Language::Cpp),
+            "js" => Some(Language::JavaScript),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Language::Python => "py",
+            Language::Rust => "rs",
+            Language::Java => "java",
+            Language::C => "c",
+            Language::Cpp => "cpp",
+            Language::JavaScript => "js",
+        }
+    }
+
+    /// Pick the language of the entry point, or the one with the most source files
+    pub fn detect(paths: &[&str], entry_point: Option<&str>) -> Option<Self> {
+        if let Some(language) =
+            entry_point.and_then(|entry| Self::from_extension(&extension(entry)))
+        {
+            return Some(language);
+        }
+
+        let mut counts: Vec<(Language, usize)> = Vec::new();
+        for language in paths
+            .iter()
+            .filter_map(|path| Self::from_extension(&extension(path)))
+        {
+            match counts.iter_mut().find(|(l, _)| *l == language) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((language, 1)),
+            }
+        }
+        // Stable max keeps the first-seen language on ties
+        counts
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .map(|(language, _)| language)
+    }
+
+    /// Build the commands for a workspace containing `files` (path, content), with the
+    /// memory budgets of the run and the build
+    pub fn plan(
+        &self,
+        files: &[(String, String)],
+        entry_point: Option<&str>,
+        memory_mb: u64,
+        build_memory_mb: u64,
+    ) -> Result<ExecutionPlan> {
+        let sources: Vec<&str> = files
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .filter(|path| extension(path) == self.extension())
+            .collect();
+        if sources.is_empty() {
+            anyhow::bail!("No {} source files found", self);
+        }
+
+        let plan = match self {
+            Language::Python => ExecutionPlan {
+                build: None,
+                run: vec![
+                    "python3".to_string(),
+                    find_entry(&sources, entry_point, &["main.py", "__main__.py"])?,
+                ],
+                limit_address_space: true,
+            },
+            Language::JavaScript => ExecutionPlan {
+                build: None,
+                run: vec![
+                    "node".to_string(),
+                    format!("--max-old-space-size={}", memory_mb),
+                    find_entry(&sources, entry_point, &["main.js", "index.js"])?,
+                ],
+                limit_address_space: false,
+            },
+            Language::Rust => ExecutionPlan {
+                build: Some(vec![
+                    "rustc".to_string(),
+                    "--edition".to_string(),
+                    "2021".to_string(),
+                    "-O".to_string(),
+                    "-o".to_string(),
+                    BINARY_NAME.to_string(),
+                    find_entry(&sources, entry_point, &["main.rs"])?,
+                ]),
+                run: vec![format!("./{}", BINARY_NAME)],
+                limit_address_space: true,
+            },
+            Language::C | Language::Cpp => {
+                let compiler = if *self == Language::C { "cc" } else { "c++" };
+                let mut build = vec![
+                    compiler.to_string(),
+                    "-O2".to_string(),
+                    "-o".to_string(),
+                    BINARY_NAME.to_string(),
+                ];
+                build.extend(sources.iter().map(|s| s.to_string()));
+                build.push("-lm".to_string());
+                ExecutionPlan {
+                    build: Some(build),
+                    run: vec![format!("./{}", BINARY_NAME)],
+                    limit_address_space: true,
+                }
+            }
+            Language::Java => {
+                let mut build = vec![
+                    "javac".to_string(),
+                    format!("-J-Xmx{}m", build_memory_mb),
+                    "-d".to_string(),
+                    "classes".to_string(),
+                ];
+                build.extend(sources.iter().map(|s| s.to_string()));
+                ExecutionPlan {
+                    build: Some(build),
+                    run: vec![
+                        "java".to_string(),
+                        format!("-Xmx{}m", memory_mb),
+                        "-cp".to_string(),
+                        "classes".to_string(),
+                        java_main_class(files, entry_point)?,
+                    ],
+                    limit_address_space: false,
+                }
+            }
+        };
+
+        Ok(plan)
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Language::Python => "python",
+            Language::Rust => "rust",
+            Language::Java => "java",
+            Language::C => "c",
+            Language::Cpp => "cpp",
+            Language::JavaScript => "javascript",
+        };
+        f.write_str(name)
+    }
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Use the configured entry point, then a conventional file name (shallowest first),
+/// then the only source file
+fn find_entry(
+    sources: &[&str],
+    entry_point: Option<&str>,
+    conventional: &[&str],
+) -> Result<String> {
+    if let Some(entry) = entry_point {
+        return sources
+            .iter()
+            .find(|source| **source == entry)
+            .map(|source| source.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Entry point {} not found in submission", entry));
+    }
+
+    let mut candidates: Vec<&str> = sources
+        .iter()
+        .copied()
+        .filter(|source| {
+            let name = Path::new(source)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("");
+            conventional.contains(&name)
+        })
+        .collect();
+    candidates.sort_by_key(|source| source.matches('/').count());
+    if let Some(entry) = candidates.first() {
+        return Ok(entry.to_string());
+    }
+
+    match sources {
+        [only] => Ok(only.to_string()),
+        _ => anyhow::bail!(
+            "Cannot determine the entry point among {} source files, set execution.entry_point",
+            sources.len()
+        ),
+    }
+}
+
+/// Fully qualified name of the class declaring `main`
+fn java_main_class(files: &[(String, String)], entry_point: Option<&str>) -> Result<String> {
+    let (path, content) = files
+        .iter()
+        .filter(|(path, _)| extension(path) == "java")
+        .find(|(path, content)| match entry_point {
+            Some(entry) => path == entry,
+            None => content.contains("static void main"),
+        })
+        .ok_or_else(|| anyhow::anyhow!("No Java class with a main method found"))?;
+
+    let class_name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let package = content
+        .lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("package "))
+        .map(|package| package.trim_end_matches(';').trim());
+
+    Ok(match package {
+        Some(package) => format!("{}.{}", package, class_name),
+        None => class_name.to_string(),
+    })
+}
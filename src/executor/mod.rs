@@ -0,0 +1,349 @@
+mod language;
+mod sandbox;
+
+pub use language::Language;
+
+use crate::models::{ExecutionConfig, StudentSubmission, TestCase, TestSummary};
+use anyhow::{Context, Result};
+use sandbox::{SandboxLimits, SandboxOutput};
+use std::path::{Component, Path};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_BUILD_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MEMORY_MB: u64 = 256;
+const DEFAULT_BUILD_MEMORY_MB: u64 = 1024;
+/// Bytes of program output kept per stream
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+/// Characters of output quoted in the evidence shown to the model
+const MAX_DETAIL_CHARS: usize = 400;
+
+/// Formats whose extracted text is not the original file, so they cannot be part of a runnable program
+const DOCUMENT_TYPES: &[&str] = &["pdf", "docx", "doc", "rtf", "ipynb"];
+
+/// Builds submissions and runs them against the instructor test cases
+pub struct Executor {
+    config: ExecutionConfig,
+}
+
+/// Outcome of a single test case
+#[derive(Debug, Clone)]
+pub struct CaseOutcome {
+    pub name: String,
+    pub passed: bool,
+    pub timed_out: bool,
+    pub detail: String,
+}
+
+/// Why the test cases of a submission did not run
+#[derive(Debug, Clone)]
+pub enum ExecutionError {
+    /// The submission does not build or its build timed out, so every case failed
+    Build(String),
+    /// The grading host could not run the submission: no language or entry point to run, a
+    /// missing interpreter or compiler, a sandbox that cannot start. Says nothing about the
+    /// student's work
+    Setup(String),
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::Build(reason) | ExecutionError::Setup(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Everything learned from building and testing one submission
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionReport {
+    pub language: Option<Language>,
+    /// Why the test cases did not run
+    pub error: Option<ExecutionError>,
+    pub cases: Vec<CaseOutcome>,
+    /// Number of configured test cases
+    pub total: usize,
+}
+
+impl ExecutionReport {
+    pub fn summary(&self) -> TestSummary {
+        TestSummary {
+            passed: self.cases.iter().filter(|case| case.passed).count(),
+            total: self.total,
+        }
+    }
+
+    /// Whether the tests could not be run for reasons outside the submission, so their
+    /// outcome must not count against it
+    pub fn setup_failed(&self) -> bool {
+        matches!(self.error, Some(ExecutionError::Setup(_)))
+    }
+
+    /// Plain-text evidence included in the grading prompt
+    pub fn render(&self) -> String {
+        if let Some(ExecutionError::Setup(reason)) = &self.error {
+            return format!(
+                "The test cases could not be run on the grading machine, this is not a fault of the submission: {}\n",
+                reason
+            );
+        }
+        let summary = self.summary();
+        let mut text = String::new();
+        if let Some(language) = self.language {
+            text.push_str(&format!("Language: {}\n", language));
+        }
+        text.push_str(&format!(
+            "Passed {} of {} test cases\n",
+            summary.passed, summary.total
+        ));
+        if let Some(error) = &self.error {
+            text.push_str(&format!("Could not run the submission: {}\n", error));
+        }
+        for case in &self.cases {
+            let status = if case.passed {
+                "PASS"
+            } else if case.timed_out {
+                "TIMEOUT"
+            } else {
+                "FAIL"
+            };
+            text.push_str(&format!("- [{}] {}", status, case.name));
+            if !case.detail.is_empty() {
+                text.push_str(&format!(": {}", case.detail));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Executor {
+    pub fn new(config: ExecutionConfig) -> Self {
+        Self { config }
+    }
+
+    /// Build the submission in a temporary workspace and run every test case
+    pub async fn run(&self, submission: &StudentSubmission) -> ExecutionReport {
+        let mut report = ExecutionReport {
+            total: self.config.test_cases.len(),
+            ..Default::default()
+        };
+
+        if let Err(e) = self.run_cases(submission, &mut report).await {
+            warn!(
+                "Could not execute submission {}: {:#}",
+                submission.student_name, e
+            );
+            report.error = Some(ExecutionError::Setup(format!("{:#}", e)));
+        }
+
+        let summary = report.summary();
+        info!(
+            "Submission {} passed {}/{} test cases",
+            submission.student_name, summary.passed, summary.total
+        );
+        report
+    }
+
+    /// Build and run the cases; errors are problems of the grading host, a submission that
+    /// does not build is recorded in the report
+    async fn run_cases(
+        &self,
+        submission: &StudentSubmission,
+        report: &mut ExecutionReport,
+    ) -> Result<()> {
+        // Running the start of a file would fail for a limit the grader imposed
+        if let Some(file) = submission
+            .files
+            .iter()
+            .find(|file| file.truncated && !DOCUMENT_TYPES.contains(&file.file_type.as_str()))
+        {
+            anyhow::bail!(
+                "{} was cut to the size limit for text files, so the program cannot be run",
+                file.filename
+            );
+        }
+        let files = source_files(submission);
+        let entry_point = self.config.entry_point.as_deref();
+        let language = match self.config.language {
+            Some(language) => language,
+            None => {
+                let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+                Language::detect(&paths, entry_point)
+                    .context("No source files in a supported language")?
+            }
+        };
+        report.language = Some(language);
+
+        let memory_mb = self.config.memory_mb.unwrap_or(DEFAULT_MEMORY_MB);
+        let build_memory_mb = self
+            .config
+            .build_memory_mb
+            .unwrap_or(DEFAULT_BUILD_MEMORY_MB);
+        let plan = language.plan(&files, entry_point, memory_mb, build_memory_mb)?;
+
+        let workspace = tempfile::Builder::new()
+            .prefix("paper-sage-run-")
+            .tempdir()
+            .context("Failed to create execution workspace")?;
+        for (path, content) in &files {
+            write_workspace_file(workspace.path(), path, content)?;
+        }
+
+        let isolate = self.config.require_isolation.unwrap_or(true);
+        if let Some(build) = &plan.build {
+            let limits = SandboxLimits {
+                timeout: Duration::from_secs(
+                    self.config
+                        .build_timeout_secs
+                        .unwrap_or(DEFAULT_BUILD_TIMEOUT_SECS),
+                ),
+                memory_mb: plan.limit_address_space.then_some(build_memory_mb),
+                isolate,
+                max_output_bytes: MAX_OUTPUT_BYTES,
+            };
+            let output = sandbox::run(workspace.path(), build, None, &limits).await?;
+            if !output.success() {
+                let reason = if output.timed_out {
+                    "build timed out".to_string()
+                } else {
+                    format!("build failed: {}", excerpt(&output.stderr))
+                };
+                report.error = Some(ExecutionError::Build(reason));
+                return Ok(());
+            }
+        }
+
+        let limits = SandboxLimits {
+            timeout: Duration::from_secs(self.config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            memory_mb: plan.limit_address_space.then_some(memory_mb),
+            isolate,
+            max_output_bytes: MAX_OUTPUT_BYTES,
+        };
+        for case in &self.config.test_cases {
+            let outcome = self
+                .run_case(workspace.path(), case, &plan.run, &limits)
+                .await
+                .with_context(|| format!("Test case {}", case.name))?;
+            report.cases.push(outcome);
+        }
+
+        Ok(())
+    }
+
+    async fn run_case(
+        &self,
+        workdir: &Path,
+        case: &TestCase,
+        run: &[String],
+        limits: &SandboxLimits,
+    ) -> Result<CaseOutcome> {
+        for file in &case.files {
+            let source = Path::new(file);
+            let name = source
+                .file_name()
+                .with_context(|| format!("Invalid test file path: {}", file))?;
+            std::fs::copy(source, workdir.join(name))
+                .with_context(|| format!("Failed to copy test file: {}", file))?;
+        }
+
+        let argv = match &case.command {
+            Some(command) => command.clone(),
+            None => run.iter().chain(case.args.iter()).cloned().collect(),
+        };
+        let output = sandbox::run(workdir, &argv, case.stdin.as_deref(), limits).await?;
+
+        let (passed, detail) = judge(case, &output);
+        Ok(CaseOutcome {
+            name: case.name.clone(),
+            passed,
+            timed_out: output.timed_out,
+            detail,
+        })
+    }
+}
+
+/// Compare the program output with the expectations of a test case
+fn judge(case: &TestCase, output: &SandboxOutput) -> (bool, String) {
+    if output.timed_out {
+        return (false, "time limit exceeded".to_string());
+    }
+    if output.exit_code != Some(0) {
+        let status = match output.exit_code {
+            Some(code) => format!("exited with code {}", code),
+            None => "killed by a signal".to_string(),
+        };
+        let stderr = excerpt(&output.stderr);
+        return if stderr.is_empty() {
+            (false, status)
+        } else {
+            (false, format!("{}: {}", status, stderr))
+        };
+    }
+
+    match &case.expected_stdout {
+        Some(expected) if normalize(expected) != normalize(&output.stdout) => (
+            false,
+            format!(
+                "expected {:?}, got {:?}",
+                excerpt(expected),
+                excerpt(&output.stdout)
+            ),
+        ),
+        _ => (true, String::new()),
+    }
+}
+
+/// Ignore trailing whitespace on each line and trailing blank lines
+fn normalize(output: &str) -> String {
+    output
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+fn excerpt(text: &str) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(MAX_DETAIL_CHARS) {
+        Some((index, _)) => format!("{}...", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+/// Submission files that belong to the program, relative to the student folder
+fn source_files(submission: &StudentSubmission) -> Vec<(String, String)> {
+    let prefix = submission.get_main_filename();
+    submission
+        .files
+        .iter()
+        .filter(|file| !DOCUMENT_TYPES.contains(&file.file_type.as_str()))
+        .map(|file| {
+            let path = file
+                .filename
+                .strip_prefix(&prefix)
+                .unwrap_or(&file.filename);
+            (path.to_string(), file.content.clone())
+        })
+        .collect()
+}
+
+fn write_workspace_file(root: &Path, path: &str, content: &str) -> Result<()> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!("Refusing to write file outside the workspace: {}", path);
+    }
+
+    let target = root.join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&target, content)
+        .with_context(|| format!("Failed to write {}", target.display()))
+}
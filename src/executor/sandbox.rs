@@ -0,0 +1,627 @@
+use anyhow::{Context, Result};
+#[cfg(target_os = "linux")]
+use std::ffi::{CStr, CString};
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tracing::warn;
+
+/// Largest file a sandboxed process may create
+const MAX_FILE_BYTES: u64 = 16 * 1024 * 1024;
+/// Processes the sandbox user may run at once, which stops fork bombs
+const MAX_PROCESSES: u64 = 256;
+/// Unprivileged user (`nobody`) programs run as when the grader itself runs as root
+const SANDBOX_UID: u32 = 65534;
+/// How long the pipes are still read once the process has exited, for descendants that
+/// left its process group and keep them open
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Resource limits applied to a sandboxed process
+#[derive(Debug, Clone)]
+pub struct SandboxLimits {
+    pub timeout: Duration,
+    /// Address space limit, `None` for runtimes that cap their own heap
+    pub memory_mb: Option<u64>,
+    /// Run the process in fresh namespaces: no network, a read-only file system apart from
+    /// the workspace, private temporary directories and only its own processes in `/proc`
+    pub isolate: bool,
+    /// Bytes of stdout/stderr kept, the rest is drained and discarded
+    pub max_output_bytes: usize,
+}
+
+/// Captured result of a sandboxed process
+#[derive(Debug, Clone)]
+pub struct SandboxOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl SandboxOutput {
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+}
+
+/// Run `argv` in `workdir` with a scrubbed environment and the given limits. A grader
+/// running as root hands the workspace to an unprivileged user and runs the program as it
+pub async fn run(
+    workdir: &Path,
+    argv: &[String],
+    stdin: Option<&str>,
+    limits: &SandboxLimits,
+) -> Result<SandboxOutput> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("Empty command"))?;
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(workdir)
+        .env_clear()
+        .env("PATH", std::env::var("PATH").unwrap_or_default())
+        .env("HOME", workdir)
+        .env("TMPDIR", workdir)
+        .env("LANG", "C.UTF-8")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    prepare_workdir(workdir)?;
+    apply_limits(&mut command, workdir, limits)?;
+
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to start {}", program))?;
+    let pid = child.id();
+
+    let mut child_stdin = child.stdin.take();
+    let stdin_data = stdin.unwrap_or_default().as_bytes().to_vec();
+    let stdin_task = tokio::spawn(async move {
+        if let Some(mut pipe) = child_stdin.take() {
+            // The program may exit without reading its input
+            let _ = pipe.write_all(&stdin_data).await;
+        }
+    });
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stdout_task = tokio::spawn(read_capped(
+        child.stdout.take().expect("stdout is piped"),
+        Arc::clone(&stdout),
+        limits.max_output_bytes,
+    ));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let stderr_task = tokio::spawn(read_capped(
+        child.stderr.take().expect("stderr is piped"),
+        Arc::clone(&stderr),
+        limits.max_output_bytes,
+    ));
+
+    let status = tokio::time::timeout(limits.timeout, child.wait()).await;
+    // Background processes the program started (`cmd &`, forked servers) would otherwise
+    // keep running and hold the pipes open
+    kill_process_group(pid);
+    let (exit_code, timed_out) = match status {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+
+    let mut tasks = [stdin_task, stdout_task, stderr_task];
+    let drain = async {
+        for task in tasks.iter_mut() {
+            let _ = task.await;
+        }
+    };
+    if tokio::time::timeout(DRAIN_TIMEOUT, drain).await.is_err() {
+        warn!("A process outside the sandboxed process group kept its output open");
+        for task in &tasks {
+            task.abort();
+        }
+    }
+
+    Ok(SandboxOutput {
+        exit_code,
+        stdout: captured(&stdout),
+        stderr: captured(&stderr),
+        timed_out,
+    })
+}
+
+fn captured(output: &Mutex<Vec<u8>>) -> String {
+    let output = output.lock().unwrap_or_else(|e| e.into_inner());
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Read `reader` to the end into `kept`, which holds what was read so far if the task is
+/// aborted
+async fn read_capped<R: AsyncRead + Unpin>(mut reader: R, kept: Arc<Mutex<Vec<u8>>>, limit: usize) {
+    let mut chunk = [0u8; 8192];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let mut kept = kept.lock().unwrap_or_else(|e| e.into_inner());
+                let room = limit.saturating_sub(kept.len());
+                kept.extend_from_slice(&chunk[..n.min(room)]);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_limits(command: &mut Command, workdir: &Path, limits: &SandboxLimits) -> Result<()> {
+    // CPU time backs up the wall-clock timeout for busy loops
+    let cpu_secs = limits.timeout.as_secs() + 1;
+    let memory_bytes = limits.memory_mb.map(|mb| mb * 1024 * 1024);
+    let drop_privileges = running_as_root();
+    let mut isolation = limits
+        .isolate
+        .then(|| Isolation::new(workdir, !drop_privileges))
+        .transpose()?;
+
+    // SAFETY: the closure runs between fork and exec and only calls
+    // async-signal-safe libc functions
+    unsafe {
+        command.pre_exec(move || {
+            // Own process group so a timeout can kill every descendant
+            libc::setpgid(0, 0);
+            check(libc::setrlimit(libc::RLIMIT_CORE, &rlimit(0)))?;
+            check(libc::setrlimit(libc::RLIMIT_FSIZE, &rlimit(MAX_FILE_BYTES)))?;
+            check(libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_secs)))?;
+            if let Some(bytes) = memory_bytes {
+                check(libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes)))?;
+            }
+            if let Some(isolation) = &mut isolation {
+                isolation.enter()?;
+            }
+            if drop_privileges {
+                check(libc::setgroups(0, std::ptr::null()))?;
+                check(libc::setgid(SANDBOX_UID))?;
+                check(libc::setuid(SANDBOX_UID))?;
+            }
+            // Processes are counted per user, so the limit only means something for the
+            // sandbox user or inside a user namespace, not for the grader's own user
+            if drop_privileges || isolation.is_some() {
+                check(libc::setrlimit(libc::RLIMIT_NPROC, &rlimit(MAX_PROCESSES)))?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn running_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Give the workspace to the sandbox user when programs will run as it
+#[cfg(target_os = "linux")]
+fn prepare_workdir(workdir: &Path) -> Result<()> {
+    if !running_as_root() {
+        return Ok(());
+    }
+    for entry in walkdir::WalkDir::new(workdir) {
+        let entry = entry?;
+        std::os::unix::fs::lchown(entry.path(), Some(SANDBOX_UID), Some(SANDBOX_UID))
+            .with_context(|| {
+                format!(
+                    "Failed to hand {} to the sandbox user",
+                    entry.path().display()
+                )
+            })?;
+    }
+    Ok(())
+}
+
+/// `struct mount_attr` of mount_setattr(2), which the libc crate does not define
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+#[cfg(target_os = "linux")]
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+/// Namespaces a sandboxed process enters, with everything that needs allocating prepared
+/// before the fork
+#[cfg(target_os = "linux")]
+struct Isolation {
+    /// `uid_map` and `gid_map` contents when an unprivileged grader needs a user namespace
+    id_maps: Option<(CString, CString)>,
+    workdir: CString,
+    /// Scratch copy of `workdir` for creating its parent directories
+    scratch: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl Isolation {
+    fn new(workdir: &Path, user_namespace: bool) -> Result<Self> {
+        let workdir = CString::new(workdir.as_os_str().as_bytes())
+            .context("Workspace path contains a NUL byte")?;
+        let id_maps = user_namespace.then(|| {
+            // SAFETY: getuid and getgid have no preconditions
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            let map = |id| CString::new(format!("{} {} 1", id, id)).expect("no NUL in a number");
+            (map(uid), map(gid))
+        });
+        Ok(Self {
+            id_maps,
+            scratch: workdir.as_bytes_with_nul().to_vec(),
+            workdir,
+        })
+    }
+
+    /// Move into the namespaces and set up the file system view. Runs in the forked child
+    unsafe fn enter(&mut self) -> std::io::Result<()> {
+        let mut flags = libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWNET;
+        if self.id_maps.is_some() {
+            // Unprivileged users need a user namespace to own the others
+            flags |= libc::CLONE_NEWUSER;
+        }
+        check(libc::unshare(flags))?;
+        if let Some((uid_map, gid_map)) = &self.id_maps {
+            write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+        }
+
+        // Only children join the new PID namespace, so the program runs in a child that is its
+        // init: when it exits, the kernel kills everything it left running
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error()),
+            0 => {}
+            init => exit_like(init),
+        }
+        check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+        self.mount_view()
+    }
+
+    unsafe fn mount_view(&mut self) -> std::io::Result<()> {
+        let none = std::ptr::null();
+        // Keep every change below out of the grader's mount namespace
+        check(libc::mount(
+            none,
+            c"/".as_ptr(),
+            none,
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ))?;
+        set_read_only(c"/", true)?;
+
+        // Private temporary directories hide other workspaces; the working directory still
+        // refers to this workspace, which is mounted back at its path below
+        for dir in [c"/tmp", c"/var/tmp", c"/dev/shm"] {
+            let ret = libc::mount(
+                c"tmpfs".as_ptr(),
+                dir.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=1777,size=64m".as_ptr().cast(),
+            );
+            if ret != 0 && std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT) {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        self.create_mount_point();
+        check(libc::mount(
+            c".".as_ptr(),
+            self.workdir.as_ptr(),
+            none,
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ))?;
+        set_read_only(&self.workdir, false)?;
+        check(libc::chdir(self.workdir.as_ptr()))?;
+
+        // Show only the sandbox's own processes, so no other process's environment (API keys
+        // included) is readable
+        if libc::mount(
+            c"proc".as_ptr(),
+            c"/proc".as_ptr(),
+            c"proc".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        ) != 0
+        {
+            // Refused where /proc is partly masked, as in containers: hide it altogether
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                c"/proc".as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Create the workspace path and its parents, which do not exist in the private /tmp.
+    /// Failures surface when mounting onto the path
+    unsafe fn create_mount_point(&mut self) {
+        let end = self.scratch.len() - 1;
+        for i in 1..end {
+            if self.scratch[i] == b'/' {
+                self.scratch[i] = 0;
+                libc::mkdir(self.scratch.as_ptr().cast(), 0o755);
+                self.scratch[i] = b'/';
+            }
+        }
+        libc::mkdir(self.scratch.as_ptr().cast(), 0o755);
+    }
+}
+
+/// Make `path` and, for the root, every mount below it read-only or writable again
+#[cfg(target_os = "linux")]
+unsafe fn set_read_only(path: &CStr, read_only: bool) -> std::io::Result<()> {
+    let attr = MountAttr {
+        attr_set: if read_only { MOUNT_ATTR_RDONLY } else { 0 },
+        attr_clr: if read_only { 0 } else { MOUNT_ATTR_RDONLY },
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let ret = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        libc::AT_RECURSIVE,
+        &attr as *const MountAttr,
+        std::mem::size_of::<MountAttr>(),
+    );
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn write_file(path: &CStr, content: &[u8]) -> std::io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let written = libc::write(fd, content.as_ptr().cast(), content.len());
+    libc::close(fd);
+    if written != content.len() as isize {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Wait for the namespace's init and exit the same way, so the grader sees its status
+#[cfg(target_os = "linux")]
+unsafe fn exit_like(init: libc::pid_t) -> ! {
+    // This process never execs, so descriptors marked close-on-exec stay open in it: the
+    // program's pipes and the one the grader waits on until the program has started
+    libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0);
+    let mut status = 0;
+    while libc::waitpid(init, &mut status, 0) == -1
+        && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+    {}
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[cfg(target_os = "linux")]
+fn rlimit(value: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn kill_process_group(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        // SAFETY: signalling a process group we created has no memory safety implications
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_limits(_command: &mut Command, _workdir: &Path, limits: &SandboxLimits) -> Result<()> {
+    if limits.isolate {
+        anyhow::bail!("Sandboxed execution with isolation is only supported on Linux");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn prepare_workdir(_workdir: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kill_process_group(_pid: Option<u32>) {}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn limits(timeout: Duration, isolate: bool) -> SandboxLimits {
+        SandboxLimits {
+            timeout,
+            memory_mb: None,
+            isolate,
+            max_output_bytes: 1024,
+        }
+    }
+
+    async fn run_sh(script: &str, limits: &SandboxLimits) -> Result<SandboxOutput> {
+        let workdir = tempfile::tempdir().unwrap();
+        let argv = ["sh", "-c", script].map(String::from);
+        run(workdir.path(), &argv, None, limits).await
+    }
+
+    async fn sh(script: &str, timeout: Duration) -> SandboxOutput {
+        run_sh(script, &limits(timeout, false)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn captures_output_and_exit_code() {
+        let output = sh("echo out; echo err >&2; exit 3", Duration::from_secs(5)).await;
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(!output.timed_out);
+    }
+
+    #[tokio::test]
+    async fn caps_output() {
+        let output = sh(
+            "head -c 5000 /dev/zero | tr '\\0' x",
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(output.stdout.len(), 1024);
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    async fn background_processes_do_not_block_the_run() {
+        let started = Instant::now();
+        let output = sh("sleep 1000 & echo done", Duration::from_secs(30)).await;
+        assert!(output.success());
+        assert_eq!(output.stdout, "done\n");
+        assert!(started.elapsed() < DRAIN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn processes_outside_the_group_are_cut_off() {
+        let started = Instant::now();
+        let output = sh("setsid sleep 5 & echo done", Duration::from_secs(30)).await;
+        assert!(output.success());
+        assert_eq!(output.stdout, "done\n");
+        assert!(started.elapsed() < DRAIN_TIMEOUT + Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_the_process_group() {
+        let started = Instant::now();
+        let output = sh("sleep 1000 & sleep 1000", Duration::from_millis(200)).await;
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert!(!output.success());
+        assert!(started.elapsed() < DRAIN_TIMEOUT);
+    }
+
+    /// Whether the kernel lets this process create the namespaces isolation needs; checked
+    /// once so that the isolation tests are skipped only where namespaces are unavailable and
+    /// fail on any other error
+    fn namespaces_available() -> bool {
+        static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+        *AVAILABLE.get_or_init(|| {
+            let mut flags = libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWNET;
+            if !running_as_root() {
+                flags |= libc::CLONE_NEWUSER;
+            }
+            let mut probe = std::process::Command::new("true");
+            // SAFETY: unshare is async-signal-safe
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(&mut probe, move || {
+                    check(libc::unshare(flags))
+                });
+            }
+            let available = probe.status().is_ok_and(|status| status.success());
+            if !available {
+                eprintln!("Namespaces are unavailable, skipping sandbox isolation tests");
+            }
+            available
+        })
+    }
+
+    #[tokio::test]
+    async fn isolation_confines_the_program() {
+        if !namespaces_available() {
+            return;
+        }
+        let script = "echo kept > note && cat note; \
+            touch /etc/paper-sage-escape 2>/dev/null && echo wrote-etc; \
+            ls /proc | grep -c '^[0-9]'; \
+            ls -A /tmp | wc -l";
+        let output = run_sh(script, &limits(Duration::from_secs(10), true))
+            .await
+            .unwrap();
+        assert!(output.success(), "{:?}", output);
+        let lines: Vec<&str> = output.stdout.lines().collect();
+        // Writable workspace, read-only /etc, a handful of own processes, only the workspace
+        // in /tmp
+        assert_eq!(lines[0], "kept");
+        assert!(!output.stdout.contains("wrote-etc"));
+        assert!(lines[1].parse::<u32>().unwrap() < 10);
+        assert_eq!(lines[2], "1");
+    }
+
+    #[tokio::test]
+    async fn isolated_program_reads_stdin() {
+        if !namespaces_available() {
+            return;
+        }
+        let workdir = tempfile::tempdir().unwrap();
+        let argv = ["cat".to_string()];
+        let limits = limits(Duration::from_secs(10), true);
+        let output = run(workdir.path(), &argv, Some("2 3"), &limits)
+            .await
+            .unwrap();
+        assert!(output.success(), "{:?}", output);
+        assert_eq!(output.stdout, "2 3");
+    }
+
+    #[tokio::test]
+    async fn isolated_program_has_no_network() {
+        if !namespaces_available() {
+            return;
+        }
+        // A server on the grader's loopback interface, reachable unless the network is cut
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connect = |isolate| async move {
+            let workdir = tempfile::tempdir().unwrap();
+            let script = format!("echo hi > /dev/tcp/127.0.0.1/{} && echo connected", port);
+            let argv = ["bash", "-c", &script].map(String::from);
+            run(
+                workdir.path(),
+                &argv,
+                None,
+                &limits(Duration::from_secs(10), isolate),
+            )
+            .await
+            .unwrap()
+        };
+
+        let output = connect(false).await;
+        assert_eq!(output.stdout, "connected\n", "{:?}", output);
+        let output = connect(true).await;
+        assert!(!output.success(), "{:?}", output);
+        assert_eq!(output.stdout, "");
+    }
+}
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+use tracing::warn;
+
+/// Maximum number of entries in one archive, extracted or not
+const MAX_ENTRIES: usize = 10_000;
+/// Maximum uncompressed size of a single extracted file
+const MAX_ENTRY_BYTES: u64 = 50 * 1024 * 1024;
+/// Maximum uncompressed size of everything decompressed from one archive
+const MAX_TOTAL_BYTES: u64 = 200 * 1024 * 1024;
+
+/// Archive formats that are expanded in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// A file extracted from an archive
+pub struct ArchiveEntry {
+    /// Path inside the archive, always relative and `/`-separated
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+impl ArchiveKind {
+    /// Recognise an archive by its file name
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Path of an archive without its archive extension (`alice/work.tar.gz` -> `alice/work`)
+pub fn strip_archive_suffix(path: &str) -> &str {
+    for suffix in [".tar.gz", ".tgz", ".tar", ".zip"] {
+        if let Some(start) = path.len().checked_sub(suffix.len()) {
+            if path.as_bytes()[start..].eq_ignore_ascii_case(suffix.as_bytes()) {
+                return &path[..start];
+            }
+        }
+    }
+    path
+}
+
+/// Extract the files accepted by `keep` from the archive at `path`
+///
+/// Entries escaping the archive root, links and other special files are skipped;
+/// archives exceeding the entry or size limits are rejected as a whole.
+pub fn read_archive(
+    path: &Path,
+    kind: ArchiveKind,
+    keep: impl Fn(&str) -> bool,
+) -> Result<Vec<ArchiveEntry>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open archive: {}", path.display()))?;
+
+    // macOS archivers add resource forks that are not part of the submission
+    let keep = |entry: &str| !entry.starts_with("__MACOSX/") && keep(entry);
+    let mut budget = Budget::default();
+    match kind {
+        ArchiveKind::Zip => read_zip(file, path, &keep, &mut budget),
+        ArchiveKind::Tar => read_tar(file, path, &keep, &mut budget),
+        ArchiveKind::TarGz => {
+            read_tar(flate2::read::GzDecoder::new(file), path, &keep, &mut budget)
+        }
+    }
+    .with_context(|| format!("Failed to read archive: {}", path.display()))
+}
+
+fn read_zip(
+    file: File,
+    archive: &Path,
+    keep: &impl Fn(&str) -> bool,
+    budget: &mut Budget,
+) -> Result<Vec<ArchiveEntry>> {
+    let mut zip = zip::ZipArchive::new(file)?;
+    let mut entries = Vec::new();
+    for index in 0..zip.len() {
+        budget.count_entry()?;
+        let mut entry = zip.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let Some(path) = entry.enclosed_name().and_then(|p| normalize_entry_path(&p)) else {
+            warn!(
+                "Skipping unsafe path {} in archive {}",
+                entry.name(),
+                archive.display()
+            );
+            continue;
+        };
+        if !keep(&path) {
+            continue;
+        }
+
+        let bytes = budget.read(&mut entry, &path)?;
+        entries.push(ArchiveEntry { path, bytes });
+    }
+
+    Ok(entries)
+}
+
+fn read_tar<R: Read>(
+    reader: R,
+    archive: &Path,
+    keep: &impl Fn(&str) -> bool,
+    budget: &mut Budget,
+) -> Result<Vec<ArchiveEntry>> {
+    let mut tar = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        budget.count_entry()?;
+        // Links could point anywhere on the grader's machine
+        let path = if entry.header().entry_type().is_file() {
+            let raw_path = entry.path()?.into_owned();
+            let path = normalize_entry_path(&raw_path);
+            if path.is_none() {
+                warn!(
+                    "Skipping unsafe path {} in archive {}",
+                    raw_path.display(),
+                    archive.display()
+                );
+            }
+            path.filter(|path| keep(path))
+        } else {
+            None
+        };
+
+        match path {
+            Some(path) => {
+                let bytes = budget.read(&mut entry, &path)?;
+                entries.push(ArchiveEntry { path, bytes });
+            }
+            // The stream has to be decompressed past skipped entries all the same
+            None => budget.skip(&mut entry)?,
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Convert an entry path into a relative `/`-separated path, rejecting
+/// absolute paths and `..` components
+fn normalize_entry_path(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Running total of archive entries and decompressed bytes, enforced on the decompressed
+/// stream so that forged size headers cannot bypass the limits. Skipped entries count too:
+/// a tar stream decompresses them to reach the next entry
+struct Budget {
+    entries: usize,
+    total: u64,
+    max_entries: usize,
+    max_entry_bytes: u64,
+    max_total_bytes: u64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            entries: 0,
+            total: 0,
+            max_entries: MAX_ENTRIES,
+            max_entry_bytes: MAX_ENTRY_BYTES,
+            max_total_bytes: MAX_TOTAL_BYTES,
+        }
+    }
+}
+
+impl Budget {
+    fn count_entry(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            anyhow::bail!("Archive has more than {} entries", self.max_entries);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, reader: &mut impl Read, path: &str) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        reader
+            .take(self.max_entry_bytes + 1)
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to extract {}", path))?;
+
+        let size = bytes.len() as u64;
+        if size > self.max_entry_bytes {
+            anyhow::bail!(
+                "Entry {} exceeds the {} MB size limit",
+                path,
+                self.max_entry_bytes / 1024 / 1024
+            );
+        }
+        self.add(size)?;
+        Ok(bytes)
+    }
+
+    /// Decompress an entry that is not extracted, counting its bytes
+    fn skip(&mut self, reader: &mut impl Read) -> Result<()> {
+        let remaining = self.max_total_bytes.saturating_sub(self.total);
+        let size = std::io::copy(&mut reader.take(remaining + 1), &mut std::io::sink())?;
+        self.add(size)
+    }
+
+    fn add(&mut self, size: u64) -> Result<()> {
+        self.total += size;
+        if self.total > self.max_total_bytes {
+            anyhow::bail!(
+                "Archive exceeds the {} MB total size limit",
+                self.max_total_bytes / 1024 / 1024
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tar_header(path: &str, size: usize, kind: tar::EntryType) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        // Written directly so that unsafe paths, which `set_path` refuses, can be tested
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_size(size as u64);
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        header.set_cksum();
+        header
+    }
+
+    fn tar_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let header = tar_header(path, content.len(), tar::EntryType::Regular);
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn paths(entries: &[ArchiveEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    fn read(tar: &[u8], budget: &mut Budget) -> Result<Vec<ArchiveEntry>> {
+        read_tar(
+            tar,
+            Path::new("test.tar"),
+            &|path: &str| !path.starts_with("skip/"),
+            budget,
+        )
+    }
+
+    #[test]
+    fn recognizes_archive_names() {
+        assert_eq!(
+            ArchiveKind::from_path(Path::new("a/Work.TGZ")),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::from_path(Path::new("work.zip")),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(ArchiveKind::from_path(Path::new("work.gz")), None);
+        assert_eq!(strip_archive_suffix("alice/work.tar.gz"), "alice/work");
+        assert_eq!(strip_archive_suffix("alice/WORK.ZIP"), "alice/WORK");
+        assert_eq!(strip_archive_suffix("alice/work.py"), "alice/work.py");
+    }
+
+    #[test]
+    fn extracts_zip_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in [
+            ("src/main.py", "print(1)"),
+            ("./README.md", "# Work"),
+            ("__MACOSX/src/._main.py", "fork"),
+            ("../escape.py", "evil"),
+            ("skip/lib.py", "dependency"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.add_directory("docs/", options).unwrap();
+        zip.finish().unwrap();
+
+        let entries =
+            read_archive(&path, ArchiveKind::Zip, |path| !path.starts_with("skip/")).unwrap();
+        assert_eq!(paths(&entries), vec!["src/main.py", "README.md"]);
+        assert_eq!(entries[0].bytes, b"print(1)");
+    }
+
+    #[test]
+    fn extracts_compressed_tar_files() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, kind, content) in [
+            ("main.c", tar::EntryType::Regular, &b"int main;"[..]),
+            ("/etc/passwd", tar::EntryType::Regular, b"root"),
+            ("a/../../up.c", tar::EntryType::Regular, b"up"),
+            ("link.c", tar::EntryType::Symlink, b""),
+            ("skip/dep.c", tar::EntryType::Regular, b"dep"),
+            ("lib/util.c", tar::EntryType::Regular, b"util"),
+        ] {
+            let header = tar_header(path, content.len(), kind);
+            builder.append(&header, content).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.tgz");
+        std::fs::write(&path, gz.finish().unwrap()).unwrap();
+
+        let entries =
+            read_archive(&path, ArchiveKind::TarGz, |path| !path.starts_with("skip/")).unwrap();
+        assert_eq!(paths(&entries), vec!["main.c", "lib/util.c"]);
+        assert_eq!(entries[1].bytes, b"util");
+    }
+
+    #[test]
+    fn skipped_entries_count_towards_the_entry_limit() {
+        let tar = tar_of(&[
+            ("skip/1", b""),
+            ("skip/2", b""),
+            ("skip/3", b""),
+            ("main.c", b""),
+        ]);
+        let mut budget = Budget {
+            max_entries: 3,
+            ..Budget::default()
+        };
+        let error = read(&tar, &mut budget).err().unwrap();
+        assert!(
+            error.to_string().contains("more than 3 entries"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn skipped_entries_count_towards_the_size_limit() {
+        let dependency = vec![0u8; 2048];
+        let tar = tar_of(&[("skip/dep.bin", &dependency), ("main.c", b"int main;")]);
+        let mut budget = Budget {
+            max_total_bytes: 1024,
+            ..Budget::default()
+        };
+        let error = read(&tar, &mut budget).err().unwrap();
+        assert!(error.to_string().contains("total size limit"), "{}", error);
+
+        let mut budget = Budget {
+            max_total_bytes: 4096,
+            ..Budget::default()
+        };
+        let entries = read(&tar, &mut budget).unwrap();
+        assert_eq!(paths(&entries), vec!["main.c"]);
+        assert_eq!(budget.total, 2048 + 9);
+    }
+
+    #[test]
+    fn rejects_oversized_entries() {
+        let big = vec![b'x'; 100];
+        let tar = tar_of(&[("big.txt", &big)]);
+        let mut budget = Budget {
+            max_entry_bytes: 99,
+            ..Budget::default()
+        };
+        let error = read(&tar, &mut budget).err().unwrap();
+        assert!(error.to_string().contains("big.txt exceeds"), "{}", error);
+    }
+}
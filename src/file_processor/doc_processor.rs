@@ -0,0 +1,228 @@
+use super::rtf_processor::windows_1252;
+use anyhow::{Context, Result};
+use std::io::{Cursor, Read};
+
+/// Magic number of a Word 97-2003 File Information Block
+const WORD_IDENT: u16 = 0xA5EC;
+/// `fWhichTblStm` flag selecting `1Table` over `0Table`
+const FLAG_TABLE_1: u16 = 0x0200;
+/// `fEncrypted` flag
+const FLAG_ENCRYPTED: u16 = 0x0100;
+/// Offset of `ccpText`, the length of the main document in characters
+const CCP_TEXT_OFFSET: usize = 0x4C;
+/// Offsets of `fcClx`/`lcbClx`, locating the piece table in the table stream
+const FC_CLX_OFFSET: usize = 0x1A2;
+const LCB_CLX_OFFSET: usize = 0x1A6;
+/// Bit of a piece's file offset marking 8-bit (compressed) text
+const COMPRESSED_FLAG: u32 = 0x4000_0000;
+
+/// Extract the main document text from a legacy Word (.doc) OLE compound file
+pub fn extract_doc_text(bytes: &[u8], name: &str) -> Result<String> {
+    let mut compound = cfb::CompoundFile::open(Cursor::new(bytes))
+        .with_context(|| format!("Not an OLE compound document: {}", name))?;
+
+    let word_document = read_stream(&mut compound, "/WordDocument")
+        .with_context(|| format!("No WordDocument stream in {}", name))?;
+    if read_u16(&word_document, 0) != Some(WORD_IDENT) {
+        anyhow::bail!("Unrecognised Word document format: {}", name);
+    }
+    let flags = read_u16(&word_document, 0x0A).unwrap_or(0);
+    if flags & FLAG_ENCRYPTED != 0 {
+        anyhow::bail!("Word document is password protected: {}", name);
+    }
+
+    let table_name = if flags & FLAG_TABLE_1 != 0 {
+        "/1Table"
+    } else {
+        "/0Table"
+    };
+    let table = read_stream(&mut compound, table_name)
+        .with_context(|| format!("No {} stream in {}", &table_name[1..], name))?;
+
+    let field = |offset| {
+        read_u32(&word_document, offset)
+            .map(|value| value as usize)
+            .with_context(|| format!("Truncated Word document header: {}", name))
+    };
+    let ccp_text = field(CCP_TEXT_OFFSET)?;
+    let fc_clx = field(FC_CLX_OFFSET)?;
+    let lcb_clx = field(LCB_CLX_OFFSET)?;
+    let clx = table
+        .get(fc_clx..fc_clx + lcb_clx)
+        .with_context(|| format!("Corrupt piece table in {}", name))?;
+
+    let text = read_pieces(&word_document, clx, ccp_text)
+        .with_context(|| format!("Corrupt piece table in {}", name))?;
+    Ok(clean_text(&text))
+}
+
+fn read_stream(compound: &mut cfb::CompoundFile<Cursor<&[u8]>>, path: &str) -> Result<Vec<u8>> {
+    let mut stream = compound.open_stream(path)?;
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Walk the piece table (`Clx`), concatenating the first `limit` characters
+fn read_pieces(document: &[u8], clx: &[u8], limit: usize) -> Option<String> {
+    // Skip property modifiers (`Prc`) that precede the piece table (`Pcdt`)
+    let mut pos = 0;
+    while *clx.get(pos)? == 0x01 {
+        pos += 3 + read_u16(clx, pos + 1)? as usize;
+    }
+    if *clx.get(pos)? != 0x02 {
+        return None;
+    }
+    let size = read_u32(clx, pos + 1)? as usize;
+    let plc = clx.get(pos + 5..pos + 5 + size)?;
+
+    // PlcPcd: n + 1 character positions followed by n 8-byte piece descriptors
+    let pieces = plc.len().checked_sub(4)? / 12;
+    let mut text = String::new();
+    let mut remaining = limit;
+    for i in 0..pieces {
+        if remaining == 0 {
+            break;
+        }
+        let start = read_u32(plc, i * 4)? as usize;
+        let end = read_u32(plc, (i + 1) * 4)? as usize;
+        let count = end.checked_sub(start)?.min(remaining);
+        let fc = read_u32(plc, (pieces + 1) * 4 + i * 8 + 2)?;
+
+        if fc & COMPRESSED_FLAG != 0 {
+            let offset = ((fc & !COMPRESSED_FLAG) / 2) as usize;
+            let bytes = document.get(offset..offset + count)?;
+            text.extend(bytes.iter().map(|&b| windows_1252(b)));
+        } else {
+            let offset = fc as usize;
+            let bytes = document.get(offset..offset + count * 2)?;
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            text.push_str(&String::from_utf16_lossy(&units));
+        }
+        remaining -= count;
+    }
+    Some(text)
+}
+
+/// Translate Word's control characters and drop field instructions,
+/// keeping the displayed field results
+fn clean_text(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    // One entry per open field, true while still in its instruction part
+    let mut fields: Vec<bool> = Vec::new();
+    for c in raw.chars() {
+        match c {
+            '\u{13}' => fields.push(true),
+            '\u{14}' => {
+                if let Some(in_instruction) = fields.last_mut() {
+                    *in_instruction = false;
+                }
+            }
+            '\u{15}' => {
+                fields.pop();
+            }
+            _ if fields.iter().any(|&in_instruction| in_instruction) => {}
+            '\r' | '\u{0b}' | '\u{0c}' => text.push('\n'),
+            '\u{07}' => text.push('\t'),
+            '\u{1e}' => text.push('-'),
+            '\u{1f}' => {}
+            c if c.is_control() && c != '\t' => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let slice = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([slice[0], slice[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let slice = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEXT_OFFSET: usize = 0x600;
+
+    /// A minimal Word 97 file: a FIB pointing at a piece table with an 8-bit piece
+    /// followed by a UTF-16 piece
+    fn word_document(first: &str, second: &str, flags: u16) -> Vec<u8> {
+        let first_bytes: Vec<u8> = first.chars().map(|c| c as u8).collect();
+        let second_units: Vec<u16> = second.encode_utf16().collect();
+        let second_offset = TEXT_OFFSET + first_bytes.len() + 1;
+
+        let mut document = vec![0u8; TEXT_OFFSET];
+        document[0..2].copy_from_slice(&WORD_IDENT.to_le_bytes());
+        document[0x0A..0x0C].copy_from_slice(&(flags | FLAG_TABLE_1).to_le_bytes());
+        let ccp_text = (first_bytes.len() + second_units.len()) as u32;
+        document[CCP_TEXT_OFFSET..CCP_TEXT_OFFSET + 4].copy_from_slice(&ccp_text.to_le_bytes());
+        document.extend(&first_bytes);
+        document.push(0);
+        for unit in &second_units {
+            document.extend(unit.to_le_bytes());
+        }
+
+        let mut plc = Vec::new();
+        for cp in [0, first_bytes.len(), first_bytes.len() + second_units.len()] {
+            plc.extend((cp as u32).to_le_bytes());
+        }
+        for fc in [
+            (TEXT_OFFSET as u32 * 2) | COMPRESSED_FLAG,
+            second_offset as u32,
+        ] {
+            plc.extend([0, 0]);
+            plc.extend(fc.to_le_bytes());
+            plc.extend([0, 0]);
+        }
+        // A property modifier before the piece table, as Word writes them
+        let mut clx = vec![0x01, 0x02, 0x00, 0xAA, 0xBB, 0x02];
+        clx.extend((plc.len() as u32).to_le_bytes());
+        clx.extend(plc);
+        let mut table = vec![0u8; 16];
+        let fc_clx = table.len() as u32;
+        table.extend(&clx);
+        document[FC_CLX_OFFSET..FC_CLX_OFFSET + 4].copy_from_slice(&fc_clx.to_le_bytes());
+        document[LCB_CLX_OFFSET..LCB_CLX_OFFSET + 4]
+            .copy_from_slice(&(clx.len() as u32).to_le_bytes());
+
+        let mut compound = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        for (path, bytes) in [("/WordDocument", &document), ("/1Table", &table)] {
+            compound
+                .create_stream(path)
+                .unwrap()
+                .write_all(bytes)
+                .unwrap();
+        }
+        compound.flush().unwrap();
+        compound.into_inner().into_inner()
+    }
+
+    #[test]
+    fn extracts_text_from_both_kinds_of_pieces() {
+        let bytes = word_document(
+            "Caf\u{e9}: see \u{13} HYPERLINK \"http://x\" \u{14}the site\u{15}.\r",
+            "\u{3bb} = 1\u{7}2\u{7}\r",
+            0,
+        );
+        let text = extract_doc_text(&bytes, "test.doc").unwrap();
+        assert_eq!(text, "Café: see the site.\nλ = 1\t2\t\n");
+    }
+
+    #[test]
+    fn reports_unreadable_documents() {
+        let encrypted = word_document("secret", "", FLAG_ENCRYPTED);
+        let error = extract_doc_text(&encrypted, "test.doc").unwrap_err();
+        assert!(error.to_string().contains("password protected"));
+
+        assert!(extract_doc_text(b"not a compound file", "test.doc").is_err());
+    }
+}
@@ -1,37 +1,783 @@
 use anyhow::{Context, Result};
-use std::path::Path;
-
-/// Read and extract text from DOCX files
-pub fn read_docx_file(path: &Path) -> Result<String> {
-    let bytes = std::fs::read(path)
-        .with_context(|| format!("Failed to read DOCX file: {}", path.display()))?;
-
-    let docx = docx_rs::read_docx(&bytes)
-        .with_context(|| format!("Failed to parse DOCX file: {}", path.display()))?;
-
-    let text = docx
-        .document
-        .children
-        .iter()
-        .map(|content| match content {
-            docx_rs::DocumentChild::Paragraph(paragraph) => paragraph
-                .children
-                .iter()
-                .map(|child| match child {
-                    docx_rs::ParagraphChild::Run(run) => run
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// Largest XML part read from a DOCX package
+const MAX_PART_BYTES: u64 = 50 * 1024 * 1024;
+/// Largest amount of XML read from all parts of one DOCX package together
+const MAX_PACKAGE_BYTES: u64 = 100 * 1024 * 1024;
+/// Deepest element nesting accepted; the tree is walked recursively, and real documents
+/// stay far below it
+const MAX_XML_DEPTH: usize = 256;
+
+/// Extract text from the contents of a DOCX file, keeping paragraphs, headings,
+/// list numbering, tables (as Markdown), text boxes, headers, footers and notes
+pub fn extract_docx_text(bytes: &[u8], name: &str) -> Result<String> {
+    let mut package = Package {
+        archive: zip::ZipArchive::new(Cursor::new(bytes))
+            .with_context(|| format!("Failed to open DOCX file: {}", name))?,
+        remaining: MAX_PACKAGE_BYTES,
+    };
+
+    let document = package
+        .read_part("word/document.xml")?
+        .with_context(|| format!("DOCX file has no document body: {}", name))?;
+    let document =
+        parse_xml(&document).with_context(|| format!("Failed to parse DOCX file: {}", name))?;
+
+    let mut renderer = Renderer {
+        headings: match package.read_part("word/styles.xml")? {
+            Some(xml) => heading_styles(&parse_xml(&xml)?),
+            None => HashMap::new(),
+        },
+        numbering: match package.read_part("word/numbering.xml")? {
+            Some(xml) => Numbering::parse(&parse_xml(&xml)?),
+            None => Numbering::default(),
+        },
+        links: match package.read_part("word/_rels/document.xml.rels")? {
+            Some(xml) => external_links(&parse_xml(&xml)?),
+            None => HashMap::new(),
+        },
+        counters: HashMap::new(),
+    };
+
+    let header = render_section(&mut package, &mut renderer, "word/header")?;
+    let footer = render_section(&mut package, &mut renderer, "word/footer")?;
+
+    let mut notes = Vec::new();
+    for (part, element, marker) in [
+        ("word/footnotes.xml", "w:footnote", ""),
+        ("word/endnotes.xml", "w:endnote", "e"),
+    ] {
+        if let Some(xml) = package.read_part(part)? {
+            for note in parse_xml(&xml)?.descendants(element) {
+                // Separator notes carry no content
+                if note.attr("w:type").is_some() {
+                    continue;
+                }
+                let text = renderer.render_blocks(note).join(" ");
+                notes.push(format!(
+                    "[^{}{}]: {}",
+                    marker,
+                    note.attr("w:id").unwrap_or_default(),
+                    text.trim()
+                ));
+            }
+        }
+    }
+
+    let mut text = String::new();
+    if !header.is_empty() {
+        text.push_str(&format!(
+            "--- header ---\n{}\n--- end header ---\n\n",
+            header
+        ));
+    }
+    text.push_str(&renderer.render_blocks(&document).join("\n"));
+    text.push('\n');
+    if !notes.is_empty() {
+        text.push_str(&format!("\n--- notes ---\n{}\n", notes.join("\n")));
+    }
+    if !footer.is_empty() {
+        text.push_str(&format!("\n--- footer ---\n{}\n", footer));
+    }
+
+    Ok(text)
+}
+
+/// Render every header or footer part, dropping repeats across sections
+fn render_section(package: &mut Package, renderer: &mut Renderer, prefix: &str) -> Result<String> {
+    let mut parts: Vec<String> = package
+        .archive
+        .file_names()
+        .filter(|part| part.starts_with(prefix) && part.ends_with(".xml"))
+        .map(str::to_string)
+        .collect();
+    parts.sort();
+
+    let mut texts: Vec<String> = Vec::new();
+    for part in parts {
+        if let Some(xml) = package.read_part(&part)? {
+            let text = renderer.render_blocks(&parse_xml(&xml)?).join("\n");
+            if !text.trim().is_empty() && !texts.contains(&text) {
+                texts.push(text);
+            }
+        }
+    }
+    Ok(texts.join("\n"))
+}
+
+/// A DOCX package and the bytes of XML that may still be read from it, so that many
+/// header and footer parts cannot add up beyond the limit
+struct Package<'a> {
+    archive: zip::ZipArchive<Cursor<&'a [u8]>>,
+    remaining: u64,
+}
+
+impl Package<'_> {
+    fn read_part(&mut self, part: &str) -> Result<Option<String>> {
+        let entry = match self.archive.by_name(part) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read DOCX part {}", part)),
+        };
+        let limit = MAX_PART_BYTES.min(self.remaining);
+        let mut xml = String::new();
+        // One byte more than allowed tells an oversized part from one that fits exactly
+        entry
+            .take(limit + 1)
+            .read_to_string(&mut xml)
+            .with_context(|| format!("Failed to read DOCX part {}", part))?;
+        if xml.len() as u64 > limit {
+            anyhow::bail!(
+                "DOCX part {} exceeds the size limit of {} MB per part and {} MB per document",
+                part,
+                MAX_PART_BYTES / 1024 / 1024,
+                MAX_PACKAGE_BYTES / 1024 / 1024
+            );
+        }
+        self.remaining -= xml.len() as u64;
+        Ok(Some(xml))
+    }
+}
+
+/// Minimal element tree, enough to walk WordprocessingML
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.name == name)
+    }
+
+    /// Value of the `w:val` attribute of the child at `path`
+    fn val(&self, path: &[&str]) -> Option<&str> {
+        let mut element = self;
+        for name in path {
+            element = element.child(name)?;
+        }
+        element.attr("w:val")
+    }
+
+    /// All descendants named `name`, not looking inside matches
+    fn descendants<'a>(&'a self, name: &'a str) -> Vec<&'a Element> {
+        let mut found = Vec::new();
+        for element in self.elements() {
+            if element.name == name {
+                found.push(element);
+            } else {
+                found.extend(element.descendants(name));
+            }
+        }
+        found
+    }
+
+    fn text(&self) -> String {
+        self.children
+            .iter()
+            .map(|node| match node {
+                Node::Text(text) => text.clone(),
+                Node::Element(element) => element.text(),
+            })
+            .collect()
+    }
+}
+
+fn parse_xml(xml: &str) -> Result<Element> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut stack = vec![Element::default()];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                if stack.len() > MAX_XML_DEPTH {
+                    anyhow::bail!("XML elements are nested more than {} deep", MAX_XML_DEPTH);
+                }
+                stack.push(start_element(&start)?)
+            }
+            Event::Empty(start) => {
+                let element = start_element(&start)?;
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(Node::Element(element));
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().context("Unbalanced XML")?;
+                stack
+                    .last_mut()
+                    .context("Unbalanced XML")?
+                    .children
+                    .push(Node::Element(element));
+            }
+            Event::Text(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent
                         .children
-                        .iter()
-                        .map(|text_child| match text_child {
-                            docx_rs::RunChild::Text(text) => text.text.clone(),
-                            _ => String::new(),
-                        })
-                        .collect::<String>(),
-                    _ => String::new(),
-                })
-                .collect::<String>(),
-            _ => String::new(),
+                        .push(Node::Text(text.unescape()?.into_owned()));
+                }
+            }
+            Event::CData(data) => {
+                if let Some(parent) = stack.last_mut() {
+                    let text = String::from_utf8_lossy(&data).into_owned();
+                    parent.children.push(Node::Text(text));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    stack.pop().context("Empty XML document")
+}
+
+fn start_element(start: &BytesStart) -> Result<Element> {
+    let mut attrs = Vec::new();
+    for attr in start.attributes() {
+        let attr = attr?;
+        attrs.push((
+            String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+            attr.unescape_value()?.into_owned(),
+        ));
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attrs,
+        children: Vec::new(),
+    })
+}
+
+/// Heading level per paragraph style id, from style names ("heading 2") or outline levels
+fn heading_styles(styles: &Element) -> HashMap<String, usize> {
+    let mut headings = HashMap::new();
+    for style in styles.descendants("w:style") {
+        let Some(id) = style.attr("w:styleId") else {
+            continue;
+        };
+        let name = style.val(&["w:name"]).unwrap_or_default().to_lowercase();
+        let level = if name == "title" {
+            Some(1)
+        } else if let Some(level) = name.strip_prefix("heading ") {
+            level.trim().parse().ok()
+        } else {
+            style
+                .val(&["w:pPr", "w:outlineLvl"])
+                .and_then(|level| level.parse::<usize>().ok())
+                .filter(|level| *level < 9)
+                .map(|level| level + 1)
+        };
+        if let Some(level) = level {
+            headings.insert(id.to_string(), level);
+        }
+    }
+    headings
+}
+
+/// Targets of the external relationships (hyperlinks) in a `.rels` part
+fn external_links(relationships: &Element) -> HashMap<String, String> {
+    relationships
+        .descendants("Relationship")
+        .into_iter()
+        .filter(|relationship| relationship.attr("TargetMode") == Some("External"))
+        .filter_map(|relationship| {
+            Some((
+                relationship.attr("Id")?.to_string(),
+                relationship.attr("Target")?.to_string(),
+            ))
         })
-        .collect::<String>();
+        .collect()
+}
 
-    Ok(text)
+/// One list level definition from `numbering.xml`
+#[derive(Debug, Clone)]
+struct ListLevel {
+    start: usize,
+    format: String,
+    text: String,
+}
+
+#[derive(Debug, Default)]
+struct Numbering {
+    /// Levels per `w:numId`
+    lists: HashMap<String, Vec<ListLevel>>,
+}
+
+impl Numbering {
+    fn parse(numbering: &Element) -> Self {
+        let mut abstracts = HashMap::new();
+        for definition in numbering.descendants("w:abstractNum") {
+            let levels: Vec<ListLevel> = definition
+                .descendants("w:lvl")
+                .into_iter()
+                .map(|level| ListLevel {
+                    start: level
+                        .val(&["w:start"])
+                        .and_then(|start| start.parse().ok())
+                        .unwrap_or(1),
+                    format: level.val(&["w:numFmt"]).unwrap_or("decimal").to_string(),
+                    text: level.val(&["w:lvlText"]).unwrap_or_default().to_string(),
+                })
+                .collect();
+            if let Some(id) = definition.attr("w:abstractNumId") {
+                abstracts.insert(id.to_string(), levels);
+            }
+        }
+
+        let lists = numbering
+            .descendants("w:num")
+            .into_iter()
+            .filter_map(|num| {
+                let levels = abstracts.get(num.val(&["w:abstractNumId"])?)?;
+                Some((num.attr("w:numId")?.to_string(), levels.clone()))
+            })
+            .collect();
+        Self { lists }
+    }
+}
+
+struct Renderer {
+    headings: HashMap<String, usize>,
+    numbering: Numbering,
+    /// Hyperlink targets per relationship id of the main document
+    links: HashMap<String, String>,
+    /// Current item number per list and level
+    counters: HashMap<String, Vec<usize>>,
+}
+
+impl Renderer {
+    /// Render block-level content (paragraphs, tables, content controls) as lines
+    fn render_blocks(&mut self, container: &Element) -> Vec<String> {
+        let mut blocks = Vec::new();
+        for element in container.elements() {
+            match element.name.as_str() {
+                "w:p" => {
+                    let mut text_boxes = Vec::new();
+                    let paragraph = self.render_paragraph(element, &mut text_boxes);
+                    if !paragraph.trim().is_empty() {
+                        blocks.push(paragraph);
+                    }
+                    for text_box in text_boxes {
+                        blocks.push(format!(
+                            "--- text box ---\n{}\n--- end text box ---",
+                            text_box
+                        ));
+                    }
+                }
+                "w:tbl" => {
+                    blocks.push(String::new());
+                    blocks.push(self.render_table(element));
+                    blocks.push(String::new());
+                }
+                // Tracked deletions and compatibility fallbacks duplicate or remove content
+                "w:del" | "w:moveFrom" | "mc:Fallback" | "w:sectPr" => {}
+                _ => blocks.extend(self.render_blocks(element)),
+            }
+        }
+        blocks
+    }
+
+    fn render_paragraph(&mut self, paragraph: &Element, text_boxes: &mut Vec<String>) -> String {
+        let mut text = String::new();
+        self.render_inline(paragraph, &mut text, text_boxes);
+        if text.trim().is_empty() {
+            return text;
+        }
+
+        let properties = paragraph.child("w:pPr");
+        let heading = properties.and_then(|properties| {
+            properties
+                .val(&["w:outlineLvl"])
+                .and_then(|level| level.parse::<usize>().ok())
+                .filter(|level| *level < 9)
+                .map(|level| level + 1)
+                .or_else(|| self.headings.get(properties.val(&["w:pStyle"])?).copied())
+        });
+        if let Some(level) = heading {
+            return format!("{} {}", "#".repeat(level.min(6)), text.trim());
+        }
+
+        let list = properties.and_then(|properties| {
+            let num_id = properties.val(&["w:numPr", "w:numId"])?;
+            let level = properties
+                .val(&["w:numPr", "w:ilvl"])
+                .and_then(|level| level.parse().ok())
+                .unwrap_or(0);
+            Some((num_id.to_string(), level))
+        });
+        match list.and_then(|(num_id, level)| self.list_marker(&num_id, level).map(|m| (m, level)))
+        {
+            Some((marker, level)) => format!("{}{} {}", "  ".repeat(level), marker, text.trim()),
+            None => text,
+        }
+    }
+
+    /// Collect run text below `element`, diverting text boxes into `text_boxes`
+    fn render_inline(
+        &mut self,
+        element: &Element,
+        text: &mut String,
+        text_boxes: &mut Vec<String>,
+    ) {
+        for child in element.elements() {
+            match child.name.as_str() {
+                "w:t" => text.push_str(&child.text()),
+                "w:tab" | "w:ptab" => text.push('\t'),
+                "w:br" | "w:cr" => text.push('\n'),
+                "w:noBreakHyphen" => text.push('-'),
+                "w:footnoteReference" => {
+                    text.push_str(&format!("[^{}]", child.attr("w:id").unwrap_or_default()))
+                }
+                "w:endnoteReference" => {
+                    text.push_str(&format!("[^e{}]", child.attr("w:id").unwrap_or_default()))
+                }
+                "w:hyperlink" => {
+                    let mut label = String::new();
+                    self.render_inline(child, &mut label, text_boxes);
+                    match child.attr("r:id").and_then(|id| self.links.get(id)) {
+                        Some(url) if !label.trim().is_empty() && label.trim() != url => {
+                            text.push_str(&format!("[{}]({})", label.trim(), url))
+                        }
+                        _ => text.push_str(&label),
+                    }
+                }
+                "w:txbxContent" => {
+                    let content = self.render_blocks(child).join("\n");
+                    if !content.trim().is_empty() {
+                        text_boxes.push(content);
+                    }
+                }
+                // Properties, tracked deletions, field codes and duplicate fallbacks hold no visible text
+                "w:pPr" | "w:rPr" | "w:del" | "w:moveFrom" | "w:delText" | "w:instrText"
+                | "mc:Fallback" => {}
+                _ => self.render_inline(child, text, text_boxes),
+            }
+        }
+    }
+
+    fn render_table(&mut self, table: &Element) -> String {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for row in table.elements().filter(|element| element.name == "w:tr") {
+            let mut cells = Vec::new();
+            for cell in row.elements().filter(|element| element.name == "w:tc") {
+                let content = self
+                    .render_blocks(cell)
+                    .iter()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("<br>");
+                cells.push(content.replace('|', "\\|").replace('\n', "<br>"));
+                // Horizontally merged cells occupy several grid columns
+                let span = cell
+                    .val(&["w:tcPr", "w:gridSpan"])
+                    .and_then(|span| span.parse::<usize>().ok())
+                    .unwrap_or(1);
+                cells.extend(std::iter::repeat_n(String::new(), span.saturating_sub(1)));
+            }
+            rows.push(cells);
+        }
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let mut lines = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let mut cells = row.clone();
+            cells.resize(columns, String::new());
+            lines.push(format!("| {} |", cells.join(" | ")));
+            if i == 0 {
+                lines.push(format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Advance the counter of a list level and format its marker
+    fn list_marker(&mut self, num_id: &str, level: usize) -> Option<String> {
+        // numId 0 explicitly removes numbering
+        let levels = self.numbering.lists.get(num_id)?;
+        let definition = levels.get(level).or_else(|| levels.last())?;
+        match definition.format.as_str() {
+            "bullet" => return Some("-".to_string()),
+            "none" => return None,
+            _ => {}
+        }
+
+        let counters = self
+            .counters
+            .entry(num_id.to_string())
+            .or_insert_with(|| vec![0; levels.len().max(1)]);
+        if counters.len() <= level {
+            counters.resize(level + 1, 0);
+        }
+        counters[level] = if counters[level] == 0 {
+            definition.start
+        } else {
+            counters[level] + 1
+        };
+        // A new item restarts every deeper level
+        for deeper in counters.iter_mut().skip(level + 1) {
+            *deeper = 0;
+        }
+
+        let mut marker = if definition.text.is_empty() {
+            format!("%{}.", level + 1)
+        } else {
+            definition.text.clone()
+        };
+        for (i, value) in counters.iter().enumerate().take(level + 1) {
+            let format = levels
+                .get(i)
+                .map(|level| level.format.as_str())
+                .unwrap_or("decimal");
+            marker = marker.replace(
+                &format!("%{}", i + 1),
+                &format_number((*value).max(1), format),
+            );
+        }
+        Some(marker)
+    }
+}
+
+fn format_number(value: usize, format: &str) -> String {
+    match format {
+        "lowerLetter" => letters(value),
+        "upperLetter" => letters(value).to_uppercase(),
+        "lowerRoman" => roman(value).to_lowercase(),
+        "upperRoman" => roman(value),
+        _ => value.to_string(),
+    }
+}
+
+/// a, b, ..., z, aa, bb, ... as Word numbers lettered lists
+fn letters(value: usize) -> String {
+    let letter = (b'a' + ((value - 1) % 26) as u8) as char;
+    letter.to_string().repeat((value - 1) / 26 + 1)
+}
+
+fn roman(mut value: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut numeral = String::new();
+    for (amount, symbol) in NUMERALS {
+        while value >= amount {
+            numeral.push_str(symbol);
+            value -= amount;
+        }
+    }
+    numeral
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const W: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships""#;
+
+    fn docx(parts: &[(&str, String)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, xml) in parts {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(xml.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn paragraph(properties: &str, runs: &str) -> String {
+        format!("<w:p><w:pPr>{}</w:pPr>{}</w:p>", properties, runs)
+    }
+
+    fn run(text: &str) -> String {
+        format!("<w:r><w:t xml:space=\"preserve\">{}</w:t></w:r>", text)
+    }
+
+    fn numbered(num_id: &str, level: usize, text: &str) -> String {
+        paragraph(
+            &format!(
+                r#"<w:numPr><w:ilvl w:val="{}"/><w:numId w:val="{}"/></w:numPr>"#,
+                level, num_id
+            ),
+            &run(text),
+        )
+    }
+
+    #[test]
+    fn keeps_the_document_structure() {
+        let body = [
+            paragraph(r#"<w:pStyle w:val="Heading1"/>"#, &run("Report")),
+            paragraph("", &format!(
+                "{}<w:r><w:tab/></w:r><w:hyperlink r:id=\"rId9\">{}</w:hyperlink>{}<w:del><w:r><w:delText>removed</w:delText></w:r></w:del><w:r><w:footnoteReference w:id=\"1\"/></w:r>",
+                run("Intro"),
+                run("the docs"),
+                run(" here."),
+            )),
+            numbered("1", 0, "First"),
+            numbered("1", 1, "Nested"),
+            numbered("1", 0, "Second"),
+            numbered("2", 0, "Point"),
+            format!(
+                r#"<w:tbl><w:tr><w:tc>{}</w:tc><w:tc>{}</w:tc></w:tr><w:tr><w:tc><w:tcPr><w:gridSpan w:val="2"/></w:tcPr>{}</w:tc></w:tr></w:tbl>"#,
+                paragraph("", &run("Input")),
+                paragraph("", &run("a|b")),
+                paragraph("", &run("merged")),
+            ),
+            paragraph("", &format!(
+                "{}<w:r><w:pict><w:txbxContent>{}</w:txbxContent></w:pict></w:r>",
+                run("See box"),
+                paragraph("", &run("Boxed text")),
+            )),
+            r#"<w:sectPr><w:headerReference r:id="rId1"/></w:sectPr>"#.to_string(),
+        ]
+        .concat();
+
+        let bytes = docx(&[
+            (
+                "word/document.xml",
+                format!("<w:document {}><w:body>{}</w:body></w:document>", W, body),
+            ),
+            (
+                "word/styles.xml",
+                format!(
+                    r#"<w:styles {}><w:style w:styleId="Heading1"><w:name w:val="heading 1"/></w:style></w:styles>"#,
+                    W
+                ),
+            ),
+            (
+                "word/numbering.xml",
+                format!(
+                    r#"<w:numbering {}>
+<w:abstractNum w:abstractNumId="0">
+  <w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1."/></w:lvl>
+  <w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="lowerLetter"/><w:lvlText w:val="%1.%2)"/></w:lvl>
+</w:abstractNum>
+<w:abstractNum w:abstractNumId="1"><w:lvl w:ilvl="0"><w:numFmt w:val="bullet"/></w:lvl></w:abstractNum>
+<w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num>
+<w:num w:numId="2"><w:abstractNumId w:val="1"/></w:num>
+</w:numbering>"#,
+                    W
+                ),
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                r#"<Relationships><Relationship Id="rId9" TargetMode="External" Target="https://example.org/docs"/></Relationships>"#.to_string(),
+            ),
+            (
+                "word/footnotes.xml",
+                format!(
+                    r#"<w:footnotes {}><w:footnote w:type="separator" w:id="-1">{}</w:footnote><w:footnote w:id="1">{}</w:footnote></w:footnotes>"#,
+                    W,
+                    paragraph("", &run("---")),
+                    paragraph("", &run("A note."))
+                ),
+            ),
+            (
+                "word/header1.xml",
+                format!("<w:hdr {}>{}</w:hdr>", W, paragraph("", &run("Alice Smith"))),
+            ),
+            (
+                "word/header2.xml",
+                format!("<w:hdr {}>{}</w:hdr>", W, paragraph("", &run("Alice Smith"))),
+            ),
+        ]);
+
+        let text = extract_docx_text(&bytes, "report.docx").unwrap();
+        assert_eq!(
+            text,
+            "--- header ---\nAlice Smith\n--- end header ---\n\n\
+             # Report\n\
+             Intro\t[the docs](https://example.org/docs) here.[^1]\n\
+             1. First\n  1.a) Nested\n2. Second\n- Point\n\n\
+             | Input | a\\|b |\n| --- | --- |\n| merged |  |\n\n\
+             See box\n--- text box ---\nBoxed text\n--- end text box ---\n\
+             \n--- notes ---\n[^1]: A note.\n"
+        );
+    }
+
+    #[test]
+    fn formats_list_numbers() {
+        assert_eq!(format_number(3, "lowerLetter"), "c");
+        assert_eq!(format_number(28, "upperLetter"), "BB");
+        assert_eq!(format_number(14, "lowerRoman"), "xiv");
+        assert_eq!(format_number(1994, "upperRoman"), "MCMXCIV");
+        assert_eq!(format_number(7, "decimal"), "7");
+    }
+
+    #[test]
+    fn rejects_packages_without_a_document() {
+        let bytes = docx(&[("word/styles.xml", "<w:styles/>".to_string())]);
+        assert!(extract_docx_text(&bytes, "empty.docx").is_err());
+        assert!(extract_docx_text(b"not a zip", "bad.docx").is_err());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_documents() {
+        let depth = 100_000;
+        let xml = format!(
+            "<w:document {}><w:body>{}{}</w:body></w:document>",
+            W,
+            "<w:sdt>".repeat(depth),
+            "</w:sdt>".repeat(depth)
+        );
+        let bytes = docx(&[("word/document.xml", xml)]);
+
+        let error = extract_docx_text(&bytes, "nested.docx").unwrap_err();
+        assert!(format!("{:#}", error).contains("nested more than"));
+    }
+
+    #[test]
+    fn limits_the_bytes_read_from_one_package() {
+        let header = format!("<w:hdr {}>{}</w:hdr>", W, paragraph("", &run("Header")));
+        let bytes = docx(&[
+            ("word/header1.xml", header.clone()),
+            ("word/header2.xml", header.clone()),
+        ]);
+        let mut package = Package {
+            archive: zip::ZipArchive::new(Cursor::new(bytes.as_slice())).unwrap(),
+            remaining: header.len() as u64 + 10,
+        };
+
+        assert!(package.read_part("word/header1.xml").unwrap().is_some());
+        let error = package.read_part("word/header2.xml").unwrap_err();
+        assert!(error.to_string().contains("size limit"));
+    }
 }
@@ -0,0 +1,225 @@
+use crate::models::FileSelection;
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// Folders holding build output, version control data, dependencies or editor settings,
+/// skipped wherever they appear
+const IGNORED_DIRS: &[&str] = &[
+    // Version control
+    ".git",
+    ".hg",
+    ".svn",
+    // Build output
+    "target",
+    "obj",
+    ".gradle",
+    "cmake-build-debug",
+    "cmake-build-release",
+    // Dependencies and virtual environments
+    "node_modules",
+    "bower_components",
+    "vendor",
+    "venv",
+    ".venv",
+    "__pycache__",
+    ".pytest_cache",
+    ".mypy_cache",
+    ".tox",
+    ".ipynb_checkpoints",
+    // Editors and operating systems
+    ".idea",
+    ".vscode",
+    ".vs",
+    "__MACOSX",
+];
+
+/// Whether a folder name is one of the default ignored folders
+pub fn is_ignored_dir(name: &str) -> bool {
+    IGNORED_DIRS.contains(&name)
+}
+
+/// Whether any folder along a `/`-separated path is ignored by default
+pub fn has_ignored_dir(path: &str) -> bool {
+    let mut components: Vec<&str> = path.split('/').collect();
+    components.pop();
+    components.into_iter().any(is_ignored_dir)
+}
+
+/// Assignment-level include/exclude globs, matched against paths inside a submission
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl FileFilter {
+    pub fn new(selection: &FileSelection) -> Result<Self> {
+        Ok(Self {
+            include: build_globs(&selection.include)?,
+            exclude: build_globs(&selection.exclude)?,
+        })
+    }
+
+    /// Whether a file at this path (relative to the student folder) is graded
+    pub fn allows(&self, path: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(path))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|globs| globs.is_match(path))
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid glob: {}", pattern))?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// `.gitignore` files found in submissions, each applying below its own folder
+#[derive(Clone, Default)]
+pub struct Gitignores {
+    /// Folder of each `.gitignore` (`/`-separated, empty for the input root) and its rules
+    rules: Vec<(String, Gitignore)>,
+}
+
+impl Gitignores {
+    /// Add the rules of a `.gitignore` found in `dir`
+    pub fn add(&mut self, dir: &str, content: &str) {
+        let mut builder = GitignoreBuilder::new(".");
+        for line in content.lines() {
+            // Lines that are not valid globs are ignored, as git does
+            let _ = builder.add_line(None, line);
+        }
+        if let Ok(gitignore) = builder.build() {
+            if !gitignore.is_empty() {
+                self.rules
+                    .push((dir.trim_end_matches('/').to_string(), gitignore));
+            }
+        }
+    }
+
+    /// Whether the file or folder at a `/`-separated path is ignored by a `.gitignore` above it;
+    /// as in git, the deepest `.gitignore` with a matching rule decides, so `!pattern` in a
+    /// nested one re-includes what a parent ignores
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .filter_map(|(dir, gitignore)| {
+                let relative = if dir.is_empty() {
+                    Some(path)
+                } else {
+                    path.strip_prefix(dir.as_str())
+                        .and_then(|rest| rest.strip_prefix('/'))
+                }?;
+                let matched = gitignore.matched_path_or_any_parents(Path::new(relative), is_dir);
+                (!matched.is_none()).then(|| (dir.len(), matched.is_ignore()))
+            })
+            .max_by_key(|(depth, _)| *depth)
+            .is_some_and(|(_, ignored)| ignored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> FileFilter {
+        FileFilter::new(&FileSelection {
+            include: include.iter().map(|glob| glob.to_string()).collect(),
+            exclude: exclude.iter().map(|glob| glob.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn recognizes_ignored_folders_along_a_path() {
+        assert!(has_ignored_dir("alice/node_modules/lib/index.js"));
+        assert!(has_ignored_dir(".git/config"));
+        assert!(has_ignored_dir("alice/project/__pycache__/main.pyc"));
+        assert!(!has_ignored_dir("alice/src/main.py"));
+        // The file name itself is not a folder
+        assert!(!has_ignored_dir("alice/target"));
+        assert!(!has_ignored_dir("alice/targets/main.rs"));
+    }
+
+    #[test]
+    fn filters_files_by_include_and_exclude_globs() {
+        let all = FileFilter::default();
+        assert!(all.allows("src/main.py"));
+
+        let only_python = filter(&["**/*.py"], &[]);
+        assert!(only_python.allows("main.py"));
+        assert!(only_python.allows("src/util.py"));
+        assert!(!only_python.allows("README.md"));
+
+        let without_tests = filter(&["src/**"], &["**/test_*.py"]);
+        assert!(without_tests.allows("src/main.py"));
+        assert!(!without_tests.allows("src/test_main.py"));
+        assert!(!without_tests.allows("main.py"));
+
+        assert!(FileFilter::new(&FileSelection {
+            include: vec!["src/[".to_string()],
+            exclude: Vec::new(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn gitignore_applies_below_its_own_folder() {
+        let mut gitignores = Gitignores::default();
+        gitignores.add("alice", "*.log\nbuild/\n");
+
+        assert!(gitignores.is_ignored("alice/run.log", false));
+        assert!(gitignores.is_ignored("alice/src/debug.log", false));
+        assert!(gitignores.is_ignored("alice/build", true));
+        assert!(gitignores.is_ignored("alice/build/out.py", false));
+        assert!(!gitignores.is_ignored("bob/run.log", false));
+        assert!(!gitignores.is_ignored("alicia/run.log", false));
+        assert!(!gitignores.is_ignored("alice/main.py", false));
+        // `build/` only matches folders
+        assert!(!gitignores.is_ignored("alice/build", false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_only_next_to_the_gitignore() {
+        let mut gitignores = Gitignores::default();
+        gitignores.add("", "/out\n");
+        gitignores.add("alice", "/dist\ncache\n");
+
+        assert!(gitignores.is_ignored("out", true));
+        assert!(!gitignores.is_ignored("alice/out", true));
+        assert!(gitignores.is_ignored("alice/dist", true));
+        assert!(!gitignores.is_ignored("alice/src/dist", true));
+        assert!(!gitignores.is_ignored("bob/dist", true));
+        assert!(gitignores.is_ignored("alice/cache", true));
+        assert!(gitignores.is_ignored("alice/src/cache", true));
+    }
+
+    #[test]
+    fn negations_re_include_files() {
+        let mut gitignores = Gitignores::default();
+        gitignores.add("", "*.txt\n!requirements.txt\n");
+        gitignores.add("alice", "!notes.txt\n");
+        gitignores.add("alice/data", "*.csv\n");
+
+        assert!(gitignores.is_ignored("bob/output.txt", false));
+        assert!(!gitignores.is_ignored("bob/requirements.txt", false));
+        // A nested `.gitignore` overrides its parents
+        assert!(!gitignores.is_ignored("alice/notes.txt", false));
+        assert!(gitignores.is_ignored("alice/output.txt", false));
+        // ...and a parent's rules still apply when the nested one does not match
+        assert!(gitignores.is_ignored("alice/data/output.txt", false));
+        assert!(gitignores.is_ignored("alice/data/points.csv", false));
+        assert!(!gitignores.is_ignored("alice/points.csv", false));
+    }
+}
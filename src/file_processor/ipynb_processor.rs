@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Characters of each output kept in the rendered text
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// Render a Jupyter notebook as linear text with cell markers, keeping code,
+/// markdown and the text outputs the student obtained
+pub fn extract_notebook_text(bytes: &[u8], name: &str) -> Result<String> {
+    let notebook: Value = serde_json::from_slice(bytes)
+        .with_context(|| format!("Failed to parse notebook: {}", name))?;
+
+    // nbformat 4 keeps cells at the top level, nbformat 3 inside worksheets
+    let cells: Vec<&Value> = match notebook.get("cells").and_then(Value::as_array) {
+        Some(cells) => cells.iter().collect(),
+        None => notebook
+            .get("worksheets")
+            .and_then(Value::as_array)
+            .with_context(|| format!("Notebook has no cells: {}", name))?
+            .iter()
+            .filter_map(|sheet| sheet.get("cells").and_then(Value::as_array))
+            .flatten()
+            .collect(),
+    };
+
+    let mut text = String::new();
+    for (i, cell) in cells.iter().enumerate() {
+        let cell_type = cell
+            .get("cell_type")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+        let source = cell
+            .get("source")
+            .or_else(|| cell.get("input"))
+            .map(join_lines)
+            .unwrap_or_default();
+
+        match cell
+            .get("execution_count")
+            .or_else(|| cell.get("prompt_number"))
+            .and_then(Value::as_u64)
+        {
+            Some(count) => text.push_str(&format!(
+                "# --- cell {} [{}] (execution {}) ---\n",
+                i + 1,
+                cell_type,
+                count
+            )),
+            None => text.push_str(&format!("# --- cell {} [{}] ---\n", i + 1, cell_type)),
+        }
+        text.push_str(source.trim_end());
+        text.push('\n');
+
+        let outputs = cell
+            .get("outputs")
+            .and_then(Value::as_array)
+            .map(|outputs| render_outputs(outputs))
+            .unwrap_or_default();
+        if !outputs.is_empty() {
+            text.push_str("# --- output ---\n");
+            text.push_str(&outputs);
+            text.push('\n');
+        }
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+fn render_outputs(outputs: &[Value]) -> String {
+    let mut rendered = Vec::new();
+    for output in outputs {
+        let output_type = output
+            .get("output_type")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        match output_type {
+            "stream" => {
+                if let Some(text) = output.get("text") {
+                    rendered.push(join_lines(text));
+                }
+            }
+            "execute_result" | "display_data" | "pyout" => {
+                // nbformat 3 stores mime data directly on the output
+                let data = output.get("data").unwrap_or(output);
+                if let Some(text) = data.get("text/plain").or_else(|| data.get("text")) {
+                    rendered.push(join_lines(text));
+                } else if let Some(mime) = data
+                    .as_object()
+                    .and_then(|data| data.keys().find(|key| key.contains('/')))
+                {
+                    rendered.push(format!("[{} output omitted]", mime));
+                }
+            }
+            "error" | "pyerr" => {
+                let ename = output.get("ename").and_then(Value::as_str).unwrap_or("");
+                let evalue = output.get("evalue").and_then(Value::as_str).unwrap_or("");
+                rendered.push(format!("{}: {}", ename, strip_ansi(evalue)));
+            }
+            _ => {}
+        }
+    }
+    // Truncating each output separately keeps errors that follow long printed output
+    rendered
+        .iter()
+        .map(|output| truncate(output.trim_end()))
+        .filter(|output| !output.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Notebook text fields are either a string or a list of lines
+fn join_lines(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_OUTPUT_CHARS) {
+        Some((index, _)) => format!(
+            "{}\n... [{} more characters of output truncated]",
+            &text[..index],
+            text[index..].chars().count()
+        ),
+        None => text.to_string(),
+    }
+}
+
+/// Remove terminal colour codes that IPython adds to error messages
+fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip to the final byte of the escape sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_cells_and_outputs_in_order() {
+        let notebook = r##"{
+            "nbformat": 4,
+            "cells": [
+                {"cell_type": "markdown", "source": ["# Task 1\n", "Sum the list"]},
+                {"cell_type": "code", "execution_count": 3, "source": "print(sum([1, 2]))",
+                 "outputs": [
+                    {"output_type": "stream", "name": "stdout", "text": ["3\n"]},
+                    {"output_type": "execute_result", "data": {"text/plain": ["'done'"]}},
+                    {"output_type": "display_data", "data": {"image/png": "iVBOR"}}
+                 ]},
+                {"cell_type": "code", "execution_count": null, "source": "1/0",
+                 "outputs": [{"output_type": "error", "ename": "ZeroDivisionError",
+                              "evalue": "\u001b[0;31mdivision by zero\u001b[0m"}]}
+            ]
+        }"##;
+        let text = extract_notebook_text(notebook.as_bytes(), "work.ipynb").unwrap();
+        assert_eq!(
+            text,
+            "# --- cell 1 [markdown] ---\n# Task 1\nSum the list\n\n\
+             # --- cell 2 [code] (execution 3) ---\nprint(sum([1, 2]))\n\
+             # --- output ---\n3\n'done'\n[image/png output omitted]\n\n\
+             # --- cell 3 [code] ---\n1/0\n\
+             # --- output ---\nZeroDivisionError: division by zero\n\n"
+        );
+    }
+
+    #[test]
+    fn reads_nbformat_3_worksheets() {
+        let notebook = r#"{
+            "nbformat": 3,
+            "worksheets": [{"cells": [
+                {"cell_type": "code", "prompt_number": 1, "input": ["x = 2\n", "x"],
+                 "outputs": [{"output_type": "pyout", "text": ["2"]}]}
+            ]}]
+        }"#;
+        let text = extract_notebook_text(notebook.as_bytes(), "old.ipynb").unwrap();
+        assert!(text.contains("# --- cell 1 [code] (execution 1) ---\nx = 2\nx\n"));
+        assert!(text.contains("# --- output ---\n2\n"));
+    }
+
+    #[test]
+    fn truncates_long_outputs() {
+        let long = "y".repeat(MAX_OUTPUT_CHARS + 10);
+        let rendered = render_outputs(&[serde_json::json!({
+            "output_type": "stream",
+            "text": long,
+        })]);
+        assert!(rendered.ends_with("... [10 more characters of output truncated]"));
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_notebooks() {
+        assert!(extract_notebook_text(b"not json", "a.ipynb").is_err());
+        assert!(extract_notebook_text(b"{\"metadata\": {}}", "a.ipynb").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use serde::Deserialize;
+
+/// How student identity is encoded in the paths of a submissions folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// One folder (or archive) per student, named after the student
+    #[default]
+    FolderPerStudent,
+    /// Every top-level file is a separate submission named after the file
+    Flat,
+    /// Moodle bulk download: `Full Name_12345_assignsubmission_file_/...`, or flat
+    /// `Full Name_12345_assignsubmission_file_report.pdf` files
+    Moodle,
+    /// Canvas bulk download: flat `lastfirst_12345_67890_filename.py` files,
+    /// with `_LATE_` after the name for late submissions
+    Canvas,
+    /// Google Classroom (Drive) download: `Full Name - filename.docx` files,
+    /// or one folder per student
+    Classroom,
+}
+
+/// Where a file belongs once the layout has been applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// Groups the files of one submission: the LMS id when known, the name otherwise
+    pub key: String,
+    pub student_name: String,
+    /// Identifier assigned by the LMS
+    pub student_id: Option<String>,
+    /// Path reported for the file, `student_name/...` unless the layout keeps paths as found
+    pub path: String,
+}
+
+impl Layout {
+    /// Work out the student a file belongs to from its path relative to the input folder
+    pub fn place(&self, rel_path: &str) -> Placement {
+        let (first, rest) = match rel_path.split_once('/') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (rel_path, None),
+        };
+
+        let identity = match self {
+            Layout::FolderPerStudent => None,
+            Layout::Flat => {
+                let name = match rest {
+                    Some(_) => first,
+                    None => first.rsplit_once('.').map_or(first, |(stem, _)| stem),
+                };
+                return Placement {
+                    key: name.to_string(),
+                    student_name: name.to_string(),
+                    student_id: None,
+                    path: rel_path.to_string(),
+                };
+            }
+            Layout::Moodle => parse_moodle(first),
+            Layout::Canvas => parse_canvas(first),
+            Layout::Classroom => match rest {
+                Some(_) => None,
+                None => first
+                    .split_once(" - ")
+                    .map(|(name, file)| (name.trim().to_string(), None, file.to_string())),
+            },
+        };
+
+        match identity {
+            Some((student_name, student_id, file)) => {
+                // Inside a folder the folder name only carries the identity
+                let inner = match rest {
+                    Some(rest) => rest,
+                    None if file.is_empty() => first,
+                    None => file.as_str(),
+                };
+                Placement {
+                    key: student_id.clone().unwrap_or_else(|| student_name.clone()),
+                    path: format!("{}/{}", student_name, inner),
+                    student_name,
+                    student_id,
+                }
+            }
+            // Paths that do not follow the layout are grouped by their top-level folder
+            None => Placement {
+                key: first.to_string(),
+                student_name: first.to_string(),
+                student_id: None,
+                path: rel_path.to_string(),
+            },
+        }
+    }
+}
+
+impl Placement {
+    /// Path of the file inside the student's folder
+    pub fn inner_path(&self) -> &str {
+        self.path
+            .strip_prefix(self.student_name.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(&self.path)
+    }
+}
+
+/// `Full Name_12345_assignsubmission_file_[filename]` into name, id and filename
+fn parse_moodle(component: &str) -> Option<(String, Option<String>, String)> {
+    let (identity, plugin) = component.split_once("_assignsubmission_")?;
+    let (name, id) = match identity.rsplit_once('_') {
+        Some((name, id)) if is_id(id) => (name, Some(id.to_string())),
+        _ => (identity, None),
+    };
+    // The plugin name (`file`, `onlinetext`) comes first, then the original filename
+    let file = plugin.split_once('_').map_or("", |(_, file)| file);
+    Some((name.trim().to_string(), id, file.to_string()))
+}
+
+/// `lastfirst[_LATE]_12345[_67890]_filename` into name, user id and filename
+fn parse_canvas(component: &str) -> Option<(String, Option<String>, String)> {
+    let mut parts = component.split('_').peekable();
+    let name = parts.next().filter(|name| !name.is_empty())?;
+    if parts
+        .peek()
+        .is_some_and(|part| part.eq_ignore_ascii_case("late"))
+    {
+        parts.next();
+    }
+    let user_id = parts.next().filter(|id| is_id(id))?;
+    // The submission id is absent in some exports
+    if parts.peek().is_some_and(|part| is_id(part)) {
+        parts.next();
+    }
+    let file = parts.collect::<Vec<_>>().join("_");
+    Some((name.to_string(), Some(user_id.to_string()), file))
+}
+
+fn is_id(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(key: &str, name: &str, id: Option<&str>, path: &str) -> Placement {
+        Placement {
+            key: key.to_string(),
+            student_name: name.to_string(),
+            student_id: id.map(str::to_string),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn places_folder_per_student_and_flat_files() {
+        assert_eq!(
+            Layout::FolderPerStudent.place("Ada Lovelace/src/main.py"),
+            placement(
+                "Ada Lovelace",
+                "Ada Lovelace",
+                None,
+                "Ada Lovelace/src/main.py"
+            )
+        );
+        assert_eq!(
+            Layout::Flat.place("ada_lovelace.py"),
+            placement("ada_lovelace", "ada_lovelace", None, "ada_lovelace.py")
+        );
+        assert_eq!(
+            Layout::Flat.place("ada/main.py"),
+            placement("ada", "ada", None, "ada/main.py")
+        );
+    }
+
+    #[test]
+    fn parses_moodle_folders_and_files() {
+        assert_eq!(
+            Layout::Moodle.place("Ada Lovelace_12345_assignsubmission_file_/report.pdf"),
+            placement(
+                "12345",
+                "Ada Lovelace",
+                Some("12345"),
+                "Ada Lovelace/report.pdf"
+            )
+        );
+        assert_eq!(
+            Layout::Moodle.place("Ada Lovelace_12345_assignsubmission_file_my_report.pdf"),
+            placement(
+                "12345",
+                "Ada Lovelace",
+                Some("12345"),
+                "Ada Lovelace/my_report.pdf"
+            )
+        );
+        // Exports without participant ids group by name
+        assert_eq!(
+            Layout::Moodle.place("Ada Lovelace_assignsubmission_onlinetext_"),
+            placement(
+                "Ada Lovelace",
+                "Ada Lovelace",
+                None,
+                "Ada Lovelace/Ada Lovelace_assignsubmission_onlinetext_"
+            )
+        );
+    }
+
+    #[test]
+    fn parses_canvas_files() {
+        assert_eq!(
+            Layout::Canvas.place("lovelaceada_12345_67890_main_v2.py"),
+            placement(
+                "12345",
+                "lovelaceada",
+                Some("12345"),
+                "lovelaceada/main_v2.py"
+            )
+        );
+        assert_eq!(
+            Layout::Canvas.place("lovelaceada_LATE_12345_67890_main.py"),
+            placement("12345", "lovelaceada", Some("12345"), "lovelaceada/main.py")
+        );
+        assert_eq!(
+            Layout::Canvas.place("lovelaceada_12345_main.py"),
+            placement("12345", "lovelaceada", Some("12345"), "lovelaceada/main.py")
+        );
+    }
+
+    #[test]
+    fn parses_classroom_files_and_folders() {
+        assert_eq!(
+            Layout::Classroom.place("Ada Lovelace - Essay draft.docx"),
+            placement(
+                "Ada Lovelace",
+                "Ada Lovelace",
+                None,
+                "Ada Lovelace/Essay draft.docx"
+            )
+        );
+        assert_eq!(
+            Layout::Classroom.place("Ada Lovelace/Essay - final.docx"),
+            placement(
+                "Ada Lovelace",
+                "Ada Lovelace",
+                None,
+                "Ada Lovelace/Essay - final.docx"
+            )
+        );
+    }
+
+    #[test]
+    fn groups_unrecognised_paths_by_their_top_level_entry() {
+        assert_eq!(
+            Layout::Canvas.place("README.txt"),
+            placement("README.txt", "README.txt", None, "README.txt")
+        );
+        assert_eq!(
+            Layout::Moodle.place("extras/notes.txt"),
+            placement("extras", "extras", None, "extras/notes.txt")
+        );
+    }
+
+    #[test]
+    fn strips_the_student_folder_from_inner_paths() {
+        let placed = Layout::Moodle.place("Ada_1_assignsubmission_file_/src/main.py");
+        assert_eq!(placed.inner_path(), "src/main.py");
+        assert_eq!(Layout::Flat.place("ada.py").inner_path(), "ada.py");
+    }
+}
@@ -1,19 +1,73 @@
+mod archive_processor;
+mod doc_processor;
 mod docx_processor;
+mod ignore_rules;
+mod ipynb_processor;
+mod layout;
 mod pdf_processor;
+mod rtf_processor;
 mod supported_formats;
 mod text_processor;
 
-use crate::models::{FileContent, StudentSubmission};
-use anyhow::Result;
-use std::collections::HashMap;
+use crate::models::{FileContent, FileSelection, StudentSubmission};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 use walkdir::WalkDir;
 
+use archive_processor::ArchiveKind;
+use ignore_rules::{FileFilter, Gitignores};
+pub use layout::{Layout, Placement};
 pub use supported_formats::SupportedFormats;
 
+/// Files larger than this are not read, matching the limit for files inside archives
+const MAX_FILE_BYTES: u64 = 50 * 1024 * 1024;
+
 /// Main file processor that handles reading different file formats
-pub struct FileProcessor;
+pub struct FileProcessor {
+    layout: Layout,
+    filter: FileFilter,
+}
+
+/// Files and processing notes collected for one student
+#[derive(Default)]
+struct StudentFiles {
+    name: String,
+    id: Option<String>,
+    files: Vec<FileContent>,
+    notes: Vec<String>,
+}
+
+impl StudentFiles {
+    /// Files of the student a placement points to
+    fn of<'a>(
+        students: &'a mut BTreeMap<String, StudentFiles>,
+        placement: &Placement,
+    ) -> &'a mut StudentFiles {
+        students
+            .entry(placement.key.clone())
+            .or_insert_with(|| StudentFiles {
+                name: placement.student_name.clone(),
+                id: placement.student_id.clone(),
+                ..Default::default()
+            })
+    }
+
+    /// Add a file, noting how it was read and when its text is incomplete
+    fn add(&mut self, file: FileContent) {
+        for note in &file.notes {
+            self.notes.push(format!("{}: {}", file.filename, note));
+        }
+        if let Some(reason) = &file.review {
+            self.notes.push(format!(
+                "{} needs OCR/manual review: {}",
+                file.filename, reason
+            ));
+        }
+        self.files.push(file);
+    }
+}
 
 impl Default for FileProcessor {
     fn default() -> Self {
@@ -23,45 +77,113 @@ impl Default for FileProcessor {
 
 impl FileProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            layout: Layout::default(),
+            filter: FileFilter::default(),
+        }
     }
 
-    /// Process directory and group files by student folder
+    /// Use a different scheme for identifying students from file paths
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Only grade files matching the assignment's include/exclude globs
+    pub fn with_file_selection(mut self, selection: &FileSelection) -> Result<Self> {
+        self.filter = FileFilter::new(selection)?;
+        Ok(self)
+    }
+
+    /// Process directory and group files by student according to the layout
     pub fn process_directory(&self, dir_path: &str) -> Result<Vec<StudentSubmission>> {
-        let mut student_files: HashMap<String, Vec<FileContent>> = HashMap::new();
+        let mut student_files: BTreeMap<String, StudentFiles> = BTreeMap::new();
         let root = PathBuf::from(dir_path);
 
-        for entry in WalkDir::new(&root)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-        {
+        let mut gitignores = Gitignores::default();
+        let mut ignored = 0;
+
+        // Sorted traversal keeps files, notes and submissions in the same order on every run
+        let mut walker = WalkDir::new(&root).sort_by_file_name().into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
             let path = entry.path();
+            let rel_path = relative_path(path, &root);
+
+            if entry.file_type().is_dir() {
+                let name = entry.file_name().to_string_lossy();
+                if entry.depth() > 0
+                    && (ignore_rules::is_ignored_dir(&name)
+                        || gitignores.is_ignored(&rel_path, true))
+                {
+                    debug!("Skipping ignored folder: {}", path.display());
+                    walker.skip_current_dir();
+                    continue;
+                }
+                // Rules apply to everything below the folder, which the walk visits next
+                if let Ok(content) = std::fs::read_to_string(path.join(".gitignore")) {
+                    let dir = if entry.depth() == 0 { "" } else { &rel_path };
+                    gitignores.add(dir, &content);
+                }
+                continue;
+            }
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if gitignores.is_ignored(&rel_path, false) {
+                debug!("Skipping ignored file: {}", path.display());
+                ignored += 1;
+                continue;
+            }
+
+            if let Some(kind) = ArchiveKind::from_path(path) {
+                ignored += self.read_archive(path, &root, kind, &gitignores, &mut student_files);
+                continue;
+            }
+
             if let Some(extension) = path.extension() {
                 let ext = extension.to_string_lossy().to_lowercase();
 
                 if SupportedFormats::is_supported(&ext) {
-                    match self.read_file(path, &root) {
+                    let placement = self.layout.place(&rel_path);
+                    if !self.filter.allows(placement.inner_path()) {
+                        debug!("Skipping excluded file: {}", path.display());
+                        ignored += 1;
+                        continue;
+                    }
+                    let student = StudentFiles::of(&mut student_files, &placement);
+                    match self.read_file(path, placement.path) {
                         Ok(content) => {
                             info!("Successfully read file: {}", path.display());
-
-                            // Extract student name from the path
-                            let student_name = self.extract_student_name(path, &root);
-                            student_files.entry(student_name).or_default().push(content);
+                            student.add(content);
                         }
                         Err(e) => {
-                            warn!("Failed to read file {}: {}", path.display(), e);
+                            warn!("Failed to read file {}: {:#}", path.display(), e);
+                            student.notes.push(unreadable_note(path, &root, &e));
                         }
                     }
                 }
             }
         }
+        if ignored > 0 {
+            info!(
+                "Skipped {} files matched by ignore rules or the assignment's file globs",
+                ignored
+            );
+        }
 
         // Convert grouped files to StudentSubmission objects
         let mut submissions = Vec::new();
-        for (student_name, files) in student_files {
-            if !files.is_empty() {
-                let submission = StudentSubmission::new(student_name, files);
+        // Students whose files were all unreadable are kept so they show up in the report
+        for (key, mut student) in student_files {
+            if !student.files.is_empty() || !student.notes.is_empty() {
+                student.files.sort_by(|a, b| a.filename.cmp(&b.filename));
+                let mut submission =
+                    StudentSubmission::with_notes(student.name, student.files, student.notes);
+                submission.submission_id = StudentSubmission::stable_id(&key);
+                submission.student_id = student.id;
                 info!(
                     "Created submission for student '{}' with {} files",
                     submission.student_name,
@@ -71,51 +193,375 @@ impl FileProcessor {
             }
         }
 
+        submissions.sort_by_cached_key(StudentSubmission::report_order);
+
         info!("Found {} student submissions", submissions.len());
         Ok(submissions)
     }
 
-    /// Extract student name from file path
-    fn extract_student_name(&self, file_path: &Path, root: &Path) -> String {
-        if let Ok(rel_path) = file_path.strip_prefix(root) {
-            // Get the first component of the relative path as student name
-            if let Some(first_component) = rel_path.components().next() {
-                return first_component.as_os_str().to_string_lossy().to_string();
+    /// Expand an archive in memory and group its files as if they were on disk in place
+    /// of the archive (`alice.zip` -> `alice/...`, `alice/work.tar.gz` -> `alice/work/...`);
+    /// an archive given as the input itself holds one folder per student
+    fn read_archive(
+        &self,
+        path: &Path,
+        root: &Path,
+        kind: ArchiveKind,
+        gitignores: &Gitignores,
+        student_files: &mut BTreeMap<String, StudentFiles>,
+    ) -> usize {
+        let rel_path = relative_path(path, root);
+        let base = if path == root {
+            ""
+        } else {
+            archive_processor::strip_archive_suffix(&rel_path)
+        };
+
+        let entries = match archive_processor::read_archive(path, kind, |entry| {
+            !ignore_rules::has_ignored_dir(entry)
+                && (is_gitignore(entry)
+                    || SupportedFormats::is_supported(&extension_of(Path::new(entry))))
+        }) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Skipping archive {}: {:#}", path.display(), e);
+                // An archive given as the input itself belongs to no single student
+                if !base.is_empty() {
+                    let placement = self.layout.place(&rel_path);
+                    let note = unreadable_note(path, root, &e);
+                    StudentFiles::of(student_files, &placement).notes.push(note);
+                }
+                return 0;
+            }
+        };
+        info!(
+            "Extracted {} files from archive: {}",
+            entries.len(),
+            path.display()
+        );
+
+        // Students often zip their project folder, which would only add a level of nesting
+        let wrapper = match entries.first().and_then(|e| e.path.split_once('/')) {
+            Some((dir, _)) if !base.is_empty() => {
+                let prefix = format!("{}/", dir);
+                entries
+                    .iter()
+                    .all(|e| e.path.starts_with(&prefix))
+                    .then_some(prefix)
+            }
+            _ => None,
+        };
+
+        let virtual_path = |entry: &str| {
+            let inner = match &wrapper {
+                Some(prefix) => &entry[prefix.len()..],
+                None => entry,
+            };
+            if base.is_empty() {
+                inner.to_string()
+            } else {
+                format!("{}/{}", base, inner)
             }
+        };
+
+        // `.gitignore` files inside the archive apply as if it had been extracted in place
+        let mut gitignores = gitignores.clone();
+        for entry in entries.iter().filter(|e| is_gitignore(&e.path)) {
+            let filename = virtual_path(&entry.path);
+            let dir = filename.rsplit_once('/').map_or("", |(dir, _)| dir);
+            gitignores.add(dir, &String::from_utf8_lossy(&entry.bytes));
         }
 
-        // Fallback: use the root directory name
-        root.file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or("unknown")
-            .to_string()
+        let mut ignored = 0;
+        for entry in entries {
+            if is_gitignore(&entry.path) {
+                continue;
+            }
+            let filename = virtual_path(&entry.path);
+            let placement = self.layout.place(&filename);
+            if gitignores.is_ignored(&filename, false)
+                || !self.filter.allows(placement.inner_path())
+            {
+                debug!("Skipping ignored file {} in {}", filename, path.display());
+                ignored += 1;
+                continue;
+            }
+            let extension = extension_of(Path::new(&filename));
+            let student = StudentFiles::of(student_files, &placement);
+            match self.decode_file(placement.path, &extension, &entry.bytes) {
+                Ok(content) => student.add(content),
+                Err(e) => {
+                    warn!(
+                        "Failed to read file from archive {}: {:#}",
+                        path.display(),
+                        e
+                    );
+                    student
+                        .notes
+                        .push(format!("Could not read {}: {:#}", filename, e));
+                }
+            }
+        }
+        ignored
     }
 
-    fn read_file(&self, path: &Path, root: &Path) -> Result<FileContent> {
-        let rel_path = path.strip_prefix(root).unwrap_or(path);
-        let filename = rel_path.to_string_lossy().replace("\\", "/");
-        let extension = path
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("")
-            .to_lowercase();
+    fn read_file(&self, path: &Path, filename: String) -> Result<FileContent> {
+        let extension = extension_of(path);
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?
+            .len();
+        if size > MAX_FILE_BYTES {
+            anyhow::bail!(
+                "File is {} MB, larger than the {} MB limit",
+                size / 1024 / 1024,
+                MAX_FILE_BYTES / 1024 / 1024
+            );
+        }
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+        self.decode_file(filename, &extension, &bytes)
+    }
 
-        let content = match extension.as_str() {
+    /// Extract the text of a file from its raw contents
+    fn decode_file(&self, filename: String, extension: &str, bytes: &[u8]) -> Result<FileContent> {
+        if extension == "pdf" {
+            let pdf = pdf_processor::extract_pdf_text(bytes, &filename)?;
+            let review = match pdf.pages_without_text.as_slice() {
+                [] => None,
+                [page] => Some(format!("page {} has no extractable text", page)),
+                pages => Some(format!(
+                    "pages {} have no extractable text",
+                    pages
+                        .iter()
+                        .map(|page| page.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            };
+            if let Some(reason) = &review {
+                warn!("{}: {}", filename, reason);
+            }
+            return Ok(FileContent {
+                filename,
+                content: pdf.text,
+                file_type: extension.to_string(),
+                metadata: pdf.metadata,
+                review,
+                notes: Vec::new(),
+                truncated: false,
+            });
+        }
+
+        let mut notes = Vec::new();
+        let mut truncated = false;
+        let content = match extension {
             // Text-based files (programming languages, configs, docs)
             "rs" | "py" | "java" | "cpp" | "c" | "cs" | "js" | "ts" | "php" | "rb" | "go"
             | "swift" | "kt" | "html" | "css" | "jsx" | "tsx" | "vue" | "svelte" | "txt" | "md"
             | "json" | "xml" | "yaml" | "yml" | "toml" | "ini" | "cfg" | "conf" | "csv" | "sql"
-            | "sh" | "bat" | "ps1" => text_processor::read_text_file(path)?,
+            | "sh" | "bat" | "ps1" => {
+                let decoded = text_processor::decode_text(bytes, &filename)?;
+                for note in &decoded.notes {
+                    info!("{}: {}", filename, note);
+                }
+                notes = decoded.notes;
+                truncated = decoded.truncated;
+                decoded.text
+            }
             // Binary/document files
-            "pdf" => pdf_processor::read_pdf_file(path)?,
-            "docx" | "doc" | "rtf" => docx_processor::read_docx_file(path)?,
+            "ipynb" => ipynb_processor::extract_notebook_text(bytes, &filename)?,
+            // Word documents are often saved with the wrong extension, so trust the content
+            "docx" | "doc" | "rtf" => match DocumentKind::sniff(bytes) {
+                Some(DocumentKind::Docx) => docx_processor::extract_docx_text(bytes, &filename)?,
+                Some(DocumentKind::Doc) => doc_processor::extract_doc_text(bytes, &filename)?,
+                Some(DocumentKind::Rtf) => rtf_processor::extract_rtf_text(bytes, &filename)?,
+                None => anyhow::bail!("Unrecognised {} document: {}", extension, filename),
+            },
             _ => anyhow::bail!("Unsupported file format: {}", extension),
         };
 
         Ok(FileContent {
             filename,
             content,
-            file_type: extension,
+            file_type: extension.to_string(),
+            metadata: BTreeMap::new(),
+            review: None,
+            notes,
+            truncated,
         })
     }
 }
+
+/// Word processor formats, identified by their leading bytes
+enum DocumentKind {
+    Docx,
+    Doc,
+    Rtf,
+}
+
+impl DocumentKind {
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"PK\x03\x04") {
+            Some(DocumentKind::Docx)
+        } else if bytes.starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]) {
+            Some(DocumentKind::Doc)
+        } else if bytes.starts_with(b"{\\rtf") {
+            Some(DocumentKind::Rtf)
+        } else {
+            None
+        }
+    }
+}
+
+/// Processing note recorded for a file that could not be read
+fn unreadable_note(path: &Path, root: &Path, error: &anyhow::Error) -> String {
+    format!("Could not read {}: {:#}", relative_path(path, root), error)
+}
+
+/// Path below the input folder with `/` separators, or the file name when the input is the file
+fn relative_path(path: &Path, root: &Path) -> String {
+    let rel_path = match path.strip_prefix(root) {
+        Ok(rel_path) if !rel_path.as_os_str().is_empty() => rel_path,
+        _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+    };
+    rel_path.to_string_lossy().replace("\\", "/")
+}
+
+/// Whether an archive entry is a `.gitignore` file
+fn is_gitignore(path: &str) -> bool {
+    path.rsplit('/').next() == Some(".gitignore")
+}
+
+fn extension_of(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::GradingResult;
+    use std::io::Write;
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn filenames(submission: &StudentSubmission) -> Vec<&str> {
+        submission
+            .files
+            .iter()
+            .map(|file| file.filename.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn archive_given_as_input_holds_one_folder_per_student() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subs.zip");
+        write_zip(
+            &path,
+            &[
+                ("alice/main.py", "print('alice')"),
+                ("bob/main.py", "print('bob')"),
+            ],
+        );
+
+        let submissions = FileProcessor::new()
+            .process_directory(path.to_str().unwrap())
+            .unwrap();
+        let names: Vec<_> = submissions
+            .iter()
+            .map(|s| s.student_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+        assert_eq!(filenames(&submissions[0]), vec!["alice/main.py"]);
+        assert_eq!(filenames(&submissions[1]), vec!["bob/main.py"]);
+    }
+
+    #[test]
+    fn submissions_come_in_report_order_with_stable_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["bob", "Carol", "alice2", "Alice"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join("main.py"), "print(1)").unwrap();
+        }
+
+        let submissions = FileProcessor::new()
+            .process_directory(dir.path().to_str().unwrap())
+            .unwrap();
+        let rows: Vec<_> = submissions
+            .iter()
+            .map(|s| (s.student_name.as_str(), s.submission_id.as_str()))
+            .collect();
+        assert_eq!(rows[0], ("Alice", "3bc51062973c458d"));
+        assert_eq!(rows[1].0, "alice2");
+        assert_eq!(rows[2], ("bob", "81b637d8fcd2c6da"));
+        assert_eq!(rows[3].0, "Carol");
+
+        // Results reconciled with a roster keep the same order
+        let mut results: Vec<GradingResult> = submissions
+            .iter()
+            .map(|s| {
+                let mut result = GradingResult::error(s.get_main_filename(), "x", None);
+                result.submission_id = s.submission_id.clone();
+                result
+            })
+            .collect();
+        let expected: Vec<String> = results.iter().map(|r| r.filename.clone()).collect();
+        crate::roster::Roster::default().reconcile(&mut results);
+        let reconciled: Vec<String> = results.iter().map(|r| r.filename.clone()).collect();
+        assert_eq!(reconciled, expected);
+    }
+
+    #[test]
+    fn binary_text_file_is_reported_not_graded() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("alice")).unwrap();
+        std::fs::write(dir.path().join("alice/main.py"), "print(1)").unwrap();
+        std::fs::write(dir.path().join("alice/notes.txt"), b"\x7FELF\x02\x01\x01").unwrap();
+
+        let submissions = FileProcessor::new()
+            .process_directory(dir.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(filenames(&submissions[0]), vec!["alice/main.py"]);
+        assert_eq!(
+            submissions[0].notes,
+            vec![
+                "Could not read alice/notes.txt: Not a text file, the content of \
+                 alice/notes.txt is an executable"
+            ]
+        );
+    }
+
+    #[test]
+    fn student_archive_is_unwrapped_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        write_zip(
+            &dir.path().join("alice.zip"),
+            &[
+                ("project/main.py", "print(1)"),
+                ("project/util.py", "x = 1"),
+            ],
+        );
+
+        let submissions = FileProcessor::new()
+            .process_directory(dir.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(submissions.len(), 1);
+        assert_eq!(submissions[0].student_name, "alice");
+        assert_eq!(
+            filenames(&submissions[0]),
+            vec!["alice/main.py", "alice/util.py"]
+        );
+    }
+}
@@ -1,13 +1,233 @@
 use anyhow::{Context, Result};
-use std::path::Path;
+use pdf_extract::{Document, Object, PlainTextOutput};
+use std::collections::BTreeMap;
 
-/// Read and extract text from PDF files
-pub fn read_pdf_file(path: &Path) -> Result<String> {
-    let bytes = std::fs::read(path)
-        .with_context(|| format!("Failed to read PDF file: {}", path.display()))?;
+/// Document information entries exposed as metadata, with the key they are reported under
+const INFO_FIELDS: &[(&[u8], &str)] = &[
+    (b"Title", "title"),
+    (b"Author", "author"),
+    (b"Creator", "creator"),
+    (b"Producer", "producer"),
+    (b"CreationDate", "created"),
+    (b"ModDate", "modified"),
+];
 
-    let text = pdf_extract::extract_text_from_mem(&bytes)
-        .with_context(|| format!("Failed to extract text from PDF: {}", path.display()))?;
+/// Text and document information extracted from a PDF
+pub struct PdfText {
+    /// Text of every page, each preceded by a `--- page N ---` marker
+    pub text: String,
+    pub metadata: BTreeMap<String, String>,
+    /// Pages without a text layer (usually scanned images) or whose text could not be extracted
+    pub pages_without_text: Vec<u32>,
+}
+
+/// Extract the text of a PDF page by page, along with its document information
+pub fn extract_pdf_text(bytes: &[u8], name: &str) -> Result<PdfText> {
+    let mut document = Document::load_mem(bytes)
+        .with_context(|| format!("Failed to extract text from PDF: {}", name))?;
+    if document.is_encrypted() {
+        // Many PDFs are encrypted with an empty user password only to restrict editing
+        document
+            .decrypt("")
+            .with_context(|| format!("PDF is password protected: {}", name))?;
+    }
+
+    let mut text = String::new();
+    let mut pages_without_text = Vec::new();
+    for page in document.get_pages().into_keys() {
+        text.push_str(&format!("--- page {} ---\n", page));
+
+        let mut page_text = String::new();
+        let extracted = pdf_extract::output_doc_page(
+            &document,
+            &mut PlainTextOutput::new(&mut page_text),
+            page,
+        );
+        match extracted {
+            Ok(()) if !page_text.trim().is_empty() => {
+                text.push_str(page_text.trim_matches('\n'));
+            }
+            Ok(()) => {
+                pages_without_text.push(page);
+                text.push_str("[no text layer, the page may be a scanned image]");
+            }
+            Err(e) => {
+                pages_without_text.push(page);
+                text.push_str(&format!("[text could not be extracted: {}]", e));
+            }
+        }
+        text.push_str("\n\n");
+    }
+
+    Ok(PdfText {
+        text,
+        metadata: read_metadata(&document),
+        pages_without_text,
+    })
+}
+
+/// Read the document information dictionary referenced from the trailer
+fn read_metadata(document: &Document) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::new();
+    let Ok(info) = document
+        .trailer
+        .get(b"Info")
+        .and_then(|info| document.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+    else {
+        return metadata;
+    };
+
+    for (key, label) in INFO_FIELDS {
+        let value = match info.get(key).and_then(|value| document.dereference(value)) {
+            Ok((_, Object::String(bytes, _))) => decode_text_string(bytes),
+            _ => continue,
+        };
+        let value = if key.ends_with(b"Date") {
+            format_date(&value)
+        } else {
+            value.trim().to_string()
+        };
+        if !value.is_empty() {
+            metadata.insert(label.to_string(), value);
+        }
+    }
+    metadata
+}
+
+/// PDF text strings are UTF-16BE with a byte order mark, or PDFDocEncoding (close to Latin-1)
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Turn `D:YYYYMMDDHHmmSS+HH'mm'` into `YYYY-MM-DD HH:mm:SS +HH:mm`, keeping
+/// anything unrecognised as written
+fn format_date(value: &str) -> String {
+    let raw = value.trim();
+    let date = raw.strip_prefix("D:").unwrap_or(raw);
+    let digits = date.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 8 {
+        return raw.to_string();
+    }
+
+    let part = |range: std::ops::Range<usize>| date.get(range).filter(|p| p.len() == 2);
+    let mut formatted = format!("{}-{}-{}", &date[0..4], &date[4..6], &date[6..8]);
+    if let (Some(hour), Some(minute)) = (part(8..10), part(10..12)) {
+        if digits >= 12 {
+            formatted.push_str(&format!(" {}:{}", hour, minute));
+            if let Some(second) = part(12..14).filter(|_| digits >= 14) {
+                formatted.push_str(&format!(":{}", second));
+            }
+        }
+    }
+
+    let zone = &date[digits..];
+    if zone.starts_with('Z') {
+        formatted.push_str(" UTC");
+    } else if let Some(sign) = zone.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let offset: Vec<&str> = zone[1..].split('\'').filter(|p| !p.is_empty()).collect();
+        match offset.as_slice() {
+            [hours, minutes, ..] => formatted.push_str(&format!(" {}{}:{}", sign, hours, minutes)),
+            [hours] => formatted.push_str(&format!(" {}{}:00", sign, hours)),
+            [] => {}
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two-page PDF: the first page draws `text`, the second draws nothing
+    fn pdf(text: &str, info: &str) -> Vec<u8> {
+        let content = format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET", text);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 5 0 R /Resources << /Font << /F1 6 0 R >> >> >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 7 0 R >>".to_string(),
+            format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_string(),
+            "<< /Length 0 >>\nstream\n\nendstream".to_string(),
+            info.to_string(),
+        ];
+
+        let mut bytes = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(bytes.len());
+            bytes.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+        let xref = bytes.len();
+        bytes.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            bytes.extend(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        bytes.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                objects.len(),
+                xref
+            )
+            .as_bytes(),
+        );
+        bytes
+    }
+
+    #[test]
+    fn extracts_pages_and_flags_the_ones_without_text() {
+        let bytes = pdf(
+            "Hello from page one",
+            "<< /Author <FEFF0041006C00690063006500200053006D0069007400680020263A> /CreationDate (D:20240315103000+01'00') /Producer ( ) >>",
+        );
+        let extracted = extract_pdf_text(&bytes, "report.pdf").unwrap();
+
+        assert!(extracted.text.starts_with("--- page 1 ---\n"));
+        assert!(extracted.text.contains("Hello from page one"));
+        assert!(extracted
+            .text
+            .contains("--- page 2 ---\n[no text layer, the page may be a scanned image]\n\n"));
+        assert_eq!(extracted.pages_without_text, vec![2]);
+        assert_eq!(extracted.metadata["author"], "Alice Smith \u{263a}");
+        assert_eq!(extracted.metadata["created"], "2024-03-15 10:30:00 +01:00");
+        assert!(!extracted.metadata.contains_key("producer"));
+    }
+
+    #[test]
+    fn formats_pdf_dates() {
+        assert_eq!(format_date("D:20240315"), "2024-03-15");
+        assert_eq!(format_date("D:202403151030Z"), "2024-03-15 10:30 UTC");
+        assert_eq!(
+            format_date("D:20240315103000-05'30'"),
+            "2024-03-15 10:30:00 -05:30"
+        );
+        assert_eq!(
+            format_date("D:20240315103000+02"),
+            "2024-03-15 10:30:00 +02:00"
+        );
+        assert_eq!(format_date("yesterday"), "yesterday");
+    }
+
+    #[test]
+    fn decodes_text_strings() {
+        assert_eq!(decode_text_string(b"caf\xe9"), "café");
+        assert_eq!(decode_text_string(&[0xFE, 0xFF, 0x03, 0xBB]), "λ");
+    }
 
-    Ok(text)
+    #[test]
+    fn rejects_files_that_are_not_pdfs() {
+        assert!(extract_pdf_text(b"not a pdf", "bad.pdf").is_err());
+    }
 }
@@ -0,0 +1,262 @@
+use anyhow::Result;
+
+/// Destinations whose content is formatting or metadata rather than document text
+const SKIPPED_DESTINATIONS: &[&str] = &[
+    "fonttbl",
+    "colortbl",
+    "stylesheet",
+    "listtable",
+    "listoverridetable",
+    "revtbl",
+    "rsidtbl",
+    "info",
+    "pict",
+    "object",
+    "themedata",
+    "colorschememapping",
+    "datastore",
+    "latentstyles",
+    "xmlnstbl",
+    "generator",
+    "header",
+    "headerl",
+    "headerr",
+    "headerf",
+    "footer",
+    "footerl",
+    "footerr",
+    "footerf",
+    "fldinst",
+];
+
+/// Extract plain text from the contents of an RTF file
+pub fn extract_rtf_text(bytes: &[u8], name: &str) -> Result<String> {
+    if !bytes.starts_with(b"{\\rtf") {
+        anyhow::bail!("Not an RTF document: {}", name);
+    }
+    Ok(RtfParser::new(bytes).parse())
+}
+
+#[derive(Clone, Copy)]
+struct GroupState {
+    skip: bool,
+    /// Number of fallback characters following each `\u` escape
+    unicode_skip: usize,
+}
+
+struct RtfParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    text: String,
+    /// Pending UTF-16 high surrogate from a `\u` escape
+    high_surrogate: Option<u16>,
+}
+
+impl<'a> RtfParser<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            text: String::new(),
+            high_surrogate: None,
+        }
+    }
+
+    fn parse(mut self) -> String {
+        let mut stack: Vec<GroupState> = Vec::new();
+        let mut state = GroupState {
+            skip: false,
+            unicode_skip: 1,
+        };
+        // Fallback characters still to be dropped after a `\u` escape
+        let mut pending_skip = 0;
+
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'{' => {
+                    stack.push(state);
+                    // `{\*\dest ...}` marks a destination readers may ignore
+                    if self.bytes[self.pos..].starts_with(b"\\*") {
+                        state.skip = true;
+                    }
+                }
+                b'}' => {
+                    state = stack.pop().unwrap_or(state);
+                    pending_skip = 0;
+                }
+                b'\\' => {
+                    let Some(&next) = self.bytes.get(self.pos) else {
+                        break;
+                    };
+                    if next.is_ascii_alphabetic() {
+                        let (word, param) = self.control_word();
+                        match word.as_str() {
+                            "uc" => state.unicode_skip = param.unwrap_or(1).max(0) as usize,
+                            "u" if !state.skip => {
+                                let unit = param.unwrap_or(0) as i16 as u16;
+                                self.push_utf16(unit);
+                                pending_skip = state.unicode_skip;
+                            }
+                            "bin" => {
+                                // Raw binary data of the given length
+                                self.pos += param.unwrap_or(0).max(0) as usize;
+                            }
+                            word if SKIPPED_DESTINATIONS.contains(&word) => state.skip = true,
+                            _ if state.skip => {}
+                            "par" | "line" | "row" | "sect" | "page" => self.text.push('\n'),
+                            "tab" | "cell" => self.text.push('\t'),
+                            "emdash" => self.text.push('\u{2014}'),
+                            "endash" => self.text.push('\u{2013}'),
+                            "bullet" => self.text.push('\u{2022}'),
+                            "lquote" => self.text.push('\u{2018}'),
+                            "rquote" => self.text.push('\u{2019}'),
+                            "ldblquote" => self.text.push('\u{201c}'),
+                            "rdblquote" => self.text.push('\u{201d}'),
+                            _ => {}
+                        }
+                    } else {
+                        self.pos += 1;
+                        match next {
+                            b'\'' => {
+                                let value = self.hex_byte();
+                                if pending_skip > 0 {
+                                    pending_skip -= 1;
+                                } else if let (Some(value), false) = (value, state.skip) {
+                                    self.text.push(windows_1252(value));
+                                }
+                            }
+                            b'\\' | b'{' | b'}' if !state.skip => {
+                                if pending_skip > 0 {
+                                    pending_skip -= 1;
+                                } else {
+                                    self.text.push(next as char);
+                                }
+                            }
+                            b'~' if !state.skip => self.text.push('\u{a0}'),
+                            b'_' if !state.skip => self.text.push('-'),
+                            b'\n' | b'\r' if !state.skip => self.text.push('\n'),
+                            _ => {}
+                        }
+                    }
+                }
+                // Line breaks in the source are not significant
+                b'\r' | b'\n' => {}
+                _ => {
+                    if pending_skip > 0 {
+                        pending_skip -= 1;
+                    } else if !state.skip {
+                        self.text.push(windows_1252(byte));
+                    }
+                }
+            }
+        }
+
+        self.text
+    }
+
+    /// Read a control word and its optional numeric parameter, consuming the delimiting space
+    fn control_word(&mut self) -> (String, Option<i32>) {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_alphabetic())
+        {
+            self.pos += 1;
+        }
+        let word = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+
+        let param_start = self.pos;
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let param = std::str::from_utf8(&self.bytes[param_start..self.pos])
+            .ok()
+            .and_then(|p| p.parse().ok());
+
+        if self.bytes.get(self.pos) == Some(&b' ') {
+            self.pos += 1;
+        }
+        (word, param)
+    }
+
+    fn hex_byte(&mut self) -> Option<u8> {
+        let hex = self.bytes.get(self.pos..self.pos + 2)?;
+        self.pos += 2;
+        u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+    }
+
+    fn push_utf16(&mut self, unit: u16) {
+        match (self.high_surrogate.take(), unit) {
+            (None, 0xD800..=0xDBFF) => self.high_surrogate = Some(unit),
+            (Some(high), 0xDC00..=0xDFFF) => {
+                let code = 0x10000 + ((high as u32 - 0xD800) << 10) + (unit as u32 - 0xDC00);
+                self.text.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+            }
+            (_, unit) => self
+                .text
+                .push(char::from_u32(unit as u32).unwrap_or('\u{fffd}')),
+        }
+    }
+}
+
+/// Decode a byte in the Windows-1252 code page used by most RTF files
+pub(super) fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '\u{20ac}', '\u{fffd}', '\u{201a}', '\u{0192}', '\u{201e}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{02c6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{fffd}',
+        '\u{017d}', '\u{fffd}', '\u{fffd}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}',
+        '\u{2022}', '\u{2013}', '\u{2014}', '\u{02dc}', '\u{2122}', '\u{0161}', '\u{203a}',
+        '\u{0153}', '\u{fffd}', '\u{017e}', '\u{0178}',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(rtf: &str) -> String {
+        extract_rtf_text(rtf.as_bytes(), "test.rtf").unwrap()
+    }
+
+    #[test]
+    fn extracts_paragraphs_and_skips_tables_of_formatting() {
+        let rtf = r"{\rtf1\ansi{\fonttbl{\f0 Times New Roman;}}{\colortbl;\red0\green0\blue0;}
+{\info{\author Alice}}\f0\fs24 First paragraph.\par
+Second\tab line with \{braces\}.\par}";
+        assert_eq!(text(rtf), "First paragraph.\nSecond\tline with {braces}.\n");
+    }
+
+    #[test]
+    fn decodes_escapes_and_unicode() {
+        assert_eq!(text(r"{\rtf1 caf\'e9 \'93q\'94}"), "café \u{201c}q\u{201d}");
+        assert_eq!(
+            text(r"{\rtf1\uc1 \u8364?5 and \u-10179?\u-8704?}"),
+            "€5 and 😀"
+        );
+        assert_eq!(text(r"{\rtf1\uc2 \u955 xyz}"), "λz");
+        assert_eq!(
+            text(r"{\rtf1 a\emdash b\~c \ldblquote d\rdblquote}"),
+            "a\u{2014}b\u{a0}c \u{201c}d\u{201d}"
+        );
+    }
+
+    #[test]
+    fn skips_ignorable_destinations_and_field_instructions() {
+        let rtf = r#"{\rtf1 See {\*\generator Word;}{\field{\*\fldinst HYPERLINK "http://x"}{\fldrslt the site}} now.{\header Page 1}}"#;
+        assert_eq!(text(rtf), "See the site now.");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(extract_rtf_text(b"plain text", "a.rtf").is_err());
+    }
+}
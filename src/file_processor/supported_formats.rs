@@ -15,7 +15,8 @@ impl SupportedFormats {
             "txt", "md", "json", "xml", "yaml", "yml", "toml", "ini", "cfg", "conf",
             // Documentation
             "pdf", "docx", "doc", "rtf", // Data files
-            "csv", "sql", "sh", "bat", "ps1",
+            "csv", "sql", "sh", "bat", "ps1", // Notebooks
+            "ipynb",
         ]
         .iter()
         .copied()
@@ -30,7 +31,7 @@ impl SupportedFormats {
             "rs", "py", "java", "cpp", "c", "cs", "js", "ts", "php", "rb", "go", "swift", "kt",
             "html", "css", "jsx", "tsx", "vue", "svelte", "txt", "md", "json", "xml", "yaml",
             "yml", "toml", "ini", "cfg", "conf", "pdf", "docx", "doc", "rtf", "csv", "sql", "sh",
-            "bat", "ps1",
+            "bat", "ps1", "ipynb",
         ]
     }
 
@@ -45,6 +46,7 @@ impl SupportedFormats {
         );
         categories.insert("text", vec!["txt", "md"]);
         categories.insert("documents", vec!["pdf", "docx", "doc", "rtf"]);
+        categories.insert("notebooks", vec!["ipynb"]);
         categories.insert("data_files", vec!["csv", "sql", "sh", "bat", "ps1"]);
         categories.insert(
             "web_technologies",
@@ -1,8 +1,221 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+use anyhow::Result;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 
-/// Read text-based files (source code and plain text)
-pub fn read_text_file(path: &Path) -> Result<String> {
-    std::fs::read_to_string(path)
-        .with_context(|| format!("Failed to read text file: {}", path.display()))
+/// Text beyond this many bytes is cut, it would not fit into a prompt anyway
+const MAX_TEXT_BYTES: usize = 1024 * 1024;
+/// Number of leading bytes inspected when deciding whether content is text
+const SNIFF_BYTES: usize = 8192;
+/// Share of control characters above which content is treated as binary
+const MAX_CONTROL_RATIO: f32 = 0.05;
+
+/// Signatures of common binary formats that students rename to `.txt` and the like
+const BINARY_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "a PDF document"),
+    (b"PK\x03\x04", "a ZIP archive or Office document"),
+    (b"\x89PNG", "a PNG image"),
+    (b"\xFF\xD8\xFF", "a JPEG image"),
+    (b"GIF8", "a GIF image"),
+    (b"\x7FELF", "an executable"),
+    (b"\xD0\xCF\x11\xE0", "an Office 97-2003 document"),
+];
+
+/// Text decoded from a file, with notes on how it was read
+pub struct DecodedText {
+    pub text: String,
+    /// Decisions worth reporting, such as a non-UTF-8 encoding or truncation
+    pub notes: Vec<String>,
+    /// Whether `text` is only the start of the file
+    pub truncated: bool,
+}
+
+/// Decode text-based files (source code and plain text) in whatever encoding they were saved,
+/// rejecting content that is not text at all
+pub fn decode_text(bytes: &[u8], name: &str) -> Result<DecodedText> {
+    let mut notes = Vec::new();
+
+    let (text, encoding, had_errors) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_length)) => {
+            let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+            (text, encoding, had_errors)
+        }
+        None => {
+            let encoding = match utf16_without_bom(bytes) {
+                Some(encoding) => encoding,
+                None => {
+                    if let Some(kind) = binary_kind(bytes) {
+                        anyhow::bail!("Not a text file, the content of {} is {}", name, kind);
+                    }
+                    guess_encoding(bytes)
+                }
+            };
+            let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+            (text, encoding, had_errors)
+        }
+    };
+
+    if encoding != UTF_8 {
+        notes.push(format!("decoded as {}", encoding.name()));
+    }
+    if had_errors {
+        notes.push(format!(
+            "some bytes are not valid {} and were replaced",
+            encoding.name()
+        ));
+    }
+
+    let mut text = text.into_owned();
+    let truncated = text.len() > MAX_TEXT_BYTES;
+    if truncated {
+        let mut end = MAX_TEXT_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        notes.push(format!(
+            "cut to the first {} KB of text",
+            MAX_TEXT_BYTES / 1024
+        ));
+    }
+
+    Ok(DecodedText {
+        text,
+        notes,
+        truncated,
+    })
+}
+
+/// UTF-8 when the content is valid UTF-8, otherwise the legacy encoding it most likely uses
+/// (Windows-1252, Windows-1251, Shift_JIS, ...)
+fn guess_encoding(bytes: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+/// Notepad and PowerShell write UTF-16 with a BOM, but other tools omit it; ASCII-heavy
+/// UTF-16 text has a zero byte in every other position
+fn utf16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(SNIFF_BYTES) & !1];
+    if sample.len() < 4 {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let zeros_at = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 10 >= pairs * 7 && even * 10 <= pairs {
+        Some(UTF_16LE)
+    } else if even * 10 >= pairs * 7 && odd * 10 <= pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Describe content that is binary rather than text: a known file signature, NUL bytes or
+/// many control characters near the start
+fn binary_kind(bytes: &[u8]) -> Option<&'static str> {
+    if let Some((_, kind)) = BINARY_SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+    {
+        return Some(kind);
+    }
+
+    let sample = &bytes[..bytes.len().min(SNIFF_BYTES)];
+    if sample.contains(&0) {
+        return Some("binary data");
+    }
+    // Tabs, line breaks, form feeds and escape sequences appear in real text files
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B))
+        .count();
+    if !sample.is_empty() && control as f32 / sample.len() as f32 > MAX_CONTROL_RATIO {
+        return Some("binary data");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn reads_utf8_without_notes() {
+        let decoded = decode_text("print('héllo')\n".as_bytes(), "a.py").unwrap();
+        assert_eq!(decoded.text, "print('héllo')\n");
+        assert!(decoded.notes.is_empty());
+
+        let decoded = decode_text(b"\xEF\xBB\xBFx = 1\n", "a.py").unwrap();
+        assert_eq!(decoded.text, "x = 1\n");
+        assert!(decoded.notes.is_empty());
+    }
+
+    #[test]
+    fn guesses_legacy_encodings() {
+        let source = "# Réponse de l'étudiant : la fonction renvoie le résultat déjà calculé.\n\
+                      # Remarque : les données sont lues à partir du fichier « entrée.txt ».\n\
+                      print(\"Café crème, très élégant\")\n";
+        let (bytes, _, _) = WINDOWS_1252.encode(source);
+        let decoded = decode_text(&bytes, "answer.py").unwrap();
+        assert_eq!(decoded.text, source);
+        assert_eq!(decoded.notes, vec!["decoded as windows-1252"]);
+    }
+
+    #[test]
+    fn reads_utf16_with_and_without_a_bom() {
+        let source = "int main() {\n    return 0;\n}\n";
+
+        let mut with_bom = vec![0xFF, 0xFE];
+        with_bom.extend(utf16le(source));
+        let decoded = decode_text(&with_bom, "main.c").unwrap();
+        assert_eq!(decoded.text, source);
+        assert_eq!(decoded.notes, vec!["decoded as UTF-16LE"]);
+
+        let decoded = decode_text(&utf16le(source), "main.c").unwrap();
+        assert_eq!(decoded.text, source);
+        assert_eq!(decoded.notes, vec!["decoded as UTF-16LE"]);
+    }
+
+    #[test]
+    fn rejects_binary_content() {
+        let error = decode_text(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "plot.txt")
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Not a text file, the content of plot.txt is a PNG image"
+        );
+
+        let error = decode_text(b"ab\0cd\0\x01\x02", "data.txt").err().unwrap();
+        assert!(error.to_string().ends_with("is binary data"), "{}", error);
+
+        let controls = [b'a', 0x01, 0x02, 0x03].repeat(16);
+        assert!(decode_text(&controls, "noise.txt").is_err());
+    }
+
+    #[test]
+    fn truncates_long_text_at_a_character_boundary() {
+        let text = format!("a{}", "é".repeat(MAX_TEXT_BYTES / 2));
+        let decoded = decode_text(text.as_bytes(), "long.txt").unwrap();
+        assert!(decoded.truncated);
+        assert_eq!(decoded.text.len(), MAX_TEXT_BYTES - 1);
+        assert!(text.starts_with(&decoded.text));
+        assert_eq!(decoded.notes, vec!["cut to the first 1024 KB of text"]);
+    }
 }
@@ -1,198 +1,594 @@
-use crate::config::AppConfig;
-use crate::grader::mock_grading;
-use crate::models::{
-    GradingRequest, GradingResult, GradingStrategy, OllamaRequest, OllamaResponse, OpenAIMessage,
-    OpenAIRequest, OpenAIResponse,
+use crate::config::{AiConfig, AppConfig};
+use crate::grader::context_budget::{self, OverflowStrategy, TokenEstimator};
+use crate::grader::prompt_builder;
+use crate::grader::providers::{
+    self, Completion, CompletionRequest, FollowUp, ModelProvider, ProviderKind,
 };
-use anyhow::{Context, Result};
-use reqwest::Client;
+use crate::grader::rate_limiter::RateLimiter;
+use crate::grader::response_cache::{self, CacheMode, ResponseCache};
+use crate::grader::response_parser::{self, InvalidResponse};
+use crate::grader::retry::RetryPolicy;
+use crate::models::{GradingRequest, GradingResult, GradingStatus, Provenance, Repair, TokenUsage};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
 
-/// AI client for communicating with different model endpoints
+/// Follow-ups asking the model to correct an invalid grade, unless configured
+const DEFAULT_REPAIR_ATTEMPTS: u32 = 2;
+
+const SYSTEM_PROMPT: &str = "You are an expert programming instructor. Grade the student submission according to the provided criteria and return a JSON response with the specified fields.";
+
+/// Returned when the model's replies stayed invalid through every repair attempt; carries the
+/// provenance of the requests so that failed results record them too
+#[derive(Debug, thiserror::Error)]
+#[error("No valid grade after {repair_attempts} repair attempts")]
+pub struct InvalidGrade {
+    pub repair_attempts: u32,
+    pub provenance: Provenance,
+    #[source]
+    pub source: InvalidResponse,
+}
+
+/// AI client that renders grading prompts and sends them to the configured model provider
 pub struct AIClient {
-    client: Client,
-    model_endpoint: String,
-    is_openai: bool,
+    provider: Box<dyn ModelProvider>,
+    cache: Option<ResponseCache>,
+    retry: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    /// Hash of the system prompt and the grading prompt template
+    prompt_template: String,
     app_config: Option<AppConfig>,
 }
 
 impl AIClient {
-    pub fn new(model_endpoint: &str, app_config: Option<AppConfig>) -> Result<Self> {
-        let client = Client::new();
-        let is_openai = model_endpoint.contains("openai.com");
-
-        Ok(Self {
-            client,
-            model_endpoint: model_endpoint.to_string(),
-            is_openai,
-            app_config,
-        })
+    /// Create a client for the provider selected in `[ai]` (defaults to OpenAI)
+    pub fn new(app_config: Option<AppConfig>) -> Result<Self> {
+        let ai_config = app_config.as_ref().and_then(|cfg| cfg.ai.as_ref());
+        let kind = ai_config
+            .and_then(|cfg| cfg.provider)
+            .unwrap_or(ProviderKind::OpenAI);
+        let endpoint = ai_config.and_then(|cfg| cfg.endpoint.as_deref());
+        let model = ai_config.and_then(|cfg| cfg.model.as_deref());
+
+        let provider = providers::build_provider(kind, endpoint, model)?;
+        tracing::info!("Using {} provider with model {}", kind, provider.model());
+        Ok(Self::with_provider(provider, app_config))
     }
 
-    pub async fn grade_submission(
-        &self,
-        request: &GradingRequest,
-        strategy: &GradingStrategy,
-    ) -> Result<GradingResult> {
-        let response = if self.is_openai {
-            self.call_openai_api(request, strategy).await?
+    /// Create a client around an already constructed provider
+    pub fn with_provider(provider: Box<dyn ModelProvider>, app_config: Option<AppConfig>) -> Self {
+        let cache_config = app_config.as_ref().and_then(|cfg| cfg.cache.as_ref());
+        let cache = if cache_config.and_then(|cfg| cfg.enabled).unwrap_or(true) {
+            let dir = cache_config
+                .and_then(|cfg| cfg.dir.as_deref())
+                .unwrap_or(response_cache::DEFAULT_CACHE_DIR);
+            let mode = if cache_config.and_then(|cfg| cfg.refresh).unwrap_or(false) {
+                CacheMode::Refresh
+            } else {
+                CacheMode::ReadWrite
+            };
+            Some(ResponseCache::new(dir, mode))
         } else {
-            self.call_ollama_api(request, strategy).await?
+            None
+        };
+
+        let ai_config = app_config.as_ref().and_then(|cfg| cfg.ai.as_ref());
+        let retry = RetryPolicy::new(ai_config.and_then(|ai| ai.retry.as_ref()));
+        let rate_limiter = RateLimiter::new(
+            ai_config.and_then(|ai| ai.requests_per_minute),
+            ai_config.and_then(|ai| ai.tokens_per_minute),
+        );
+
+        let digest = Sha256::new()
+            .chain_update(SYSTEM_PROMPT.as_bytes())
+            .chain_update([0u8])
+            .chain_update(prompt_builder::template(app_config.as_ref()).as_bytes())
+            .finalize();
+        let prompt_template = format!("{:x}", digest)[..16].to_string();
+
+        Self {
+            provider,
+            cache,
+            retry,
+            rate_limiter,
+            prompt_template,
+            app_config,
+        }
+    }
+
+    /// Name of the model grading requests are sent to
+    pub fn model(&self) -> &str {
+        self.provider.model()
+    }
+
+    /// Provider, prompt template and temperature every grade from this client shares;
+    /// the details of the requests are added per grade
+    pub fn provenance(&self) -> Provenance {
+        Provenance {
+            provider: self.provider.kind().to_string(),
+            prompt_template: self.prompt_template.clone(),
+            temperature: self.temperature(),
+            ..Provenance::default()
+        }
+    }
+
+    /// Approximate token counter for the configured model
+    pub fn token_estimator(&self) -> TokenEstimator {
+        self.provider.token_estimator()
+    }
+
+    /// How submissions larger than the context are graded
+    pub fn overflow(&self) -> OverflowStrategy {
+        self.ai_config()
+            .and_then(|cfg| cfg.overflow)
+            .unwrap_or_default()
+    }
+
+    /// Tokens available for the submission content of `request`, after the system prompt, the
+    /// rest of the grading prompt and the reply; `None` when not even a small submission fits
+    pub fn content_budget(&self, request: &GradingRequest) -> Option<usize> {
+        let estimator = self.provider.token_estimator();
+        let empty = GradingRequest {
+            content: String::new(),
+            ..request.clone()
         };
+        let prompt = prompt_builder::build_grading_prompt(&empty, self.app_config.as_ref());
+        let overhead = estimator.estimate(SYSTEM_PROMPT)
+            + estimator.estimate(&prompt)
+            + self.max_tokens() as usize;
+        context_budget::content_budget(self.context_tokens(), overhead)
+    }
+
+    /// Tokens the model attends to, from `[ai] context_tokens` or the provider's default
+    fn context_tokens(&self) -> usize {
+        self.ai_config()
+            .and_then(|cfg| cfg.context_tokens)
+            .unwrap_or_else(|| self.provider.context_window())
+    }
 
-        Ok(response)
+    fn temperature(&self) -> f32 {
+        self.ai_config()
+            .and_then(|cfg| cfg.temperature)
+            .unwrap_or(0.3)
     }
 
-    async fn call_openai_api(
+    fn max_tokens(&self) -> u32 {
+        self.ai_config()
+            .and_then(|cfg| cfg.max_tokens)
+            .unwrap_or(1000)
+    }
+
+    fn ai_config(&self) -> Option<&AiConfig> {
+        self.app_config.as_ref().and_then(|cfg| cfg.ai.as_ref())
+    }
+
+    pub async fn grade_submission(&self, request: &GradingRequest) -> Result<GradingResult> {
+        let prompt = prompt_builder::build_grading_prompt(request, self.app_config.as_ref());
+        self.grade_prompt(prompt, request).await
+    }
+
+    /// Ask the model to combine the assessments of a submission graded in parts into one
+    /// grade; `request.content` lists the files of the whole submission. Assessments that do
+    /// not fit into one prompt together are combined in batches first
+    pub async fn merge_assessments(
         &self,
         request: &GradingRequest,
-        strategy: &GradingStrategy,
+        parts: Vec<GradingResult>,
     ) -> Result<GradingResult> {
-        let prompt =
-            crate::grader::prompt_builder::build_grading_prompt(request, self.app_config.as_ref());
-
-        // Get AI config values with defaults
-        let ai_config = self.app_config.as_ref().and_then(|cfg| cfg.ai.as_ref());
-        let temperature = ai_config.and_then(|cfg| cfg.temperature).unwrap_or(0.3);
-        let max_tokens = ai_config.and_then(|cfg| cfg.max_tokens).unwrap_or(1000);
-        let timeout_secs = ai_config.and_then(|cfg| cfg.timeout_secs).unwrap_or(300);
-
-        let openai_request = OpenAIRequest {
-            model: "gpt-3.5-turbo".to_string(),
-            messages: vec![
-                OpenAIMessage {
-                    role: "system".to_string(),
-                    content: "You are an expert programming instructor. Grade the student submission according to the provided criteria and return a JSON response with the specified fields.".to_string(),
-                },
-                OpenAIMessage {
-                    role: "user".to_string(),
-                    content: prompt,
-                },
-            ],
-            temperature,
-            max_tokens,
+        let estimator = self.provider.token_estimator();
+        let fits = |prompt: &str| {
+            let tokens = estimator.estimate(SYSTEM_PROMPT)
+                + estimator.estimate(prompt)
+                + self.max_tokens() as usize;
+            context_budget::content_budget(self.context_tokens(), tokens).is_some()
         };
 
-        let mut attempts = 0;
-        let max_retries = 3;
-        loop {
-            attempts += 1;
-            let response = self
-                .client
-                .post(&self.model_endpoint)
-                .header(
-                    "Authorization",
-                    format!(
-                        "Bearer {}",
-                        std::env::var("OPENAI_API_KEY").unwrap_or_default()
-                    ),
-                )
-                .header("Content-Type", "application/json")
-                .timeout(std::time::Duration::from_secs(timeout_secs))
-                .json(&openai_request)
-                .send()
-                .await
-                .with_context(|| "Failed to send request to OpenAI API")?;
-
-            if !response.status().is_success() {
-                let error_text = response.text().await?;
-                if error_text.contains("rate_limit_exceeded")
-                    || error_text.contains("Rate limit reached")
+        let mut level = parts;
+        while level.len() > 1 {
+            let mut merged = Vec::new();
+            let mut rest = level.as_slice();
+            while !rest.is_empty() {
+                let mut size = rest.len();
+                while size > 2 && !fits(&prompt_builder::build_merge_prompt(request, &rest[..size]))
                 {
-                    if attempts < max_retries {
-                        println!(
-                            "Rate limit exceeded, retrying in 20 seconds... (attempt {}/{})",
-                            attempts, max_retries
-                        );
-                        tokio::time::sleep(std::time::Duration::from_secs(20)).await;
-                        continue;
-                    } else {
-                        anyhow::bail!(
-                            "OpenAI API error after {} attempts: {}",
-                            attempts,
-                            error_text
-                        );
-                    }
+                    size -= 1;
+                }
+                if size == 1 {
+                    merged.push(rest[0].clone());
                 } else {
-                    anyhow::bail!("OpenAI API error: {}", error_text);
+                    let prompt = prompt_builder::build_merge_prompt(request, &rest[..size]);
+                    let mut result = self.grade_prompt(prompt, request).await?;
+                    // The merged grade accounts for the requests of the grades it combines
+                    if let Some(provenance) = result.provenance.as_mut() {
+                        for part in &rest[..size] {
+                            if let Some(part) = &part.provenance {
+                                provenance.absorb(part);
+                            }
+                        }
+                    }
+                    merged.push(result);
                 }
+                rest = &rest[size..];
             }
-
-            let openai_response: OpenAIResponse = response.json().await?;
-            let content = openai_response
-                .choices
-                .first()
-                .ok_or_else(|| anyhow::anyhow!("No response from OpenAI"))?
-                .message
-                .content
-                .clone();
-
-            return crate::grader::response_parser::parse_grading_response(
-                &content,
-                &request.filename,
-                strategy,
-            );
+            level = merged;
         }
+        level
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("No partial assessments to merge"))
     }
 
-    async fn call_ollama_api(
+    async fn grade_prompt(
         &self,
+        prompt: String,
         request: &GradingRequest,
-        strategy: &GradingStrategy,
     ) -> Result<GradingResult> {
-        let prompt =
-            crate::grader::prompt_builder::build_grading_prompt(request, self.app_config.as_ref());
-
         // Get AI config values with defaults
-        let ai_config = self.app_config.as_ref().and_then(|cfg| cfg.ai.as_ref());
+        let ai_config = self.ai_config();
         let timeout_secs = ai_config.and_then(|cfg| cfg.timeout_secs).unwrap_or(300);
+        let repair_attempts = ai_config
+            .and_then(|cfg| cfg.repair_attempts)
+            .unwrap_or(DEFAULT_REPAIR_ATTEMPTS);
 
-        let ollama_request = OllamaRequest {
-            model: "qwen2.5:0.5b".to_string(),
+        let mut completion = CompletionRequest {
+            system: SYSTEM_PROMPT.to_string(),
             prompt,
-            stream: false,
+            temperature: self.temperature(),
+            max_tokens: self.max_tokens(),
+            timeout: std::time::Duration::from_secs(timeout_secs),
+            context_tokens: self.context_tokens(),
+            rubric: request.rubric.clone(),
+            schema: ai_config
+                .and_then(|cfg| cfg.structured_output)
+                .unwrap_or(true)
+                .then(|| response_parser::response_schema(&request.rubric)),
+            follow_ups: Vec::new(),
         };
 
-        println!(
-            "Sending request to Ollama: {}",
-            &format!("{}/api/generate", self.model_endpoint.trim_end_matches('/'))
-        );
+        let mut provenance = self.provenance();
+        // Every reply of an exchange that ends in a grade is cached, invalid ones included, so
+        // that reruns replay the repairs too; exchanges without a grade are asked afresh
+        let mut uncached = Vec::new();
+        loop {
+            let (response, from_cache, latency) =
+                self.complete(&completion, &request.filename).await?;
+            if !from_cache {
+                uncached.push((self.cache_key(&completion), response.clone()));
+            }
+            provenance.latency_ms += latency.as_millis() as u64;
+            provenance.requests += 1;
+            provenance.cached += u32::from(from_cache);
+            if let Some(usage) = response.usage {
+                *provenance.usage.get_or_insert_with(TokenUsage::default) += usage;
+            }
 
-        let response = self
-            .client
-            .post(format!(
-                "{}/api/generate",
-                self.model_endpoint.trim_end_matches('/')
-            ))
-            .header("Content-Type", "application/json")
-            .timeout(std::time::Duration::from_secs(timeout_secs))
-            .json(&ollama_request)
-            .send()
-            .await;
-
-        match response {
-            Ok(response) => {
-                println!("Ollama response status: {}", response.status());
-                if !response.status().is_success() {
-                    let error_text = response.text().await?;
-                    anyhow::bail!("Ollama API error: {}", error_text);
+            let mut invalid = match response_parser::parse_grading_response(
+                &response.text,
+                &request.filename,
+                &request.rubric,
+            ) {
+                Ok(mut result) => {
+                    self.store(completion.temperature, uncached);
+                    provenance.raw_response = Some(response.text);
+                    if self.provider.simulated() {
+                        result.status = GradingStatus::Mock;
+                    }
+                    result.model = Some(self.provider.model().to_string());
+                    result.provenance = Some(provenance);
+                    return Ok(result);
                 }
+                Err(invalid) => invalid,
+            };
 
-                let ollama_response: OllamaResponse = response.json().await?;
-                println!("Ollama response received successfully");
-                crate::grader::response_parser::parse_grading_response(
-                    &ollama_response.response,
-                    &request.filename,
-                    strategy,
-                )
+            let attempt = provenance.repairs.len() as u32 + 1;
+            if attempt > repair_attempts {
+                provenance.raw_response = Some(response.text);
+                // Scores out of range are the only problem: keep the grade, clamped, for review
+                if let Some(mut result) = invalid.clamped.take().map(|result| *result) {
+                    tracing::warn!(
+                        "Clamping out of range scores for {}: {}",
+                        request.filename,
+                        invalid.problems.join("; ")
+                    );
+                    self.store(completion.temperature, uncached);
+                    result.status = GradingStatus::NeedsReview;
+                    result.notes.push(format!(
+                        "Scores clamped to the allowed range: {}",
+                        invalid.problems.join("; ")
+                    ));
+                    result.model = Some(self.provider.model().to_string());
+                    result.provenance = Some(provenance);
+                    return Ok(result);
+                }
+                return Err(InvalidGrade {
+                    repair_attempts,
+                    provenance,
+                    source: invalid,
+                }
+                .into());
             }
-            Err(e) => {
-                // Fallback to mock response when Ollama is not available
-                println!(
-                    "Ollama API unavailable (error: {:?}), using mock response for {}",
-                    e, request.filename
-                );
-                Ok(mock_grading::generate_mock_result(&request.filename))
+            tracing::warn!(
+                "Reply for {} is not a valid grade, asking the model to correct it (repair {}/{}): {}",
+                request.filename,
+                attempt,
+                repair_attempts,
+                invalid.problems.join("; ")
+            );
+            completion.follow_ups.push(FollowUp {
+                reply: response.text.clone(),
+                message: prompt_builder::build_repair_prompt(request, &invalid.problems),
+            });
+            provenance.repairs.push(Repair {
+                problems: invalid.problems,
+                response: response.text,
+            });
+        }
+    }
+
+    /// Send a request, or answer it from the cache; returns the response, whether it came
+    /// from the cache and the time spent on it
+    async fn complete(
+        &self,
+        completion: &CompletionRequest,
+        filename: &str,
+    ) -> Result<(Completion, bool, Duration)> {
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(&self.cache_key(completion)));
+        if let Some(response) = cached {
+            tracing::info!("Using cached model response for {}", filename);
+            return Ok((response, true, Duration::ZERO));
+        }
+
+        // Replies are budgeted at their maximum length, the real size is not known up front
+        let estimator = self.provider.token_estimator();
+        let tokens = estimator.estimate(&completion.system)
+            + estimator.estimate(&completion.prompt)
+            + completion
+                .follow_ups
+                .iter()
+                .map(|follow_up| {
+                    estimator.estimate(&follow_up.reply) + estimator.estimate(&follow_up.message)
+                })
+                .sum::<usize>()
+            + completion.max_tokens as usize;
+        let started = Instant::now();
+        let response = self
+            .retry
+            .run(&format!("Request for {}", filename), || async {
+                if let Some(limiter) = &self.rate_limiter {
+                    limiter.acquire(tokens).await;
+                }
+                self.provider.complete(completion).await
+            })
+            .await?;
+        Ok((response, false, started.elapsed()))
+    }
+
+    fn cache_key(&self, completion: &CompletionRequest) -> String {
+        ResponseCache::key(self.provider.as_ref(), completion)
+    }
+
+    fn store(&self, temperature: f32, responses: Vec<(String, Completion)>) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        for (key, response) in responses {
+            if let Err(e) = cache.put(
+                &key,
+                self.provider.kind(),
+                self.provider.model(),
+                temperature,
+                &response,
+            ) {
+                tracing::warn!("Failed to cache model response: {}", e);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+    use crate::models::Criterion;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers with canned replies in order and records every request it receives
+    struct ScriptedProvider {
+        replies: Mutex<VecDeque<&'static str>>,
+        requests: Arc<Mutex<Vec<CompletionRequest>>>,
+    }
+
+    #[async_trait]
+    impl ModelProvider for ScriptedProvider {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::OpenAICompatible
+        }
+
+        fn model(&self) -> &str {
+            "scripted"
+        }
+
+        fn endpoint(&self) -> &str {
+            "http://localhost/v1/chat/completions"
+        }
+
+        fn token_estimator(&self) -> TokenEstimator {
+            TokenEstimator::new(4.0)
+        }
+
+        fn context_window(&self) -> usize {
+            8192
+        }
+
+        async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+            self.requests.lock().unwrap().push(request.clone());
+            let text = self
+                .replies
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("more requests than scripted replies");
+            Ok(Completion {
+                text: text.to_string(),
+                usage: None,
+            })
+        }
+    }
+
+    const MALFORMED: &str = "Great work overall, 9 out of 10!";
+    const OUT_OF_RANGE: &str = r#"{"scores": {"functionality": 12, "style": 4}, "comment": "ok"}"#;
+    const VALID: &str = r#"{"scores": {"functionality": 9, "style": 4}, "comment": "Well done"}"#;
+
+    /// A client without a response cache whose provider answers with `replies`
+    fn scripted_client(
+        replies: &[&'static str],
+        repair_attempts: u32,
+    ) -> (AIClient, Arc<Mutex<Vec<CompletionRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let provider = ScriptedProvider {
+            replies: Mutex::new(replies.iter().copied().collect()),
+            requests: requests.clone(),
+        };
+        let app_config = AppConfig {
+            ai: Some(AiConfig {
+                repair_attempts: Some(repair_attempts),
+                ..AiConfig::default()
+            }),
+            prompt: None,
+            report: None,
+            cache: Some(CacheConfig {
+                enabled: Some(false),
+                ..CacheConfig::default()
+            }),
+            input: None,
+        };
+        (
+            AIClient::with_provider(Box::new(provider), Some(app_config)),
+            requests,
+        )
+    }
+
+    fn request() -> GradingRequest {
+        let criterion = |id: &str, max_points, weight| Criterion {
+            id: id.to_string(),
+            description: String::new(),
+            max_points,
+            weight,
+        };
+        GradingRequest {
+            filename: "alice/".to_string(),
+            content: "print(1)".to_string(),
+            task_description: "Print a number".to_string(),
+            evaluation_criteria: Vec::new(),
+            rubric: vec![
+                criterion("functionality", 10.0, 0.8),
+                criterion("style", 5.0, 0.2),
+            ],
+            teacher_comment: None,
+            execution_results: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn repairs_invalid_replies_until_the_grade_is_valid() {
+        let (client, requests) = scripted_client(&[MALFORMED, OUT_OF_RANGE, VALID], 2);
+        let result = client.grade_submission(&request()).await.unwrap();
+
+        assert_eq!(result.status, GradingStatus::Graded);
+        assert_eq!(result.scores["functionality"], 9.0);
+        let provenance = result.provenance.unwrap();
+        assert_eq!(provenance.requests, 3);
+        assert_eq!(provenance.raw_response.as_deref(), Some(VALID));
+        let responses: Vec<_> = provenance
+            .repairs
+            .iter()
+            .map(|repair| repair.response.as_str())
+            .collect();
+        assert_eq!(responses, vec![MALFORMED, OUT_OF_RANGE]);
+        assert_eq!(
+            provenance.repairs[1].problems,
+            vec!["the score for 'functionality' is 12, outside the allowed range 0-10"]
+        );
+
+        // Each repair continues the conversation with the rejected reply and its problems
+        let requests = requests.lock().unwrap();
+        assert!(requests
+            .iter()
+            .all(|sent| sent.prompt == requests[0].prompt));
+        let follow_ups = &requests[2].follow_ups;
+        assert_eq!(follow_ups.len(), 2);
+        assert_eq!(follow_ups[0].reply, MALFORMED);
+        assert!(follow_ups[0]
+            .message
+            .starts_with("Your reply could not be used as a grade:\n- "));
+        assert_eq!(follow_ups[1].reply, OUT_OF_RANGE);
+        assert!(follow_ups[1]
+            .message
+            .contains("- the score for 'functionality' is 12, outside the allowed range 0-10"));
+    }
+
+    #[tokio::test]
+    async fn clamps_scores_that_stay_out_of_range() {
+        let (client, requests) = scripted_client(&[MALFORMED, OUT_OF_RANGE], 1);
+        let result = client.grade_submission(&request()).await.unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(result.status, GradingStatus::NeedsReview);
+        assert_eq!(result.scores["functionality"], 10.0);
+        assert_eq!(result.scores["style"], 4.0);
+        assert_eq!(
+            result.notes,
+            vec![
+                "Scores clamped to the allowed range: the score for 'functionality' is 12, \
+                 outside the allowed range 0-10"
+            ]
+        );
+        let provenance = result.provenance.unwrap();
+        assert_eq!(provenance.requests, 2);
+        assert_eq!(provenance.repairs.len(), 1);
+        assert_eq!(provenance.raw_response.as_deref(), Some(OUT_OF_RANGE));
+    }
+
+    #[tokio::test]
+    async fn clamps_right_away_without_repair_attempts() {
+        let (client, requests) = scripted_client(&[OUT_OF_RANGE], 0);
+        let result = client.grade_submission(&request()).await.unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(result.status, GradingStatus::NeedsReview);
+        assert!(result.provenance.unwrap().repairs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_repair_attempt() {
+        let (client, requests) =
+            scripted_client(&[MALFORMED, MALFORMED, "{\"comment\": \"no scores\"}"], 2);
+        let error = client.grade_submission(&request()).await.unwrap_err();
+
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        let invalid = error.downcast_ref::<InvalidGrade>().unwrap();
+        assert_eq!(invalid.repair_attempts, 2);
+        assert_eq!(
+            invalid.source.problems,
+            vec![
+                "the score for 'functionality' is missing",
+                "the score for 'style' is missing"
+            ]
+        );
+        assert_eq!(invalid.provenance.requests, 3);
+        assert_eq!(invalid.provenance.repairs.len(), 2);
+        assert_eq!(
+            invalid.provenance.raw_response.as_deref(),
+            Some("{\"comment\": \"no scores\"}")
+        );
+
+        let (client, requests) = scripted_client(&[MALFORMED], 0);
+        let error = client.grade_submission(&request()).await.unwrap_err();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(error.to_string(), "No valid grade after 0 repair attempts");
+    }
+}
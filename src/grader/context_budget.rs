@@ -0,0 +1,333 @@
+use crate::models::{FileContent, StudentSubmission};
+use serde::Deserialize;
+
+/// Tokens kept free on top of the estimate, since the estimate is only approximate
+const SAFETY_MARGIN: f32 = 0.1;
+/// Below this many tokens for submission content grading is not attempted
+const MIN_CONTENT_TOKENS: usize = 256;
+
+/// File stems that usually hold a program's entry point
+const ENTRY_POINT_STEMS: &[&str] = &["main", "__main__", "app", "index", "program", "lib", "run"];
+
+/// What to do with submissions that do not fit into the model's context
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowStrategy {
+    /// Grade the submission in parts and let the model merge the partial assessments
+    #[default]
+    MapReduce,
+    /// Send the most important files that fit and list the ones left out
+    Truncate,
+}
+
+/// Approximate token counter for a provider's tokenizer
+#[derive(Debug, Clone, Copy)]
+pub struct TokenEstimator {
+    /// Average number of ASCII characters per token
+    chars_per_token: f32,
+}
+
+impl TokenEstimator {
+    pub const fn new(chars_per_token: f32) -> Self {
+        Self { chars_per_token }
+    }
+
+    /// Estimated number of tokens in `text`; non-ASCII characters (accents, CJK, symbols)
+    /// are counted as a token each, as BPE vocabularies rarely merge them
+    pub fn estimate(&self, text: &str) -> usize {
+        text.chars().map(|c| self.char_cost(c)).sum::<f32>().ceil() as usize
+    }
+
+    fn char_cost(&self, c: char) -> f32 {
+        if c.is_ascii() {
+            1.0 / self.chars_per_token
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Tokens left for submission content once the prompt around it and the reply are accounted for
+pub fn content_budget(context_tokens: usize, overhead_tokens: usize) -> Option<usize> {
+    let usable = (context_tokens as f32 * (1.0 - SAFETY_MARGIN)) as usize;
+    usable
+        .checked_sub(overhead_tokens)
+        .filter(|available| *available >= MIN_CONTENT_TOKENS)
+}
+
+/// Order files by how much they tell about the submission: entry points first, then files
+/// the task description mentions, then the rest; smaller files first within each group
+pub fn prioritize<'a>(files: &'a [FileContent], task_description: &str) -> Vec<&'a FileContent> {
+    let task = task_description.to_lowercase();
+    let mut files: Vec<&FileContent> = files.iter().collect();
+    files.sort_by_cached_key(|file| {
+        let name = file
+            .filename
+            .rsplit('/')
+            .next()
+            .unwrap_or(&file.filename)
+            .to_lowercase();
+        let stem = name.split('.').next().unwrap_or(&name).to_string();
+        let group = if ENTRY_POINT_STEMS.contains(&stem.as_str()) {
+            0
+        } else if task.contains(&name) || (stem.len() > 3 && task.contains(&stem)) {
+            1
+        } else {
+            2
+        };
+        (group, file.content.len(), file.filename.clone())
+    });
+    files
+}
+
+/// Render the submission as parts that each fit into `available` tokens, in priority order;
+/// files too large for a part on their own are split by lines
+pub fn split_into_parts(
+    submission: &StudentSubmission,
+    task_description: &str,
+    estimator: TokenEstimator,
+    available: usize,
+) -> Vec<String> {
+    let notes = StudentSubmission::render_notes(&submission.notes);
+    // Every part repeats the processing notes and carries a short header added later
+    let available = available
+        .saturating_sub(estimator.estimate(&notes) + 64)
+        .max(MIN_CONTENT_TOKENS / 2);
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for file in prioritize(&submission.files, task_description) {
+        for section in split_file(file, estimator, available) {
+            let tokens = estimator.estimate(&section);
+            if current_tokens + tokens > available && !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+            current.push_str(&section);
+            current_tokens += tokens;
+        }
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+
+    parts.into_iter().map(|part| part + &notes).collect()
+}
+
+/// Render the files that fit into `available` tokens in priority order, cutting the first file
+/// that does not fit; returns the content and the files left out or cut short
+pub fn truncate(
+    submission: &StudentSubmission,
+    task_description: &str,
+    estimator: TokenEstimator,
+    available: usize,
+) -> (String, Vec<String>) {
+    let notes = StudentSubmission::render_notes(&submission.notes);
+    let mut remaining = available.saturating_sub(estimator.estimate(&notes));
+
+    let mut content = String::new();
+    let mut left_out = Vec::new();
+    for file in prioritize(&submission.files, task_description) {
+        let rendered = file.render();
+        let tokens = estimator.estimate(&rendered);
+        if tokens <= remaining {
+            content.push_str(&rendered);
+            remaining -= tokens;
+            continue;
+        }
+        // A partial file is only worth sending while there is room for a sizeable piece
+        if left_out.is_empty() && remaining >= MIN_CONTENT_TOKENS {
+            if let Some(first) = split_file(file, estimator, remaining).into_iter().next() {
+                remaining = remaining.saturating_sub(estimator.estimate(&first));
+                content.push_str(&first);
+                left_out.push(format!("{} (cut short)", file.filename));
+                continue;
+            }
+        }
+        left_out.push(file.filename.clone());
+    }
+    content.push_str(&notes);
+    (content, left_out)
+}
+
+/// Render a file whole, or as consecutive line ranges that each fit into `available` tokens
+fn split_file(file: &FileContent, estimator: TokenEstimator, available: usize) -> Vec<String> {
+    let rendered = file.render();
+    if estimator.estimate(&rendered) <= available {
+        return vec![rendered];
+    }
+
+    let header_tokens = estimator.estimate(&file.header("lines 000000-000000"));
+    let limit = available.saturating_sub(header_tokens).max(1);
+    let mut sections = Vec::new();
+    let mut body = String::new();
+    let mut body_tokens = 0;
+    let mut first_line = 1;
+    for (index, line) in file.content.split_inclusive('\n').enumerate() {
+        // Lines longer than a whole part (minified code, data) are cut into pieces
+        for piece in split_line(line, estimator, limit) {
+            let tokens = estimator.estimate(piece);
+            if body_tokens + tokens > limit && !body.is_empty() {
+                sections.push(file.section(first_line, index.max(first_line), &body));
+                body.clear();
+                body_tokens = 0;
+                first_line = index + 1;
+            }
+            body.push_str(piece);
+            body_tokens += tokens;
+        }
+    }
+    if !body.is_empty() {
+        let last_line = file.content.lines().count().max(first_line);
+        sections.push(file.section(first_line, last_line, &body));
+    }
+    sections
+}
+
+fn split_line(line: &str, estimator: TokenEstimator, limit: usize) -> Vec<&str> {
+    if estimator.estimate(line) <= limit {
+        return vec![line];
+    }
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0.0;
+    for (offset, c) in line.char_indices() {
+        let cost = estimator.char_cost(c);
+        if tokens + cost > limit as f32 && offset > start {
+            pieces.push(&line[start..offset]);
+            start = offset;
+            tokens = 0.0;
+        }
+        tokens += cost;
+    }
+    pieces.push(&line[start..]);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per character, so that budgets can be read off the text
+    const CHARS: TokenEstimator = TokenEstimator::new(1.0);
+
+    fn file(filename: &str, content: &str) -> FileContent {
+        FileContent {
+            filename: filename.to_string(),
+            content: content.to_string(),
+            file_type: "py".to_string(),
+            metadata: Default::default(),
+            review: None,
+            notes: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|i| format!("line {:04}\n", i)).collect()
+    }
+
+    #[test]
+    fn estimates_non_ascii_characters_as_whole_tokens() {
+        let estimator = TokenEstimator::new(4.0);
+        assert_eq!(estimator.estimate(""), 0);
+        assert_eq!(estimator.estimate("abcdefgh"), 2);
+        assert_eq!(estimator.estimate("abcdefghi"), 3);
+        assert_eq!(estimator.estimate("日本語"), 3);
+    }
+
+    #[test]
+    fn budget_leaves_a_safety_margin() {
+        assert_eq!(content_budget(4096, 1000), Some(2686));
+        assert_eq!(content_budget(1000, 700), None);
+    }
+
+    #[test]
+    fn entry_points_and_mentioned_files_come_first() {
+        let files = vec![
+            file("alice/zeta.py", "z"),
+            file("alice/helpers.py", "a much longer helper file"),
+            file("alice/parser.py", "parse"),
+            file("alice/src/main.py", "main body"),
+        ];
+        let order: Vec<&str> = prioritize(&files, "Write a Parser for the input")
+            .into_iter()
+            .map(|file| file.filename.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "alice/src/main.py",
+                "alice/parser.py",
+                "alice/zeta.py",
+                "alice/helpers.py"
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_large_files_by_line_ranges() {
+        let big = file("alice/main.py", &numbered_lines(100));
+        let sections = split_file(&big, CHARS, 300);
+        assert!(sections.len() > 1);
+        for section in &sections {
+            assert!(CHARS.estimate(section) <= 300, "{}", section);
+        }
+        assert!(sections[0].contains("lines 1-"));
+        assert!(sections.last().unwrap().contains("-100)"));
+
+        // Every line appears exactly once across the sections
+        let all = sections.concat();
+        for i in 1..=100 {
+            assert_eq!(all.matches(&format!("line {:04}\n", i)).count(), 1, "{}", i);
+        }
+    }
+
+    #[test]
+    fn cuts_lines_longer_than_a_part() {
+        let line = "x".repeat(250);
+        let pieces = split_line(&line, CHARS, 100);
+        assert_eq!(
+            pieces.iter().map(|piece| piece.len()).collect::<Vec<_>>(),
+            vec![100, 100, 50]
+        );
+    }
+
+    #[test]
+    fn splits_a_submission_into_parts_that_fit() {
+        let submission = StudentSubmission::new(
+            "alice".to_string(),
+            vec![
+                file("alice/main.py", &numbered_lines(60)),
+                file("alice/util.py", &numbered_lines(60)),
+            ],
+        );
+        let parts = split_into_parts(&submission, "", CHARS, 400);
+        assert!(parts.len() >= 3);
+        for part in &parts {
+            assert!(CHARS.estimate(part) <= 400);
+        }
+        assert!(parts[0].contains("alice/main.py"));
+        assert!(parts.last().unwrap().contains("alice/util.py"));
+    }
+
+    #[test]
+    fn truncation_lists_the_files_left_out() {
+        let submission = StudentSubmission::new(
+            "alice".to_string(),
+            vec![
+                file("alice/main.py", "print('hi')\n"),
+                file("alice/data.py", &numbered_lines(100)),
+                file("alice/extra.py", &numbered_lines(100)),
+            ],
+        );
+        let (content, left_out) = truncate(&submission, "", CHARS, 600);
+        assert!(content.contains("print('hi')"));
+        assert!(CHARS.estimate(&content) <= 600);
+        assert_eq!(
+            left_out,
+            vec!["alice/data.py (cut short)", "alice/extra.py"]
+        );
+    }
+}
@@ -1,16 +1,22 @@
+use crate::executor::Executor;
+use crate::grader::context_budget::{self, OverflowStrategy};
+use crate::grader::response_parser::{self, calculate_total};
 use crate::grader::AIClient;
-use crate::models::{Config, GradingRequest, GradingResult, StudentSubmission};
-use anyhow::Result;
+use crate::models::{Config, GradingRequest, GradingResult, GradingStatus, StudentSubmission};
+use anyhow::{Context, Result};
+use tracing::{info, warn};
 
 /// Engine that handles the grading logic
 pub struct GradingEngine {
     config: Config,
+    executor: Option<Executor>,
 }
 
 impl GradingEngine {
     pub fn new(config: &Config) -> Self {
         Self {
             config: config.clone(),
+            executor: config.execution.clone().map(Executor::new),
         }
     }
 
@@ -19,16 +25,328 @@ impl GradingEngine {
         ai_client: &AIClient,
         submission: &StudentSubmission,
     ) -> Result<GradingResult> {
-        let request = GradingRequest {
+        if submission.files.is_empty() {
+            return Ok(GradingResult::skipped(
+                submission.get_main_filename(),
+                "no readable files in the submission",
+            ));
+        }
+
+        let execution = match &self.executor {
+            Some(executor) => Some(executor.run(submission).await),
+            None => None,
+        };
+
+        let mut request = GradingRequest {
             filename: submission.get_main_filename(),
             content: submission.merged_content.clone(),
             task_description: self.config.task_description.clone(),
             evaluation_criteria: self.config.evaluation_criteria.clone(),
+            rubric: self.config.rubric.clone(),
             teacher_comment: self.config.teacher_comment.clone(),
+            execution_results: execution.as_ref().map(|report| report.render()),
         };
 
+        let estimator = ai_client.token_estimator();
+        let available = ai_client.content_budget(&request).context(
+            "The grading prompt leaves no room for the submission in the model's context, raise `context_tokens` or lower `max_tokens` in config.toml",
+        )?;
+        let mut notes = Vec::new();
+        let mut result = if estimator.estimate(&request.content) <= available {
+            ai_client.grade_submission(&request).await?
+        } else {
+            match ai_client.overflow() {
+                OverflowStrategy::MapReduce => {
+                    let parts = context_budget::split_into_parts(
+                        submission,
+                        &self.config.task_description,
+                        estimator,
+                        available,
+                    );
+                    if parts.len() > 1 {
+                        notes.push(format!(
+                            "Graded in {} parts to fit the model's context",
+                            parts.len()
+                        ));
+                    }
+                    self.grade_in_parts(ai_client, request, submission, parts)
+                        .await?
+                }
+                OverflowStrategy::Truncate => {
+                    let (content, left_out) = context_budget::truncate(
+                        submission,
+                        &self.config.task_description,
+                        estimator,
+                        available,
+                    );
+                    warn!(
+                        "{} does not fit into the model's context, left out: {}",
+                        submission.student_name,
+                        left_out.join(", ")
+                    );
+                    notes.push(format!(
+                        "Too large for the model's context, left out of the prompt: {}",
+                        left_out.join(", ")
+                    ));
+                    request.content = content;
+                    ai_client.grade_submission(&request).await?
+                }
+            }
+        };
+        result.notes.splice(0..0, notes);
+
+        if let Some(report) = execution.as_ref().filter(|report| report.setup_failed()) {
+            // The grading host is at fault, so the tests neither count nor zero the criterion
+            let error = report.error.as_ref().map(ToString::to_string);
+            warn!(
+                "Test cases of {} could not be run, keeping the model's scores: {}",
+                submission.student_name,
+                error.as_deref().unwrap_or_default()
+            );
+            result.notes.push(format!(
+                "Test cases could not be run, the model's scores were kept: {}",
+                error.unwrap_or_default()
+            ));
+            if result.status == GradingStatus::Graded {
+                result.status = GradingStatus::NeedsReview;
+            }
+        } else if let Some(report) = &execution {
+            let summary = report.summary();
+            result.tests = Some(summary);
+
+            // Test outcomes are objective, so they replace the model's guess for the tied criterion
+            let tied = self
+                .config
+                .execution
+                .as_ref()
+                .and_then(|e| e.criterion.as_ref());
+            if let Some(criterion) =
+                tied.and_then(|id| self.config.rubric.iter().find(|c| &c.id == id))
+            {
+                if summary.total > 0 {
+                    result.scores.insert(
+                        criterion.id.clone(),
+                        summary.pass_rate() * criterion.max_points,
+                    );
+                    result.total = calculate_total(&result.scores, &self.config.rubric);
+                }
+            }
+        }
+
+        self.apply_scale(&mut result);
+        Ok(result)
+    }
+
+    /// Whether `result` scores every criterion of the rubric, so that it can be kept when
+    /// resuming
+    pub fn covers_rubric(&self, result: &GradingResult) -> bool {
+        self.config
+            .rubric
+            .iter()
+            .all(|criterion| result.scores.contains_key(&criterion.id))
+    }
+
+    /// Express a grade on the assignment's score scale
+    pub fn apply_scale(&self, result: &mut GradingResult) {
+        response_parser::apply_scale(result, &self.config.rubric, &self.config.scale);
+    }
+
+    /// Grade each part on its own, then have the model combine the partial assessments
+    async fn grade_in_parts(
+        &self,
+        ai_client: &AIClient,
+        mut request: GradingRequest,
+        submission: &StudentSubmission,
+        parts: Vec<String>,
+    ) -> Result<GradingResult> {
+        let count = parts.len();
+        if count == 1 {
+            request.content = parts.into_iter().next().unwrap_or_default();
+            return ai_client.grade_submission(&request).await;
+        }
+        info!(
+            "{} does not fit into the model's context, grading it in {} parts",
+            submission.student_name, count
+        );
+
+        let mut assessments = Vec::with_capacity(count);
+        for (i, content) in parts.into_iter().enumerate() {
+            let part = GradingRequest {
+                content: format!(
+                    "[Part {} of {} of the submission. Grade only what this part shows; the other parts are graded separately and the assessments combined afterwards.]\n\n{}",
+                    i + 1,
+                    count,
+                    content
+                ),
+                ..request.clone()
+            };
+            let assessment = ai_client
+                .grade_submission(&part)
+                .await
+                .with_context(|| format!("Failed to grade part {} of {}", i + 1, count))?;
+            assessments.push(assessment);
+        }
+
+        // The merge prompt lists the files instead of repeating their content
+        request.content = submission
+            .files
+            .iter()
+            .map(|file| {
+                format!(
+                    "- {} ({} lines)",
+                    file.filename,
+                    file.content.lines().count()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
         ai_client
-            .grade_submission(&request, &self.config.grading_strategy)
+            .merge_assessments(&request, assessments)
+            .await
+            .context("Failed to merge the assessments of the parts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, CacheConfig};
+    use crate::grader::context_budget::TokenEstimator;
+    use crate::grader::providers::{Completion, CompletionRequest, ModelProvider, ProviderKind};
+    use crate::models::FileContent;
+    use async_trait::async_trait;
+    use std::collections::BTreeMap;
+
+    /// Always gives full marks for functionality and 3 of 5 for style
+    struct FixedProvider;
+
+    #[async_trait]
+    impl ModelProvider for FixedProvider {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::OpenAICompatible
+        }
+
+        fn model(&self) -> &str {
+            "fixed"
+        }
+
+        fn endpoint(&self) -> &str {
+            "http://localhost/v1/chat/completions"
+        }
+
+        fn token_estimator(&self) -> TokenEstimator {
+            TokenEstimator::new(4.0)
+        }
+
+        fn context_window(&self) -> usize {
+            8192
+        }
+
+        async fn complete(&self, _request: &CompletionRequest) -> Result<Completion> {
+            Ok(Completion {
+                text: r#"{"scores": {"functionality": 10, "style": 3}, "comment": "ok"}"#
+                    .to_string(),
+                usage: None,
+            })
+        }
+    }
+
+    fn client() -> AIClient {
+        let app_config = AppConfig {
+            ai: None,
+            prompt: None,
+            report: None,
+            cache: Some(CacheConfig {
+                enabled: Some(false),
+                ..CacheConfig::default()
+            }),
+            input: None,
+        };
+        AIClient::with_provider(Box::new(FixedProvider), Some(app_config))
+    }
+
+    fn config(execution: serde_json::Value) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "task_description": "Add two numbers",
+            "rubric": [
+                {"id": "functionality", "description": "Works", "max_points": 10, "weight": 0.5},
+                {"id": "style", "description": "Readable", "max_points": 5, "weight": 0.5}
+            ],
+            "execution": execution,
+        }))
+        .unwrap()
+    }
+
+    fn submission() -> StudentSubmission {
+        submission_of(false)
+    }
+
+    fn submission_of(truncated: bool) -> StudentSubmission {
+        StudentSubmission::new(
+            "alice".to_string(),
+            vec![FileContent {
+                filename: "alice/main.py".to_string(),
+                content: "print(sum(map(int, input().split())))\n".to_string(),
+                file_type: "py".to_string(),
+                metadata: BTreeMap::new(),
+                review: None,
+                notes: Vec::new(),
+                truncated,
+            }],
+        )
+    }
+
+    #[tokio::test]
+    async fn keeps_the_model_score_when_tests_cannot_run() {
+        for execution in [
+            // No runnable source in the configured language
+            serde_json::json!({
+                "language": "rust",
+                "criterion": "functionality",
+                "test_cases": [{"name": "sum", "stdin": "1 2", "expected_stdout": "3"}]
+            }),
+            // The test runner does not exist on the grading machine
+            serde_json::json!({
+                "criterion": "functionality",
+                "test_cases": [{"name": "sum", "command": ["/nonexistent/paper-sage-runner"]}]
+            }),
+        ] {
+            let engine = GradingEngine::new(&config(execution));
+            let result = engine
+                .grade_submission(&client(), &submission())
+                .await
+                .unwrap();
+
+            assert_eq!(result.scores["functionality"], 10.0);
+            assert_eq!(result.status, GradingStatus::NeedsReview);
+            assert!(result.tests.is_none());
+            assert!(
+                result.notes[0].starts_with("Test cases could not be run"),
+                "{:?}",
+                result.notes
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_run_files_cut_to_the_size_limit() {
+        let engine = GradingEngine::new(&config(serde_json::json!({
+            "criterion": "functionality",
+            "test_cases": [{"name": "sum", "stdin": "1 2", "expected_stdout": "3"}]
+        })));
+        let result = engine
+            .grade_submission(&client(), &submission_of(true))
             .await
+            .unwrap();
+
+        assert_eq!(result.scores["functionality"], 10.0);
+        assert_eq!(result.status, GradingStatus::NeedsReview);
+        assert_eq!(
+            result.notes,
+            vec![
+                "Test cases could not be run, the model's scores were kept: alice/main.py was \
+                 cut to the size limit for text files, so the program cannot be run"
+            ]
+        );
     }
 }
@@ -1,37 +1,63 @@
 pub mod ai_client;
+pub mod context_budget;
 pub mod grading_engine;
-pub mod mock_grading;
 pub mod prompt_builder;
+pub mod providers;
+pub mod rate_limiter;
+pub mod response_cache;
 pub mod response_parser;
+pub mod retry;
 
 use crate::config::AppConfig;
 use crate::file_processor::FileProcessor;
-use crate::models::{Config, GradingResult};
+use crate::grader::ai_client::InvalidGrade;
+use crate::grader::retry::ErrorClass;
+use crate::models::{Config, GradingResult, GradingStatus, StudentSubmission};
 use anyhow::Result;
+use futures::stream::{self, StreamExt};
 use std::collections::HashMap;
-use tracing::{error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use tracing::{error, info, warn};
 
 pub use ai_client::AIClient;
 pub use grading_engine::GradingEngine;
+pub use providers::{ModelProvider, ProviderKind};
+
+/// A run stopped by an error that would fail every remaining submission, with the results
+/// completed before it so that they can still be written and resumed from
+#[derive(Debug, thiserror::Error)]
+#[error("{completed} of {total} submissions were graded before the run stopped")]
+pub struct GradingStopped {
+    pub results: Vec<GradingResult>,
+    pub completed: usize,
+    pub total: usize,
+    #[source]
+    pub source: Box<dyn std::error::Error + Send + Sync>,
+}
 
 /// Main grader that orchestrates the grading process
 pub struct Grader {
     ai_client: AIClient,
     grading_engine: GradingEngine,
+    concurrency: usize,
 }
 
 impl Grader {
-    pub fn new(
-        model_endpoint: &str,
-        config: &Config,
-        app_config: Option<AppConfig>,
-    ) -> Result<Self> {
-        let ai_client = AIClient::new(model_endpoint, app_config)?;
+    pub fn new(config: &Config, app_config: Option<AppConfig>) -> Result<Self> {
+        let concurrency = app_config
+            .as_ref()
+            .and_then(|cfg| cfg.ai.as_ref())
+            .and_then(|ai| ai.concurrency)
+            .unwrap_or(1)
+            .max(1);
+        let ai_client = AIClient::new(app_config)?;
         let grading_engine = GradingEngine::new(config);
 
         Ok(Self {
             ai_client,
             grading_engine,
+            concurrency,
         })
     }
 
@@ -41,50 +67,23 @@ impl Grader {
         file_processor: &FileProcessor,
     ) -> Result<Vec<GradingResult>> {
         let submissions = file_processor.process_directory(input_dir)?;
-        let mut results = Vec::new();
+        let total = submissions.len();
 
         info!(
-            "Starting to grade {} student submissions",
-            submissions.len()
+            "Starting to grade {} student submissions ({} concurrent requests)",
+            total, self.concurrency
         );
 
-        for (i, submission) in submissions.iter().enumerate() {
-            info!(
-                "Grading submission {}/{}: {} ({} files)",
-                i + 1,
-                submissions.len(),
-                submission.student_name,
-                submission.files.len()
-            );
-
-            match self
-                .grading_engine
-                .grade_submission(&self.ai_client, submission)
-                .await
-            {
-                Ok(result) => {
-                    info!(
-                        "Successfully graded: {} (Score: {:.2})",
-                        submission.student_name, result.total
-                    );
-                    results.push(result);
-                }
-                Err(e) => {
-                    error!("Failed to grade {}: {}", submission.student_name, e);
-                    // Add a failed result
-                    results.push(GradingResult {
-                        filename: submission.get_main_filename(),
-                        correctness: 0.0,
-                        style: 0.0,
-                        edge_cases: 0.0,
-                        total: 0.0,
-                        comment: format!("Error during grading: {}", e),
-                    });
-                }
-            }
-        }
+        // `buffered` bounds the number of in-flight requests and yields results in input order;
+        // a fatal error stops the run instead of failing every remaining submission
+        let stopped = AtomicBool::new(false);
+        let outcomes = stream::iter(submissions.iter().enumerate())
+            .map(|(i, submission)| self.grade_unless_stopped(&stopped, i + 1, total, submission))
+            .buffered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
 
-        Ok(results)
+        finish(outcomes, total)
     }
 
     pub async fn resume_grading(
@@ -93,51 +92,191 @@ impl Grader {
         file_processor: &FileProcessor,
         resume_path: &str,
     ) -> Result<Vec<GradingResult>> {
-        let existing_results: Vec<GradingResult> =
-            serde_json::from_str(&std::fs::read_to_string(resume_path)?)?;
+        let existing_results = GradingResult::read_all(&std::fs::read_to_string(resume_path)?)?;
+        let existing = existing_results.len();
 
+        // Only model grades of the current rubric are kept: failed, skipped, mock and review
+        // results are graded again, and missing-student rows must not shadow a late
+        // submission. Results from older runs have no submission id and are matched by filename
         let mut completed_submissions: HashMap<String, GradingResult> = existing_results
             .into_iter()
-            .map(|r| (r.filename.clone(), r))
+            .filter(|r| r.status == GradingStatus::Graded && self.grading_engine.covers_rubric(r))
+            .map(|r| {
+                let key = if r.submission_id.is_empty() {
+                    r.filename.clone()
+                } else {
+                    r.submission_id.clone()
+                };
+                (key, r)
+            })
             .collect();
+        info!(
+            "Keeping {} of {} earlier results, grading the rest again",
+            completed_submissions.len(),
+            existing
+        );
 
         let submissions = file_processor.process_directory(input_dir)?;
-        let mut results = Vec::new();
-
-        for submission in submissions {
-            let submission_key = submission.get_main_filename();
-            if let Some(existing) = completed_submissions.remove(&submission_key) {
-                results.push(existing);
-                info!("Using existing result for: {}", submission.student_name);
-            } else {
-                info!("Grading submission: {}", submission.student_name);
-                match self
-                    .grading_engine
-                    .grade_submission(&self.ai_client, &submission)
-                    .await
-                {
-                    Ok(result) => {
-                        info!(
-                            "Successfully graded: {} (Score: {:.2})",
-                            submission.student_name, result.total
-                        );
-                        results.push(result);
-                    }
-                    Err(e) => {
-                        error!("Failed to grade {}: {}", submission.student_name, e);
-                        results.push(GradingResult {
-                            filename: submission.get_main_filename(),
-                            correctness: 0.0,
-                            style: 0.0,
-                            edge_cases: 0.0,
-                            total: 0.0,
-                            comment: format!("Error during grading: {}", e),
-                        });
+        let total = submissions.len();
+        let pending: Vec<(StudentSubmission, Option<GradingResult>)> = submissions
+            .into_iter()
+            .map(|submission| {
+                let existing = completed_submissions
+                    .remove(&submission.submission_id)
+                    .or_else(|| completed_submissions.remove(&submission.get_main_filename()));
+                (submission, existing)
+            })
+            .collect();
+
+        let stopped = AtomicBool::new(false);
+        let outcomes = stream::iter(pending.into_iter().enumerate())
+            .map(|(i, (submission, existing))| {
+                let stopped = &stopped;
+                async move {
+                    match existing {
+                        Some(mut existing) => {
+                            info!("Using existing result for: {}", submission.student_name);
+                            existing.submission_id = submission.submission_id.clone();
+                            // Earlier runs may have reported totals as percentages or on another scale
+                            self.grading_engine.apply_scale(&mut existing);
+                            Some(Ok(existing))
+                        }
+                        None => {
+                            self.grade_unless_stopped(stopped, i + 1, total, &submission)
+                                .await
+                        }
                     }
                 }
+            })
+            .buffered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        finish(outcomes, total)
+    }
+
+    /// Grade a submission unless a fatal error has stopped the run. Submissions already in
+    /// flight when that happens still finish, as their requests are paid for
+    async fn grade_unless_stopped(
+        &self,
+        stopped: &AtomicBool,
+        position: usize,
+        total: usize,
+        submission: &StudentSubmission,
+    ) -> Option<Result<GradingResult>> {
+        if stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        let outcome = self.grade_one(position, total, submission).await;
+        if outcome.is_err() {
+            stopped.store(true, Ordering::Relaxed);
+        }
+        Some(outcome)
+    }
+
+    /// Grade a single submission, turning errors into a failed result; only errors that
+    /// would fail every other submission too are returned
+    async fn grade_one(
+        &self,
+        position: usize,
+        total: usize,
+        submission: &StudentSubmission,
+    ) -> Result<GradingResult> {
+        info!(
+            "Grading submission {}/{}: {} ({} files)",
+            position,
+            total,
+            submission.student_name,
+            submission.files.len()
+        );
+
+        let started = SystemTime::now();
+        let mut result = match self
+            .grading_engine
+            .grade_submission(&self.ai_client, submission)
+            .await
+        {
+            Ok(result) if result.status == GradingStatus::Skipped => {
+                warn!("Skipping {}: {}", submission.student_name, result.comment);
+                result
+            }
+            Ok(result) => {
+                info!(
+                    "Successfully graded: {} (Score: {:.2})",
+                    submission.student_name, result.total
+                );
+                result
+            }
+            Err(e) if ErrorClass::of(&e) == ErrorClass::Fatal => {
+                error!(
+                    "Stopping the run while grading {}: {:#}",
+                    submission.student_name, e
+                );
+                return Err(e.context(
+                    "Grading stopped, the model API rejected a request in a way that affects every submission",
+                ));
+            }
+            Err(e) => {
+                error!("Failed to grade {}: {:#}", submission.student_name, e);
+                let mut result = GradingResult::error(
+                    submission.get_main_filename(),
+                    format!("{:#}", e),
+                    Some(self.ai_client.model().to_string()),
+                );
+                // Rejected replies are kept for a look at what the model answered
+                result.provenance = e
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<InvalidGrade>())
+                    .map(|invalid| invalid.provenance.clone());
+                result
             }
+        };
+        result.submission_id = submission.submission_id.clone();
+        result.student_id = submission.student_id.clone();
+        // Notes from reading the submission come before those from grading it
+        result.notes.splice(0..0, submission.notes.iter().cloned());
+        result.metadata = submission.metadata();
+        if result.status == GradingStatus::Graded && submission.needs_review() {
+            result.status = GradingStatus::NeedsReview;
         }
+        // Failed grades still record which provider and prompt were tried, and when
+        if result.status != GradingStatus::Skipped {
+            let provenance = result
+                .provenance
+                .get_or_insert_with(|| self.ai_client.provenance());
+            provenance.started_at = humantime::format_rfc3339_seconds(started).to_string();
+            provenance.finished_at =
+                humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        }
+        Ok(result)
+    }
+}
+
+/// The results of a run in submission order, or a [`GradingStopped`] holding those completed
+/// before the first fatal error
+fn finish(
+    outcomes: Vec<Option<Result<GradingResult>>>,
+    total: usize,
+) -> Result<Vec<GradingResult>> {
+    let mut results = Vec::with_capacity(outcomes.len());
+    let mut fatal = None;
+    for outcome in outcomes.into_iter().flatten() {
+        match outcome {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                fatal.get_or_insert(e);
+            }
+        }
+    }
 
-        Ok(results)
+    match fatal {
+        None => Ok(results),
+        Some(e) => Err(GradingStopped {
+            completed: results.len(),
+            results,
+            total,
+            source: e.into(),
+        }
+        .into()),
     }
 }
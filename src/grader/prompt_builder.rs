@@ -1,15 +1,19 @@
 use crate::config::AppConfig;
-use crate::models::GradingRequest;
+use crate::models::{GradingRequest, GradingResult};
+
+const DEFAULT_TEMPLATE: &str = "Please grade the following student submission according to the task description and evaluation criteria.\n\nTask Description:\n{task_description}\n\nEvaluation Criteria:\n{criteria}{teacher_comment}\n\nStudent Submission (File: {filename}):\n```\n{content}\n```\n{execution_results}\nPlease provide your evaluation in the following JSON format, with one score per criterion:\n{\n    \"scores\": {\n{scores_format}\n    },\n    \"comment\": \"<detailed feedback>\"\n}\n\nNote: The total score will be calculated automatically using the criterion weights.";
+
+/// The grading prompt template: `prompt.template` from config.toml or the built-in one
+pub fn template(app_config: Option<&AppConfig>) -> &str {
+    app_config
+        .and_then(|cfg| cfg.prompt.as_ref()?.template.as_deref())
+        .unwrap_or(DEFAULT_TEMPLATE)
+}
 
 /// Build grading prompts for AI models
 pub fn build_grading_prompt(request: &GradingRequest, app_config: Option<&AppConfig>) -> String {
-    let criteria_text = request
-        .evaluation_criteria
-        .iter()
-        .enumerate()
-        .map(|(i, criteria)| format!("{}. {}", i + 1, criteria))
-        .collect::<Vec<_>>()
-        .join("\n");
+    let criteria_text = criteria_text(request);
+    let scores_format = scores_format(request);
 
     let teacher_comment = request
         .teacher_comment
@@ -17,17 +21,128 @@ pub fn build_grading_prompt(request: &GradingRequest, app_config: Option<&AppCon
         .map(|c| format!("\nTeacher Comment: {}", c))
         .unwrap_or_default();
 
-    let default_template = "Please grade the following student submission according to the task description and evaluation criteria.\n\nTask Description:\n{task_description}\n\nEvaluation Criteria:\n{criteria}{teacher_comment}\n\nStudent Submission (File: {filename}):\n```\n{content}\n```\n\nPlease provide your evaluation in the following JSON format:\n{{\n    \"filename\": \"{filename}\",\n    \"correctness\": <score 0-100>,\n    \"style\": <score 0-100>,\n    \"edge_cases\": <score 0-100>,\n    \"total\": <weighted average score 0-100>,\n    \"comment\": \"<detailed feedback>\"\n}}\n\nNote: The total score will be calculated automatically using the grading weights.";
+    let execution_results = request
+        .execution_results
+        .as_ref()
+        .map(|results| format!("\nAutomated Test Results:\n{}", results))
+        .unwrap_or_default();
+
+    let mut template = template(app_config).to_string();
 
-    let template = app_config
-        .and_then(|cfg| cfg.prompt.as_ref()?.template.as_ref())
-        .map(|s| s.as_str())
-        .unwrap_or(default_template);
+    // Custom templates written before test execution existed still get the evidence
+    if !template.contains("{execution_results}") && !execution_results.is_empty() {
+        template.push_str("\n{execution_results}");
+    }
 
     template
         .replace("{task_description}", &request.task_description)
         .replace("{criteria}", &criteria_text)
+        .replace("{scores_format}", &scores_format)
         .replace("{teacher_comment}", &teacher_comment)
         .replace("{filename}", &request.filename)
+        .replace("{execution_results}", &execution_results)
         .replace("{content}", &request.content)
 }
+
+/// Build the prompt combining the assessments of a submission graded in parts;
+/// `request.content` lists the files of the whole submission
+pub fn build_merge_prompt(request: &GradingRequest, parts: &[GradingResult]) -> String {
+    let mut prompt = format!(
+        "A student submission was too large to grade at once, so it was graded in {} parts. Combine the partial assessments below into one final grade for the whole submission. Judge each criterion on the evidence from all parts together: do not penalize a part for work that appears in another part, and do not count the same work twice.\n\nTask Description:\n{}\n\nEvaluation Criteria:\n{}\n",
+        parts.len(),
+        request.task_description,
+        criteria_text(request)
+    );
+    if let Some(comment) = &request.teacher_comment {
+        prompt.push_str(&format!("\nTeacher Comment: {}\n", comment));
+    }
+    prompt.push_str(&format!(
+        "\nFiles in the submission ({}):\n{}\n",
+        request.filename, request.content
+    ));
+    for (i, part) in parts.iter().enumerate() {
+        let scores = part
+            .scores
+            .iter()
+            .map(|(id, score)| format!("{}: {}", id, score))
+            .collect::<Vec<_>>()
+            .join(", ");
+        prompt.push_str(&format!(
+            "\nPart {} of {}:\nScores: {}\nComment: {}\n",
+            i + 1,
+            parts.len(),
+            scores,
+            part.comment
+        ));
+    }
+    if let Some(results) = &request.execution_results {
+        prompt.push_str(&format!("\nAutomated Test Results:\n{}\n", results));
+    }
+    prompt.push_str(&format!(
+        "\nPlease provide the final evaluation in the following JSON format, with one score per criterion and feedback covering the whole submission:\n{{\n    \"scores\": {{\n{}\n    }},\n    \"comment\": \"<detailed feedback>\"\n}}",
+        scores_format(request)
+    ));
+    prompt
+}
+
+/// Build the follow-up asking the model to correct a reply that is not a valid grade
+pub fn build_repair_prompt(request: &GradingRequest, problems: &[String]) -> String {
+    format!(
+        "Your reply could not be used as a grade:\n{}\n\nReply again with only the corrected JSON object in the following format, with a score within the allowed range for every criterion:\n{{\n    \"scores\": {{\n{}\n    }},\n    \"comment\": \"<detailed feedback>\"\n}}",
+        problems
+            .iter()
+            .map(|problem| format!("- {}", problem))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        scores_format(request)
+    )
+}
+
+/// Numbered rubric criteria followed by the free-form guidance
+fn criteria_text(request: &GradingRequest) -> String {
+    let mut criteria_text = request
+        .rubric
+        .iter()
+        .enumerate()
+        .map(|(i, criterion)| {
+            format!(
+                "{}. {} (0-{} points, weight {:.0}%): {}",
+                i + 1,
+                criterion.id,
+                criterion.max_points,
+                criterion.weight * 100.0,
+                criterion.description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if !request.evaluation_criteria.is_empty() {
+        criteria_text.push_str("\n\nAdditional guidance:\n");
+        criteria_text.push_str(
+            &request
+                .evaluation_criteria
+                .iter()
+                .map(|guidance| format!("- {}", guidance))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+
+    criteria_text
+}
+
+/// Lines of the expected `scores` object, one per criterion
+fn scores_format(request: &GradingRequest) -> String {
+    request
+        .rubric
+        .iter()
+        .map(|criterion| {
+            format!(
+                "        \"{}\": <score 0-{}>",
+                criterion.id, criterion.max_points
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n")
+}
@@ -0,0 +1,113 @@
+use super::{Completion, CompletionRequest, ModelProvider, ProviderKind};
+use crate::grader::context_budget::TokenEstimator;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+const MOCK_COMMENT: &str = "Mock grade from --provider mock: no model was called, the scores are derived from a hash of the prompt.";
+
+/// Backend for dry runs that answers like a model without calling one: every criterion gets
+/// between half and full points in half-point steps, derived from a hash of the criterion and
+/// the prompt, so the same submission always gets the same scores
+pub struct MockProvider;
+
+#[async_trait]
+impl ModelProvider for MockProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Mock
+    }
+
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    fn endpoint(&self) -> &str {
+        ""
+    }
+
+    fn token_estimator(&self) -> TokenEstimator {
+        TokenEstimator::new(3.5)
+    }
+
+    fn context_window(&self) -> usize {
+        128_000
+    }
+
+    fn simulated(&self) -> bool {
+        true
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let scores: serde_json::Map<String, Value> = request
+            .rubric
+            .iter()
+            .map(|criterion| {
+                let digest = Sha256::new()
+                    .chain_update(criterion.id.as_bytes())
+                    .chain_update([0u8])
+                    .chain_update(request.prompt.as_bytes())
+                    .finalize();
+                let hash = u16::from_be_bytes([digest[0], digest[1]]);
+                let fraction = 0.5 + 0.5 * f32::from(hash) / f32::from(u16::MAX);
+                let score = (criterion.max_points * fraction * 2.0).round() / 2.0;
+                (criterion.id.clone(), json!(score.min(criterion.max_points)))
+            })
+            .collect();
+
+        Ok(Completion {
+            text: json!({ "scores": scores, "comment": MOCK_COMMENT }).to_string(),
+            usage: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grader::response_parser::parse_grading_response;
+    use crate::models::Criterion;
+    use std::time::Duration;
+
+    fn request(prompt: &str) -> CompletionRequest {
+        CompletionRequest {
+            system: String::new(),
+            prompt: prompt.to_string(),
+            temperature: 0.0,
+            max_tokens: 100,
+            timeout: Duration::from_secs(1),
+            context_tokens: 1000,
+            rubric: vec![
+                Criterion {
+                    id: "functionality".to_string(),
+                    description: String::new(),
+                    max_points: 40.0,
+                    weight: 0.6,
+                },
+                Criterion {
+                    id: "style".to_string(),
+                    description: String::new(),
+                    max_points: 5.0,
+                    weight: 0.4,
+                },
+            ],
+            schema: None,
+            follow_ups: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_with_a_valid_deterministic_grade() {
+        let request = request("grade this");
+        let first = MockProvider.complete(&request).await.unwrap();
+        let second = MockProvider.complete(&request).await.unwrap();
+        assert_eq!(first.text, second.text);
+
+        let result = parse_grading_response(&first.text, "a/", &request.rubric).unwrap();
+        for criterion in &request.rubric {
+            let score = result.scores[&criterion.id];
+            assert!(score >= criterion.max_points / 2.0 && score <= criterion.max_points);
+            assert_eq!(score * 2.0, (score * 2.0).round());
+        }
+    }
+}
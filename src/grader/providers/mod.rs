@@ -0,0 +1,489 @@
+mod mock;
+mod ollama;
+mod openai;
+
+use crate::grader::context_budget::TokenEstimator;
+use crate::models::{Criterion, TokenUsage};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+pub use mock::MockProvider;
+pub use ollama::OllamaProvider;
+pub use openai::{OpenAICompatibleProvider, OpenAIProvider};
+
+/// Default endpoint used when the OpenAI provider is selected without `--model-endpoint`
+pub const DEFAULT_OPENAI_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
+
+/// Default endpoint used when the Ollama provider is selected without `--model-endpoint`
+pub const DEFAULT_OLLAMA_ENDPOINT: &str = "http://localhost:11434";
+
+/// Default model used by the OpenAI provider when `--model` is not given
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-3.5-turbo";
+
+/// Default model used by the Ollama provider when `--model` is not given
+pub const DEFAULT_OLLAMA_MODEL: &str = "qwen2.5:0.5b";
+
+/// Model backends that can be selected via `config.toml` or `--provider`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum ProviderKind {
+    /// OpenAI chat completions API
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAI,
+    /// Local Ollama server (`/api/generate`)
+    #[serde(rename = "ollama")]
+    #[value(name = "ollama")]
+    Ollama,
+    /// Any server exposing an OpenAI-compatible `/v1/chat/completions` route
+    /// (vLLM, llama.cpp server, LM Studio, proxies)
+    #[serde(rename = "openai-compatible")]
+    #[value(name = "openai-compatible")]
+    OpenAICompatible,
+    /// Deterministic pseudo-grades without calling any model, for dry runs and tests
+    #[serde(rename = "mock")]
+    #[value(name = "mock")]
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "openai",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::OpenAICompatible => "openai-compatible",
+            ProviderKind::Mock => "mock",
+        }
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single prompt sent to a model provider
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub system: String,
+    pub prompt: String,
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub timeout: Duration,
+    /// Context window the prompt was budgeted for, requested from providers that let the
+    /// client choose it
+    pub context_tokens: usize,
+    /// Criteria the reply must score
+    pub rubric: Vec<Criterion>,
+    /// JSON schema the reply must follow, for providers with structured output
+    pub schema: Option<serde_json::Value>,
+    /// Earlier replies to `prompt` and the messages answering them, oldest first
+    pub follow_ups: Vec<FollowUp>,
+}
+
+/// A reply of the model and the next user message in a conversation
+#[derive(Debug, Clone)]
+pub struct FollowUp {
+    pub reply: String,
+    pub message: String,
+}
+
+/// Raw model output of a request
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub text: String,
+    /// Tokens consumed, when the API reports them
+    pub usage: Option<TokenUsage>,
+}
+
+/// Backend capable of turning a grading prompt into raw model output
+#[async_trait]
+pub trait ModelProvider: Send + Sync {
+    /// Provider kind used for logging and result provenance
+    fn kind(&self) -> ProviderKind;
+
+    /// Name of the model requests are sent to
+    fn model(&self) -> &str;
+
+    /// URL requests are sent to, empty for providers that call no server
+    fn endpoint(&self) -> &str;
+
+    /// Approximate token counter matching the model's tokenizer
+    fn token_estimator(&self) -> TokenEstimator;
+
+    /// Tokens the model attends to, prompt and reply together, when not configured
+    fn context_window(&self) -> usize;
+
+    /// Whether replies are made up without a model, so that grades are reported as mock
+    fn simulated(&self) -> bool {
+        false
+    }
+
+    /// Send the prompt to the model and return its raw text response
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion>;
+}
+
+/// Returned by providers when the endpoint could not be reached at all
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} endpoint {endpoint} is unavailable: {source}")]
+pub struct ProviderUnavailable {
+    pub provider: ProviderKind,
+    pub endpoint: String,
+    #[source]
+    pub source: reqwest::Error,
+}
+
+/// Returned by providers when the API answered with an error status
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} API error ({status}): {message}")]
+pub struct ApiError {
+    pub provider: ProviderKind,
+    pub status: u16,
+    pub message: String,
+    /// The request parameter the error is about, as OpenAI's `error.param` reports it
+    pub param: Option<String>,
+    /// Wait requested through `Retry-After` or rate limit headers
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    /// Whether the API refused the structured output `parameter` itself, as servers and
+    /// models without support for it do. The parameter must be the one the error reports or be
+    /// named in the message as `'parameter'` or `Request.parameter`, not merely mentioned
+    pub fn rejects_parameter(&self, parameter: &str) -> bool {
+        self.status == 400
+            && (self.param.as_deref() == Some(parameter) || names_field(&self.message, parameter))
+    }
+
+    /// Read the status, rate limit headers and error message of a failed response
+    pub async fn from_response(provider: ProviderKind, response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        Self {
+            provider,
+            status,
+            message: error_message(&body),
+            param: error_param(&body),
+            retry_after,
+        }
+    }
+}
+
+/// Whether a provider still sends the rubric schema with its requests; cleared for good once
+/// the server refuses the request parameter carrying it, as servers and models without
+/// structured output support do
+struct StructuredOutput {
+    supported: AtomicBool,
+    /// Names the refusal may give the parameter by
+    parameters: &'static [&'static str],
+}
+
+impl StructuredOutput {
+    fn new(parameters: &'static [&'static str]) -> Self {
+        Self {
+            supported: AtomicBool::new(true),
+            parameters,
+        }
+    }
+
+    /// Send the request built by `send` with the schema while the server accepts it, and
+    /// again without it when the server refuses it; returns the successful response
+    async fn send<'a, F, Fut>(
+        &self,
+        kind: ProviderKind,
+        model: &str,
+        request: &'a CompletionRequest,
+        send: F,
+    ) -> Result<reqwest::Response>
+    where
+        F: Fn(Option<&'a serde_json::Value>) -> Fut,
+        Fut: Future<Output = Result<reqwest::Response>>,
+    {
+        loop {
+            let schema = request
+                .schema
+                .as_ref()
+                .filter(|_| self.supported.load(Ordering::Relaxed));
+            let response = send(schema).await?;
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let error = ApiError::from_response(kind, response).await;
+            if schema.is_some()
+                && self
+                    .parameters
+                    .iter()
+                    .any(|parameter| error.rejects_parameter(parameter))
+            {
+                // Concurrent requests may all be refused, the switch is logged once
+                if self.supported.swap(false, Ordering::Relaxed) {
+                    tracing::warn!(
+                        "{} model {} does not support structured output, asking for plain JSON instead: {}",
+                        kind,
+                        model,
+                        error.message
+                    );
+                }
+                continue;
+            }
+            return Err(error.into());
+        }
+    }
+}
+
+/// The wait a server asks for: `retry-after-ms`, `Retry-After` (seconds or an HTTP date),
+/// or the later of OpenAI's `x-ratelimit-reset-requests` / `x-ratelimit-reset-tokens`
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        if let Some(wait) = seconds(ms / 1000.0) {
+            return Some(wait);
+        }
+    }
+    if let Some(value) = header("retry-after") {
+        let value = value.trim();
+        if let Ok(secs) = value.parse::<f64>() {
+            return seconds(secs);
+        }
+        if let Ok(date) = httpdate::parse_http_date(value) {
+            return Some(
+                date.duration_since(std::time::SystemTime::now())
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_reset))
+        .max()
+}
+
+/// Parse OpenAI reset durations such as `20ms`, `1.5s` or `6m0s`
+fn parse_reset(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        total += number.parse::<f64>().ok()?;
+    }
+    seconds(total)
+}
+
+/// A server-supplied number of seconds as a duration, `None` when it is not representable
+/// (`inf`, `NaN`, or beyond `Duration::MAX`) so that a bogus header cannot abort the run
+fn seconds(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs.max(0.0)).ok()
+}
+
+/// The message of an OpenAI (`{"error": {"message": ...}}`) or Ollama (`{"error": ...}`)
+/// error body, or the body itself
+fn error_message(body: &str) -> String {
+    let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    let error = parsed.as_ref().and_then(|json| json.get("error"));
+    let message = error
+        .and_then(|error| error.get("message").or(Some(error)))
+        .and_then(|message| message.as_str());
+    let code = error
+        .and_then(|error| error.get("code").or_else(|| error.get("type")))
+        .and_then(|code| code.as_str());
+    match (message, code) {
+        (Some(message), Some(code)) => format!("{} ({})", message, code),
+        (Some(message), None) => message.to_string(),
+        _ => body.trim().to_string(),
+    }
+}
+
+/// The `error.param` of an OpenAI error body
+fn error_param(body: &str) -> Option<String> {
+    let parsed: serde_json::Value = serde_json::from_str(body).ok()?;
+    parsed
+        .get("error")?
+        .get("param")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Whether `message` names `field` in quotes or as a struct field, as in
+/// `'response_format'` or Go's `GenerateRequest.format`
+fn names_field(message: &str, field: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let is_quote = |c: char| matches!(c, '\'' | '"' | '`');
+    message.match_indices(field).any(|(start, _)| {
+        let before = message[..start].chars().next_back();
+        let after = message[start + field.len()..].chars().next();
+        match before {
+            Some(c) if is_quote(c) => after == Some(c),
+            Some('.') => !after.is_some_and(is_ident),
+            _ => false,
+        }
+    })
+}
+
+/// Create the provider selected in the configuration
+pub fn build_provider(
+    kind: ProviderKind,
+    endpoint: Option<&str>,
+    model: Option<&str>,
+) -> Result<Box<dyn ModelProvider>> {
+    let provider: Box<dyn ModelProvider> = match kind {
+        ProviderKind::OpenAI => Box::new(OpenAIProvider::new(
+            endpoint.unwrap_or(DEFAULT_OPENAI_ENDPOINT),
+            model.unwrap_or(DEFAULT_OPENAI_MODEL),
+        )),
+        ProviderKind::Ollama => Box::new(OllamaProvider::new(
+            endpoint.unwrap_or(DEFAULT_OLLAMA_ENDPOINT),
+            model.unwrap_or(DEFAULT_OLLAMA_MODEL),
+        )),
+        ProviderKind::OpenAICompatible => {
+            let endpoint = endpoint.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The openai-compatible provider requires --model-endpoint (or ai.endpoint in config.toml)"
+                )
+            })?;
+            let model = model.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The openai-compatible provider requires --model (or ai.model in config.toml)"
+                )
+            })?;
+            Box::new(OpenAICompatibleProvider::new(endpoint, model))
+        }
+        ProviderKind::Mock => Box::new(MockProvider),
+    };
+
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bad_request(body: &str) -> ApiError {
+        ApiError {
+            provider: ProviderKind::OpenAI,
+            status: 400,
+            message: error_message(body),
+            param: error_param(body),
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn recognizes_refused_structured_output() {
+        let openai = bad_request(
+            r#"{"error": {"message": "Invalid parameter: 'response_format' of type 'json_schema' is not supported with this model.", "param": "response_format"}}"#,
+        );
+        assert!(openai.rejects_parameter("response_format"));
+        assert!(openai.rejects_parameter("json_schema"));
+
+        let ollama = bad_request(
+            r#"{"error": "json: cannot unmarshal object into Go struct field GenerateRequest.format of type string"}"#,
+        );
+        assert!(ollama.rejects_parameter("format"));
+    }
+
+    #[test]
+    fn ignores_errors_that_merely_mention_the_parameter() {
+        let error = bad_request(r#"{"error": "invalid format: the prompt is too long"}"#);
+        assert!(!error.rejects_parameter("format"));
+
+        let error =
+            bad_request(r#"{"error": {"message": "bad image_format.", "param": "messages"}}"#);
+        assert!(!error.rejects_parameter("format"));
+
+        let mut error = bad_request(r#"{"error": {"message": "x", "param": "response_format"}}"#);
+        error.status = 500;
+        assert!(!error.rejects_parameter("response_format"));
+    }
+
+    #[test]
+    fn parses_openai_reset_durations() {
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset("soon"), None);
+        assert_eq!(parse_reset(&format!("{}s", "9".repeat(30))), None);
+        assert_eq!(parse_reset(&format!("{}h", "9".repeat(400))), None);
+    }
+
+    #[test]
+    fn reads_the_requested_wait_from_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            map
+        };
+
+        assert_eq!(retry_after(&headers(&[])), None);
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "7")])),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            retry_after(&headers(&[("retry-after-ms", "250"), ("retry-after", "7")])),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            retry_after(&headers(&[
+                ("x-ratelimit-reset-requests", "2s"),
+                ("x-ratelimit-reset-tokens", "1m"),
+            ])),
+            Some(Duration::from_secs(60))
+        );
+        let past = httpdate::fmt_http_date(std::time::UNIX_EPOCH);
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", &past)])),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn ignores_waits_that_do_not_fit_a_duration() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        let header = |name: &'static str, value: &str| {
+            let mut map = HeaderMap::new();
+            map.insert(name, HeaderValue::from_str(value).unwrap());
+            map
+        };
+
+        assert_eq!(retry_after(&header("retry-after", "1e20")), None);
+        assert_eq!(retry_after(&header("retry-after", "inf")), None);
+        assert_eq!(
+            retry_after(&header("retry-after", "-inf")),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&header("retry-after-ms", "1e300")), None);
+        assert_eq!(
+            retry_after(&header("x-ratelimit-reset-tokens", &"9".repeat(40))),
+            None
+        );
+    }
+}
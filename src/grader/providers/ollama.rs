@@ -0,0 +1,122 @@
+use super::{
+    Completion, CompletionRequest, ModelProvider, ProviderKind, ProviderUnavailable,
+    StructuredOutput,
+};
+use crate::grader::context_budget::TokenEstimator;
+use crate::models::{OllamaOptions, OllamaRequest, OllamaResponse, TokenUsage};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// Llama and Qwen tokenizers split code into more tokens than OpenAI's
+const OLLAMA_TOKENS: TokenEstimator = TokenEstimator::new(3.0);
+/// Ollama's default `num_ctx`; prompts beyond it are cut from the start without an error
+const OLLAMA_CONTEXT: usize = 4096;
+
+/// Provider for a local Ollama server
+pub struct OllamaProvider {
+    client: Client,
+    endpoint: String,
+    model: String,
+    /// Off once the server rejects a schema as `format`, as releases before 0.5 do
+    structured_output: StructuredOutput,
+}
+
+impl OllamaProvider {
+    pub fn new(endpoint: &str, model: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            structured_output: StructuredOutput::new(&["format"]),
+        }
+    }
+}
+
+#[async_trait]
+impl ModelProvider for OllamaProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn token_estimator(&self) -> TokenEstimator {
+        OLLAMA_TOKENS
+    }
+
+    fn context_window(&self) -> usize {
+        OLLAMA_CONTEXT
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let url = format!("{}/api/generate", self.endpoint);
+        let url = url.as_str();
+        let response = self
+            .structured_output
+            .send(self.kind(), &self.model, request, |schema| async move {
+                let ollama_request = OllamaRequest {
+                    model: self.model.clone(),
+                    system: Some(request.system.clone()),
+                    prompt: prompt(request),
+                    stream: false,
+                    // Without it Ollama uses its own default, which may be smaller than the
+                    // window the prompt was budgeted for
+                    options: Some(OllamaOptions {
+                        num_ctx: request.context_tokens,
+                    }),
+                    format: schema.cloned(),
+                };
+
+                tracing::debug!("Sending request to Ollama: {}", url);
+
+                let response = self
+                    .client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .timeout(request.timeout)
+                    .json(&ollama_request)
+                    .send()
+                    .await
+                    .map_err(|source| ProviderUnavailable {
+                        provider: self.kind(),
+                        endpoint: url.to_string(),
+                        source,
+                    })?;
+                tracing::debug!("Ollama response status: {}", response.status());
+                Ok(response)
+            })
+            .await?;
+
+        let ollama_response: OllamaResponse = response.json().await?;
+        tracing::debug!("Ollama response received successfully");
+        let usage = ollama_response
+            .eval_count
+            .map(|completion_tokens| TokenUsage {
+                prompt_tokens: ollama_response.prompt_eval_count.unwrap_or_default(),
+                completion_tokens,
+            });
+        Ok(Completion {
+            text: ollama_response.response,
+            usage,
+        })
+    }
+}
+
+/// `/api/generate` takes a single prompt, so follow-ups are appended to it as a transcript
+fn prompt(request: &CompletionRequest) -> String {
+    let mut prompt = request.prompt.clone();
+    for follow_up in &request.follow_ups {
+        prompt.push_str(&format!(
+            "\n\nYour previous answer:\n{}\n\n{}",
+            follow_up.reply, follow_up.message
+        ));
+    }
+    prompt
+}
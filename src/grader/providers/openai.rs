@@ -0,0 +1,242 @@
+use super::{
+    Completion, CompletionRequest, ModelProvider, ProviderKind, ProviderUnavailable,
+    StructuredOutput,
+};
+use crate::grader::context_budget::TokenEstimator;
+use crate::models::{OpenAIMessage, OpenAIRequest, OpenAIResponse};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// OpenAI tokenizers average about four characters per token on English text, less on code
+const OPENAI_TOKENS: TokenEstimator = TokenEstimator::new(3.5);
+/// Unknown local models often use smaller vocabularies than OpenAI's
+const COMPATIBLE_TOKENS: TokenEstimator = TokenEstimator::new(3.0);
+/// Context assumed for OpenAI-compatible servers, whose model is unknown
+const COMPATIBLE_CONTEXT: usize = 8192;
+
+/// Names OpenAI and compatible servers give the structured output parameter when refusing it
+const STRUCTURED_OUTPUT_PARAMETERS: &[&str] = &["response_format", "json_schema"];
+
+/// Context windows of OpenAI models by name prefix, most specific first
+const OPENAI_CONTEXT: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_000_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-5", 400_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+];
+
+/// Provider for the hosted OpenAI chat completions API
+pub struct OpenAIProvider {
+    client: Client,
+    endpoint: String,
+    model: String,
+    /// Off once the model rejects `response_format`, e.g. older models
+    structured_output: StructuredOutput,
+}
+
+impl OpenAIProvider {
+    pub fn new(endpoint: &str, model: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.to_string(),
+            model: model.to_string(),
+            structured_output: StructuredOutput::new(STRUCTURED_OUTPUT_PARAMETERS),
+        }
+    }
+}
+
+#[async_trait]
+impl ModelProvider for OpenAIProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAI
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn token_estimator(&self) -> TokenEstimator {
+        OPENAI_TOKENS
+    }
+
+    fn context_window(&self) -> usize {
+        OPENAI_CONTEXT
+            .iter()
+            .find(|(prefix, _)| self.model.starts_with(prefix))
+            .map_or(COMPATIBLE_CONTEXT, |(_, tokens)| *tokens)
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let api_key = std::env::var("OPENAI_API_KEY").unwrap_or_default();
+        send_chat_completion(
+            &self.client,
+            self.kind(),
+            &self.endpoint,
+            Some(&api_key),
+            &self.model,
+            request,
+            &self.structured_output,
+        )
+        .await
+    }
+}
+
+/// Provider for self-hosted servers that speak the OpenAI chat completions protocol
+pub struct OpenAICompatibleProvider {
+    client: Client,
+    endpoint: String,
+    model: String,
+    /// Off once the server rejects `response_format`
+    structured_output: StructuredOutput,
+}
+
+impl OpenAICompatibleProvider {
+    /// Accepts either the full `/chat/completions` URL or the server base URL
+    pub fn new(endpoint: &str, model: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let endpoint = if endpoint.ends_with("/chat/completions") {
+            endpoint.to_string()
+        } else if endpoint.ends_with("/v1") {
+            format!("{}/chat/completions", endpoint)
+        } else {
+            format!("{}/v1/chat/completions", endpoint)
+        };
+
+        Self {
+            client: Client::new(),
+            endpoint,
+            model: model.to_string(),
+            structured_output: StructuredOutput::new(STRUCTURED_OUTPUT_PARAMETERS),
+        }
+    }
+}
+
+#[async_trait]
+impl ModelProvider for OpenAICompatibleProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAICompatible
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn token_estimator(&self) -> TokenEstimator {
+        COMPATIBLE_TOKENS
+    }
+
+    fn context_window(&self) -> usize {
+        COMPATIBLE_CONTEXT
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        // Most local servers ignore authentication, proxies usually require it
+        let api_key = std::env::var("OPENAI_API_KEY").ok();
+        send_chat_completion(
+            &self.client,
+            self.kind(),
+            &self.endpoint,
+            api_key.as_deref(),
+            &self.model,
+            request,
+            &self.structured_output,
+        )
+        .await
+    }
+}
+
+async fn send_chat_completion(
+    client: &Client,
+    kind: ProviderKind,
+    endpoint: &str,
+    api_key: Option<&str>,
+    model: &str,
+    request: &CompletionRequest,
+    structured_output: &StructuredOutput,
+) -> Result<Completion> {
+    let response = structured_output
+        .send(kind, model, request, |schema| async move {
+            let mut messages = vec![
+                OpenAIMessage {
+                    role: "system".to_string(),
+                    content: request.system.clone(),
+                },
+                OpenAIMessage {
+                    role: "user".to_string(),
+                    content: request.prompt.clone(),
+                },
+            ];
+            for follow_up in &request.follow_ups {
+                messages.push(OpenAIMessage {
+                    role: "assistant".to_string(),
+                    content: follow_up.reply.clone(),
+                });
+                messages.push(OpenAIMessage {
+                    role: "user".to_string(),
+                    content: follow_up.message.clone(),
+                });
+            }
+            let openai_request = OpenAIRequest {
+                model: model.to_string(),
+                messages,
+                temperature: request.temperature,
+                max_tokens: request.max_tokens,
+                response_format: schema.map(|schema| {
+                    serde_json::json!({
+                        "type": "json_schema",
+                        "json_schema": { "name": "grading", "strict": true, "schema": schema },
+                    })
+                }),
+            };
+
+            let mut http_request = client
+                .post(endpoint)
+                .header("Content-Type", "application/json")
+                .timeout(request.timeout)
+                .json(&openai_request);
+            if let Some(api_key) = api_key {
+                http_request = http_request.header("Authorization", format!("Bearer {}", api_key));
+            }
+
+            let response = http_request
+                .send()
+                .await
+                .map_err(|source| ProviderUnavailable {
+                    provider: kind,
+                    endpoint: endpoint.to_string(),
+                    source,
+                })?;
+            Ok(response)
+        })
+        .await?;
+
+    let openai_response: OpenAIResponse = response.json().await?;
+    let text = openai_response
+        .choices
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No response from {}", kind))?
+        .message
+        .content
+        .clone();
+
+    Ok(Completion {
+        text,
+        usage: openai_response.usage,
+    })
+}
@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// Client-side pacing of model requests by requests and tokens per minute, shared by all
+/// concurrent workers so that the provider's limits are not hit in the first place
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+/// Budget that refills continuously up to one minute's worth
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    per_second: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(limit: u32) -> Self {
+        let capacity = f64::from(limit);
+        Self {
+            capacity,
+            available: capacity,
+            per_second: capacity / 60.0,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until `amount` is available; amounts above the capacity wait for a full bucket
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_second)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+impl RateLimiter {
+    /// Limiter for the configured budgets, `None` when neither is set
+    pub fn new(requests_per_minute: Option<u32>, tokens_per_minute: Option<u32>) -> Option<Self> {
+        if requests_per_minute.is_none() && tokens_per_minute.is_none() {
+            return None;
+        }
+        Some(Self {
+            buckets: Mutex::new(Buckets {
+                requests: requests_per_minute.map(TokenBucket::per_minute),
+                tokens: tokens_per_minute.map(TokenBucket::per_minute),
+            }),
+        })
+    }
+
+    /// Wait until one request using `tokens` tokens fits into both budgets, then spend them.
+    /// Waiters are served in arrival order, the lock being held while waiting
+    pub async fn acquire(&self, tokens: usize) {
+        let mut buckets = self.buckets.lock().await;
+        let tokens = tokens as f64;
+        loop {
+            let now = Instant::now();
+            let Buckets {
+                requests,
+                tokens: token_bucket,
+            } = &mut *buckets;
+            for bucket in [requests.as_mut(), token_bucket.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                bucket.refill(now);
+            }
+
+            let wait = requests
+                .as_ref()
+                .map_or(Duration::ZERO, |bucket| bucket.wait_for(1.0))
+                .max(
+                    token_bucket
+                        .as_ref()
+                        .map_or(Duration::ZERO, |bucket| bucket.wait_for(tokens)),
+                );
+            if wait.is_zero() {
+                if let Some(bucket) = requests {
+                    bucket.take(1.0);
+                }
+                if let Some(bucket) = token_bucket {
+                    bucket.take(tokens);
+                }
+                return;
+            }
+
+            info!(
+                "Waiting {:.1}s to stay within the rate limit",
+                wait.as_secs_f32()
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_continuously_up_to_its_capacity() {
+        let mut bucket = TokenBucket::per_minute(60);
+        let start = bucket.updated;
+        bucket.take(60.0);
+        assert_eq!(bucket.wait_for(1.0), Duration::from_secs(1));
+
+        bucket.refill(start + Duration::from_millis(500));
+        assert_eq!(bucket.wait_for(1.0), Duration::from_millis(500));
+
+        bucket.refill(start + Duration::from_secs(600));
+        assert_eq!(bucket.available, 60.0);
+        assert_eq!(bucket.wait_for(60.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn oversized_amounts_wait_for_a_full_bucket() {
+        let mut bucket = TokenBucket::per_minute(600);
+        assert_eq!(bucket.wait_for(10_000.0), Duration::ZERO);
+        bucket.take(10_000.0);
+        assert_eq!(bucket.available, 0.0);
+        assert_eq!(bucket.wait_for(10_000.0), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn no_budget_means_no_limiter() {
+        assert!(RateLimiter::new(None, None).is_none());
+        assert!(RateLimiter::new(Some(60), None).is_some());
+    }
+
+    #[tokio::test]
+    async fn waits_until_the_tokens_are_available() {
+        // 100 tokens per second
+        let limiter = RateLimiter::new(None, Some(6000)).unwrap();
+        let start = Instant::now();
+        limiter.acquire(6000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        limiter.acquire(20).await;
+        let waited = start.elapsed();
+        assert!(
+            waited >= Duration::from_millis(180) && waited < Duration::from_secs(2),
+            "{:?}",
+            waited
+        );
+    }
+}
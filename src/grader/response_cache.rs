@@ -0,0 +1,184 @@
+use crate::grader::providers::{Completion, CompletionRequest, ModelProvider, ProviderKind};
+use crate::models::TokenUsage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default directory for cached model responses
+pub const DEFAULT_CACHE_DIR: &str = ".paper-sage-cache";
+
+/// How the cache participates in a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Return stored responses and store new ones
+    ReadWrite,
+    /// Ignore stored responses but overwrite them with fresh ones
+    Refresh,
+}
+
+/// On-disk cache of raw model responses, one JSON file per request hash
+pub struct ResponseCache {
+    dir: PathBuf,
+    mode: CacheMode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    provider: String,
+    model: String,
+    temperature: f32,
+    created_at: u64,
+    response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<TokenUsage>,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>, mode: CacheMode) -> Self {
+        Self {
+            dir: dir.into(),
+            mode,
+        }
+    }
+
+    /// Hash of everything that influences the model output for a request
+    pub fn key(provider: &dyn ModelProvider, request: &CompletionRequest) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            provider.kind().as_str(),
+            provider.endpoint(),
+            provider.model(),
+            &request.temperature.to_string(),
+            &request.max_tokens.to_string(),
+            &request.context_tokens.to_string(),
+            &request.system,
+            &request.prompt,
+        ] {
+            hasher.update(part.as_bytes());
+            // Separator so that adjacent fields cannot run into each other
+            hasher.update([0u8]);
+        }
+        if let Some(schema) = &request.schema {
+            hasher.update(schema.to_string().as_bytes());
+        }
+        for follow_up in &request.follow_ups {
+            for part in [&follow_up.reply, &follow_up.message] {
+                hasher.update([0u8]);
+                hasher.update(part.as_bytes());
+            }
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Look up a stored response; always misses in refresh mode
+    pub fn get(&self, key: &str) -> Option<Completion> {
+        if self.mode == CacheMode::Refresh {
+            return None;
+        }
+
+        let path = self.entry_path(key);
+        let content = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<CacheEntry>(&content) {
+            Ok(entry) => Some(Completion {
+                text: entry.response,
+                usage: entry.usage,
+            }),
+            Err(e) => {
+                tracing::warn!("Ignoring corrupt cache entry {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Store a raw response and its token usage under `key`
+    pub fn put(
+        &self,
+        key: &str,
+        provider: ProviderKind,
+        model: &str,
+        temperature: f32,
+        completion: &Completion,
+    ) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory: {}", self.dir.display()))?;
+
+        let entry = CacheEntry {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            temperature,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            response: completion.text.clone(),
+            usage: completion.usage,
+        };
+
+        let path = self.entry_path(key);
+        std::fs::write(&path, serde_json::to_string_pretty(&entry)?)
+            .with_context(|| format!("Failed to write cache entry: {}", path.display()))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grader::providers::{FollowUp, OllamaProvider};
+    use std::time::Duration;
+
+    fn request() -> CompletionRequest {
+        CompletionRequest {
+            system: "system".to_string(),
+            prompt: "prompt".to_string(),
+            temperature: 0.1,
+            max_tokens: 1000,
+            timeout: Duration::from_secs(60),
+            context_tokens: 4096,
+            rubric: Vec::new(),
+            schema: None,
+            follow_ups: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn key_covers_everything_that_shapes_the_reply() {
+        let local = OllamaProvider::new("http://localhost:11434", "m");
+        let remote = OllamaProvider::new("http://gpu-box:11434", "m");
+        let key = ResponseCache::key(&local, &request());
+
+        assert_eq!(key, ResponseCache::key(&local, &request()));
+        assert_ne!(key, ResponseCache::key(&remote, &request()));
+
+        let mut longer = request();
+        longer.max_tokens = 2000;
+        assert_ne!(key, ResponseCache::key(&local, &longer));
+
+        let mut repaired = request();
+        repaired.follow_ups.push(FollowUp {
+            reply: "{}".to_string(),
+            message: "fix it".to_string(),
+        });
+        assert_ne!(key, ResponseCache::key(&local, &repaired));
+    }
+
+    #[test]
+    fn fields_cannot_run_into_each_other() {
+        let provider = OllamaProvider::new("http://localhost:11434", "m");
+        let mut first = request();
+        first.context_tokens = 40;
+        first.system = "96system".to_string();
+        let mut second = request();
+        second.context_tokens = 4096;
+        second.system = "system".to_string();
+        assert_ne!(
+            ResponseCache::key(&provider, &first),
+            ResponseCache::key(&provider, &second)
+        );
+    }
+}
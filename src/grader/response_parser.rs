@@ -1,37 +1,409 @@
-use crate::models::{GradingResponse, GradingResult, GradingStrategy};
-use anyhow::{Context, Result};
-use serde_json;
+use crate::models::{Criterion, GradingResponse, GradingResult, GradingStatus, ScoreScale};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 
-/// Parse AI model responses into grading results
+/// Start positions tried when looking for JSON objects in a response
+const MAX_CANDIDATES: usize = 256;
+
+/// Why a reply is not a usable grade, phrased so that it can be sent back to the model
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Invalid grading response: {}", .problems.join("; "))]
+pub struct InvalidResponse {
+    pub problems: Vec<String>,
+    /// The grade with out of range scores clamped into range, when that is all that is wrong
+    pub clamped: Option<Box<GradingResult>>,
+}
+
+impl InvalidResponse {
+    fn new(problem: impl Into<String>) -> Self {
+        Self {
+            problems: vec![problem.into()],
+            clamped: None,
+        }
+    }
+}
+
+/// JSON schema of a grading response for `rubric`, for providers that can constrain their
+/// output to it. Every property is required and no others are allowed, as OpenAI's strict
+/// mode demands
+pub fn response_schema(rubric: &[Criterion]) -> Value {
+    let scores: serde_json::Map<String, Value> = rubric
+        .iter()
+        .map(|criterion| {
+            (
+                criterion.id.clone(),
+                json!({
+                    "type": "number",
+                    "minimum": 0,
+                    "maximum": criterion.max_points,
+                }),
+            )
+        })
+        .collect();
+    let ids: Vec<&str> = rubric
+        .iter()
+        .map(|criterion| criterion.id.as_str())
+        .collect();
+
+    json!({
+        "type": "object",
+        "properties": {
+            "scores": {
+                "type": "object",
+                "properties": scores,
+                "required": ids,
+                "additionalProperties": false,
+            },
+            "comment": { "type": "string" },
+        },
+        "required": ["scores", "comment"],
+        "additionalProperties": false,
+    })
+}
+
+/// Parse AI model responses into grading results. Models without structured output wrap
+/// their JSON in prose or code fences, so every JSON object in the response is tried, fenced
+/// `json` blocks first, until one holds a score within range for each criterion
 pub fn parse_grading_response(
     response: &str,
-    _filename: &str,
-    strategy: &GradingStrategy,
-) -> Result<GradingResult> {
-    // Try to extract JSON from the response
-    let json_start = response.find('{');
-    let json_end = response.rfind('}');
-
-    let json_text = if let (Some(start), Some(end)) = (json_start, json_end) {
-        &response[start..=end]
-    } else {
-        anyhow::bail!("No valid JSON found in response");
-    };
-
-    let grading_response: GradingResponse = serde_json::from_str(json_text)
-        .with_context(|| format!("Failed to parse grading response: {}", json_text))?;
-
-    // Calculate total score using the grading strategy
-    let total = grading_response.correctness * strategy.correctness_weight
-        + grading_response.style * strategy.style_weight
-        + grading_response.edge_cases * strategy.edge_cases_weight;
-
-    Ok(GradingResult {
-        filename: grading_response.filename,
-        correctness: grading_response.correctness,
-        style: grading_response.style,
-        edge_cases: grading_response.edge_cases,
+    filename: &str,
+    rubric: &[Criterion],
+) -> Result<GradingResult, InvalidResponse> {
+    let candidates = json_candidates(response);
+    if candidates.is_empty() {
+        return Err(InvalidResponse::new("the reply contains no JSON object"));
+    }
+
+    // A candidate that is valid JSON but not a complete grade explains more than a syntax
+    // error, and among syntax errors the longest candidate is most likely the intended grade
+    let mut incomplete = None;
+    let mut malformed: Option<(usize, InvalidResponse)> = None;
+    for candidate in &candidates {
+        let grading_response = match parse_json(candidate) {
+            Ok(grading_response) => grading_response,
+            Err(e) => {
+                if malformed
+                    .as_ref()
+                    .is_none_or(|(len, _)| candidate.len() > *len)
+                {
+                    malformed = Some((candidate.len(), e));
+                }
+                continue;
+            }
+        };
+        let (scores, problems, clampable) = collect_scores(&grading_response, rubric);
+        if problems.is_empty() {
+            return Ok(grade(filename, scores, grading_response.comment, rubric));
+        }
+        incomplete.get_or_insert_with(|| InvalidResponse {
+            problems,
+            clamped: clampable
+                .then(|| Box::new(grade(filename, scores, grading_response.comment, rubric))),
+        });
+    }
+
+    Err(incomplete
+        .or(malformed.map(|(_, e)| e))
+        .unwrap_or_else(|| InvalidResponse::new("the reply contains no JSON object")))
+}
+
+fn grade(
+    filename: &str,
+    scores: BTreeMap<String, f32>,
+    comment: String,
+    rubric: &[Criterion],
+) -> GradingResult {
+    let total = calculate_total(&scores, rubric);
+    GradingResult {
+        filename: filename.to_string(),
+        submission_id: String::new(),
+        student_id: None,
+        section: None,
+        status: GradingStatus::Graded,
+        scores,
         total,
-        comment: grading_response.comment,
-    })
+        out_of: None,
+        comment,
+        model: None,
+        tests: None,
+        notes: Vec::new(),
+        metadata: BTreeMap::new(),
+        provenance: None,
+    }
+}
+
+/// One score per rubric criterion, accepting scores emitted at the top level. Scores outside
+/// `0..=max_points` are clamped into range; every missing or out of range score is reported,
+/// and the clamped scores are usable when no score is missing
+fn collect_scores(
+    grading_response: &GradingResponse,
+    rubric: &[Criterion],
+) -> (BTreeMap<String, f32>, Vec<String>, bool) {
+    let mut scores = BTreeMap::new();
+    let mut problems = Vec::new();
+    let mut clampable = true;
+    for criterion in rubric {
+        let score = grading_response
+            .scores
+            .get(&criterion.id)
+            .copied()
+            .or_else(|| {
+                grading_response
+                    .extra
+                    .get(&criterion.id)
+                    .and_then(|value| value.as_f64())
+                    .map(|value| value as f32)
+            });
+        match score {
+            Some(score) if (0.0..=criterion.max_points).contains(&score) => {
+                scores.insert(criterion.id.clone(), score);
+            }
+            Some(score) => {
+                problems.push(format!(
+                    "the score for '{}' is {}, outside the allowed range 0-{}",
+                    criterion.id, score, criterion.max_points
+                ));
+                scores.insert(criterion.id.clone(), score.clamp(0.0, criterion.max_points));
+            }
+            None => {
+                problems.push(format!("the score for '{}' is missing", criterion.id));
+                clampable = false;
+            }
+        }
+    }
+    (scores, problems, clampable)
+}
+
+/// Parse one candidate, dropping trailing commas if it does not parse as is
+fn parse_json(candidate: &str) -> Result<GradingResponse, InvalidResponse> {
+    serde_json::from_str(candidate)
+        .or_else(|e| serde_json::from_str(&strip_trailing_commas(candidate)).map_err(|_| e))
+        .map_err(|e| InvalidResponse::new(format!("the JSON is malformed: {}", e)))
+}
+
+/// Text that may hold the grade, most likely first: fenced `json` blocks, then every
+/// balanced `{...}` in order of its opening brace, outer objects before the ones they contain
+fn json_candidates(response: &str) -> Vec<&str> {
+    let mut candidates: Vec<&str> = fenced_blocks(response)
+        .into_iter()
+        .map(str::trim)
+        .filter(|block| block.starts_with('{'))
+        .collect();
+
+    for (start, _) in response.match_indices('{').take(MAX_CANDIDATES) {
+        if let Some(object) = balanced_object(&response[start..]) {
+            if !candidates.contains(&object) {
+                candidates.push(object);
+            }
+        }
+    }
+    candidates
+}
+
+/// Contents of the ```json (or unlabelled) code fences in `text`
+fn fenced_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find("```") {
+        let after = &rest[open + 3..];
+        let Some(line_end) = after.find('\n') else {
+            break;
+        };
+        let label = after[..line_end].trim().to_lowercase();
+        let body = &after[line_end + 1..];
+        let Some(close) = body.find("```") else {
+            break;
+        };
+        if label.is_empty() || label == "json" || label == "jsonc" {
+            blocks.push(&body[..close]);
+        }
+        rest = &body[close + 3..];
+    }
+    blocks
+}
+
+/// The `{...}` object `text` starts with, matching braces outside of strings
+fn balanced_object(text: &str) -> Option<&str> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (offset, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(&text[..=offset]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Remove commas directly before a closing brace or bracket, outside of strings
+fn strip_trailing_commas(json: &str) -> String {
+    let mut output = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (offset, c) in json.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next = json[offset + 1..].trim_start().chars().next();
+            if matches!(next, Some('}') | Some(']')) {
+                continue;
+            }
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// Express a grade on the assignment's scale: scores rounded to the step (never above the
+/// criterion's maximum) and the total out of the scale's total
+pub fn apply_scale(result: &mut GradingResult, rubric: &[Criterion], scale: &ScoreScale) {
+    for criterion in rubric {
+        if let Some(score) = result.scores.get_mut(&criterion.id) {
+            *score = scale.round(*score).clamp(0.0, criterion.max_points);
+        }
+    }
+    let percent = calculate_total(&result.scores, rubric);
+    // Weights only need to sum to 1.0 within a tolerance, so a full grade can land a
+    // little above the scale's total
+    result.total = scale
+        .round(scale.from_percent(percent))
+        .clamp(0.0, scale.total);
+    result.out_of = Some(scale.total);
+}
+
+/// Weighted total as a percentage, each criterion contributing `weight * score / max_points`
+pub fn calculate_total(scores: &BTreeMap<String, f32>, rubric: &[Criterion]) -> f32 {
+    rubric
+        .iter()
+        .map(|criterion| {
+            let score = scores.get(&criterion.id).copied().unwrap_or(0.0);
+            criterion.weight * score / criterion.max_points * 100.0
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rubric() -> Vec<Criterion> {
+        [("correctness", 40.0, 0.8), ("style", 10.0, 0.2)]
+            .into_iter()
+            .map(|(id, max_points, weight)| Criterion {
+                id: id.to_string(),
+                description: format!("{} of the solution", id),
+                max_points,
+                weight,
+            })
+            .collect()
+    }
+
+    fn parse(response: &str) -> Result<GradingResult, InvalidResponse> {
+        parse_grading_response(response, "alice/", &rubric())
+    }
+
+    #[test]
+    fn parses_a_plain_grade() {
+        let result =
+            parse(r#"{"scores": {"correctness": 30, "style": 5}, "comment": "Good"}"#).unwrap();
+        assert_eq!(result.filename, "alice/");
+        assert_eq!(result.scores["correctness"], 30.0);
+        assert_eq!(result.comment, "Good");
+        assert_eq!(result.total, 70.0);
+        assert_eq!(result.status, GradingStatus::Graded);
+    }
+
+    #[test]
+    fn finds_the_grade_in_prose_and_fences() {
+        let fenced = "Here is my evaluation:\n```json\n{\"scores\": {\"correctness\": 40, \"style\": 10}, \"comment\": \"Uses {braces} well\"}\n```\nThanks!";
+        assert_eq!(parse(fenced).unwrap().total, 100.0);
+
+        let inline = r#"I think {"note": "not it"} and then {"scores": {"correctness": 20, "style": 10}, "comment": "ok"} is fair."#;
+        assert_eq!(parse(inline).unwrap().total, 60.0);
+    }
+
+    #[test]
+    fn tolerates_trailing_commas_and_top_level_scores() {
+        let result = parse(r#"{"correctness": 40, "style": 0, "comment": "x",}"#).unwrap();
+        assert_eq!(result.scores["correctness"], 40.0);
+        assert_eq!(result.total, 80.0);
+    }
+
+    #[test]
+    fn reports_missing_and_out_of_range_scores() {
+        let missing = parse(r#"{"scores": {"correctness": 30}, "comment": ""}"#).unwrap_err();
+        assert_eq!(missing.problems, vec!["the score for 'style' is missing"]);
+        assert!(missing.clamped.is_none());
+
+        let over =
+            parse(r#"{"scores": {"correctness": 45, "style": -1}, "comment": ""}"#).unwrap_err();
+        assert_eq!(over.problems.len(), 2);
+        let clamped = over.clamped.unwrap();
+        assert_eq!(clamped.scores["correctness"], 40.0);
+        assert_eq!(clamped.scores["style"], 0.0);
+    }
+
+    #[test]
+    fn explains_replies_without_a_grade() {
+        let none = parse("I cannot grade this.").unwrap_err();
+        assert_eq!(none.problems, vec!["the reply contains no JSON object"]);
+
+        let malformed = parse(r#"{"scores": {"correctness": 30 "style": 5}}"#).unwrap_err();
+        assert!(malformed.problems[0].starts_with("the JSON is malformed"));
+    }
+
+    #[test]
+    fn keeps_the_scaled_total_within_the_scale() {
+        let mut rubric = rubric();
+        rubric[0].weight = 0.81;
+        let mut result = parse_grading_response(
+            r#"{"scores": {"correctness": 40, "style": 10}}"#,
+            "alice/",
+            &rubric,
+        )
+        .unwrap();
+        let scale: ScoreScale = serde_json::from_value(json!({"total": 20})).unwrap();
+
+        apply_scale(&mut result, &rubric, &scale);
+        assert_eq!(result.total, 20.0);
+        assert_eq!(result.out_of, Some(20.0));
+    }
+
+    #[test]
+    fn schema_requires_every_criterion() {
+        let schema = response_schema(&rubric());
+        assert_eq!(
+            schema["properties"]["scores"]["required"],
+            json!(["correctness", "style"])
+        );
+        assert_eq!(
+            schema["properties"]["scores"]["properties"]["style"]["maximum"],
+            json!(10.0)
+        );
+        assert_eq!(schema["required"], json!(["scores", "comment"]));
+    }
 }
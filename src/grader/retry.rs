@@ -0,0 +1,277 @@
+use crate::config::RetryConfig;
+use crate::grader::providers::{ApiError, ProviderUnavailable};
+use anyhow::Result;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Server-requested waits longer than this (daily limits) are not waited out
+const MAX_SERVER_WAIT: Duration = Duration::from_secs(600);
+
+/// How a failed model request should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Transient: network errors, timeouts, rate limits and server errors
+    Retryable,
+    /// Affects every request of the run: rejected API key, unknown model or endpoint, exhausted quota
+    Fatal,
+    /// Specific to this request, e.g. an unparseable response or a prompt the API refuses
+    Failed,
+}
+
+impl ErrorClass {
+    /// Classify an error by the provider error anywhere in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(unavailable) = cause.downcast_ref::<ProviderUnavailable>() {
+                // A malformed endpoint URL never starts working
+                return if unavailable.source.is_builder() {
+                    ErrorClass::Fatal
+                } else {
+                    ErrorClass::Retryable
+                };
+            }
+            if let Some(api) = cause.downcast_ref::<ApiError>() {
+                return Self::of_api_error(api);
+            }
+            // The connection dropped or timed out while the response body was read
+            if let Some(http) = cause.downcast_ref::<reqwest::Error>() {
+                if http.is_timeout() || http.is_connect() || http.is_body() {
+                    return ErrorClass::Retryable;
+                }
+            }
+        }
+        ErrorClass::Failed
+    }
+
+    fn of_api_error(error: &ApiError) -> Self {
+        let message = error.message.to_lowercase();
+        match error.status {
+            // OpenAI reports an exhausted quota with the rate limit status
+            429 if message.contains("quota") || message.contains("billing") => ErrorClass::Fatal,
+            429 if error.retry_after.is_some_and(|wait| wait > MAX_SERVER_WAIT) => {
+                ErrorClass::Fatal
+            }
+            408 | 409 | 425 | 429 => ErrorClass::Retryable,
+            401 | 403 | 404 => ErrorClass::Fatal,
+            400 if message.contains("model")
+                && (message.contains("does not exist")
+                    || message.contains("not found")
+                    || message.contains("invalid model")) =>
+            {
+                ErrorClass::Fatal
+            }
+            501 | 505 => ErrorClass::Fatal,
+            500..=599 => ErrorClass::Retryable,
+            _ => ErrorClass::Failed,
+        }
+    }
+}
+
+/// Exponential backoff with jitter for model requests, shared by all providers
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Policy from `[ai.retry]`, with defaults for anything not configured
+    pub fn new(config: Option<&RetryConfig>) -> Self {
+        Self {
+            max_attempts: config
+                .and_then(|cfg| cfg.max_attempts)
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+                .max(1),
+            initial_delay: config
+                .and_then(|cfg| cfg.initial_delay_ms)
+                .map_or(DEFAULT_INITIAL_DELAY, Duration::from_millis),
+            max_delay: config
+                .and_then(|cfg| cfg.max_delay_secs)
+                .map_or(DEFAULT_MAX_DELAY, Duration::from_secs),
+        }
+    }
+
+    /// Run `operation` until it succeeds, fails with an error that is not retryable,
+    /// or runs out of attempts
+    pub async fn run<T, F, Fut>(&self, label: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if ErrorClass::of(&error) != ErrorClass::Retryable {
+                return Err(error);
+            }
+            if attempt >= self.max_attempts {
+                warn!("{} failed {} times, giving up", label, attempt);
+                return Err(error);
+            }
+
+            let delay = self.delay(attempt, server_wait(&error));
+            warn!(
+                "{} failed (attempt {}/{}), retrying in {:.1}s: {:#}",
+                label,
+                attempt,
+                self.max_attempts,
+                delay.as_secs_f32(),
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Wait before the next attempt: the initial delay doubled per attempt up to the maximum,
+    /// randomised between half and the full value so that parallel workers spread out.
+    /// A wait requested by the server is never shortened
+    fn delay(&self, attempt: u32, server_wait: Option<Duration>) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        let jittered = backoff.mul_f64(0.5 + 0.5 * random_fraction());
+        server_wait.map_or(jittered, |wait| wait.max(jittered))
+    }
+}
+
+fn server_wait(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ApiError>())
+        .and_then(|api| api.retry_after)
+}
+
+/// A number in `[0, 1)` from the randomly seeded std hasher, which is enough for jitter
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64,
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grader::providers::ProviderKind;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn api_error(status: u16, message: &str, retry_after: Option<Duration>) -> anyhow::Error {
+        ApiError {
+            provider: ProviderKind::OpenAI,
+            status,
+            message: message.to_string(),
+            param: None,
+            retry_after,
+        }
+        .into()
+    }
+
+    fn class(status: u16, message: &str) -> ErrorClass {
+        ErrorClass::of(&api_error(status, message, None))
+    }
+
+    #[test]
+    fn classifies_api_errors() {
+        assert_eq!(class(429, "Rate limit reached"), ErrorClass::Retryable);
+        assert_eq!(class(503, "overloaded"), ErrorClass::Retryable);
+        assert_eq!(
+            class(429, "You exceeded your current quota"),
+            ErrorClass::Fatal
+        );
+        assert_eq!(class(401, "Incorrect API key provided"), ErrorClass::Fatal);
+        assert_eq!(class(404, "Not found"), ErrorClass::Fatal);
+        assert_eq!(
+            class(400, "The model `gpt-9` does not exist"),
+            ErrorClass::Fatal
+        );
+        assert_eq!(class(400, "context too long"), ErrorClass::Failed);
+
+        let daily = api_error(429, "Rate limit", Some(Duration::from_secs(3600)));
+        assert_eq!(ErrorClass::of(&daily), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn classifies_through_the_error_chain() {
+        let error = api_error(500, "oops", None).context("Failed to grade alice/");
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Retryable);
+        assert_eq!(
+            ErrorClass::of(&anyhow::anyhow!("unparseable reply")),
+            ErrorClass::Failed
+        );
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_jitter() {
+        let policy = RetryPolicy::new(Some(&RetryConfig {
+            max_attempts: Some(5),
+            initial_delay_ms: Some(1000),
+            max_delay_secs: Some(5),
+        }));
+        for _ in 0..20 {
+            let first = policy.delay(1, None);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+            let third = policy.delay(3, None);
+            assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+            assert!(policy.delay(10, None) <= Duration::from_secs(5));
+        }
+        let requested = Duration::from_secs(30);
+        assert_eq!(policy.delay(1, Some(requested)), requested);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_only() {
+        let policy = RetryPolicy::new(Some(&RetryConfig {
+            max_attempts: Some(3),
+            initial_delay_ms: Some(1),
+            max_delay_secs: None,
+        }));
+
+        let calls = AtomicU32::new(0);
+        let value = policy
+            .run("test", || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(api_error(502, "bad gateway", None)),
+                    _ => Ok(42),
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!((value, calls.load(Ordering::SeqCst)), (42, 3));
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = policy
+            .run("test", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(api_error(401, "Incorrect API key provided", None))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = policy
+            .run("test", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(api_error(503, "unavailable", None))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,20 +1,24 @@
 //! Paper Sage - AI-powered student submission grader
 //!
 //! This library provides functionality for automatically grading student programming submissions
-//! using AI models. It supports multiple file formats and can work with both OpenAI and local LLMs.
+//! using AI models. It supports multiple file formats and can work with OpenAI, Ollama and any
+//! OpenAI-compatible server through pluggable model providers.
 
 pub mod config;
 pub mod excel_generator;
+pub mod executor;
 pub mod file_processor;
 pub mod grader;
 pub mod models;
+pub mod roster;
 
 // Re-export main types for easier access
 pub use excel_generator::ExcelGenerator;
 pub use file_processor::FileProcessor;
-pub use grader::Grader;
+pub use grader::{Grader, GradingStopped, ModelProvider, ProviderKind};
 pub use models::Config;
 pub use models::{FileContent, GradingRequest, GradingResponse, GradingResult, StudentSubmission};
+pub use roster::{Roster, RosterEntry};
 
 #[allow(dead_code)]
 /// Main application struct that orchestrates the grading process
@@ -24,20 +28,46 @@ pub struct PaperSage {
     file_processor: FileProcessor,
     grader: Grader,
     excel_generator: ExcelGenerator,
+    roster: Option<Roster>,
 }
 
 impl PaperSage {
     /// Create a new PaperSage instance
-    pub fn new(config_path: &str, model_endpoint: Option<String>) -> anyhow::Result<Self> {
+    pub fn new(config_path: &str, overrides: config::Overrides) -> anyhow::Result<Self> {
         let config = Config::from_file(config_path)?;
-        let app_config = config::AppConfig::from_toml("config.toml")?;
-        let file_processor = FileProcessor::new();
-
-        let model_endpoint = model_endpoint
-            .unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".to_string());
-        let grader = Grader::new(&model_endpoint, &config, Some(app_config.clone()))?;
-
-        let excel_generator = ExcelGenerator::new();
+        let mut app_config = config::AppConfig::from_toml("config.toml")?;
+        app_config.apply_overrides(&overrides);
+        let layout = app_config
+            .input
+            .as_ref()
+            .and_then(|input| input.layout)
+            .unwrap_or_default();
+        let file_processor = FileProcessor::new()
+            .with_layout(layout)
+            .with_file_selection(&config.files)?;
+        let roster = match app_config
+            .input
+            .as_ref()
+            .and_then(|input| input.roster.as_deref())
+        {
+            Some(path) => {
+                let roster = Roster::from_csv(path)?;
+                tracing::info!("Loaded roster of {} students", roster.students.len());
+                Some(roster)
+            }
+            None => None,
+        };
+
+        let grader = Grader::new(&config, Some(app_config.clone()))?;
+
+        let mut excel_generator = ExcelGenerator::new().with_scale(config.scale);
+        if let Some(passing_score) = app_config
+            .report
+            .as_ref()
+            .and_then(|report| report.passing_score)
+        {
+            excel_generator = excel_generator.with_passing_score(passing_score);
+        }
 
         Ok(Self {
             config,
@@ -45,6 +75,7 @@ impl PaperSage {
             file_processor,
             grader,
             excel_generator,
+            roster,
         })
     }
 
@@ -52,10 +83,11 @@ impl PaperSage {
     pub async fn grade_submissions(&self, input_dir: &str) -> anyhow::Result<Vec<GradingResult>> {
         tracing::info!("Starting to grade submissions in: {}", input_dir);
 
-        let results = self
+        let mut results = self
             .grader
             .grade_submissions(input_dir, &self.file_processor)
             .await?;
+        self.reconcile(&mut results);
 
         tracing::info!("Completed grading {} submissions", results.len());
         Ok(results)
@@ -69,10 +101,11 @@ impl PaperSage {
     ) -> anyhow::Result<Vec<GradingResult>> {
         tracing::info!("Resuming grading from: {}", resume_path);
 
-        let results = self
+        let mut results = self
             .grader
             .resume_grading(input_dir, &self.file_processor, resume_path)
             .await?;
+        self.reconcile(&mut results);
 
         tracing::info!(
             "Completed resuming grading for {} submissions",
@@ -81,6 +114,14 @@ impl PaperSage {
         Ok(results)
     }
 
+    /// Match results to the class roster, when one is configured, adding rows for students
+    /// without a submission
+    pub fn reconcile(&self, results: &mut Vec<GradingResult>) {
+        if let Some(roster) = &self.roster {
+            roster.reconcile(results);
+        }
+    }
+
     /// Generate reports from grading results
     pub fn generate_reports(
         &self,
@@ -89,8 +130,9 @@ impl PaperSage {
     ) -> anyhow::Result<()> {
         tracing::info!("Generating reports for {} results", results.len());
 
-        // Generate CSV report
-        self.excel_generator.generate_report(results, output_path)?;
+        // Generate XLSX workbook and CSV export
+        self.excel_generator
+            .generate_report(results, &self.config.rubric, output_path)?;
 
         // Save JSON results for potential resume
         let json_path = "results.json";
@@ -1,6 +1,8 @@
 use clap::Parser;
-use paper_sage::PaperSage;
-use tracing::{info, Level};
+use paper_sage::config::Overrides;
+use paper_sage::file_processor::Layout;
+use paper_sage::{GradingStopped, PaperSage, ProviderKind};
+use tracing::{info, warn, Level};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,11 +15,41 @@ struct Args {
     #[arg(short, long)]
     config: String,
 
-    /// Base URL of the AI model endpoint (defaults to OpenAI)
+    /// Base URL of the AI model endpoint (defaults depend on the provider)
     #[arg(short, long)]
     model_endpoint: Option<String>,
 
-    /// Resume from partial results file
+    /// Model provider backend (overrides `ai.provider` in config.toml, defaults to openai)
+    #[arg(short, long, value_enum)]
+    provider: Option<ProviderKind>,
+
+    /// Model name sent to the provider (overrides `ai.model` in config.toml)
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Number of submissions graded concurrently (overrides `ai.concurrency`, defaults to 1)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency: Option<u16>,
+
+    /// Always query the model, neither reading nor writing the response cache
+    #[arg(long, conflicts_with = "refresh_cache")]
+    no_cache: bool,
+
+    /// Ignore cached responses and replace them with fresh ones
+    #[arg(long)]
+    refresh_cache: bool,
+
+    /// How student names and LMS ids are read from submission paths
+    /// (overrides `input.layout` in config.toml, defaults to folder-per-student)
+    #[arg(long, value_enum)]
+    layout: Option<Layout>,
+
+    /// Class roster CSV (id, name, email, section); students without a submission are
+    /// reported as missing (overrides `input.roster` in config.toml)
+    #[arg(long)]
+    roster: Option<String>,
+
+    /// Resume from an earlier results.json, grading again everything not `graded`
     #[arg(short, long)]
     resume: Option<String>,
 }
@@ -34,7 +66,17 @@ async fn main() -> anyhow::Result<()> {
     info!("Config file: {}", args.config);
 
     // Initialize Paper Sage application
-    let paper_sage = PaperSage::new(&args.config, args.model_endpoint)?;
+    let overrides = Overrides {
+        model_endpoint: args.model_endpoint,
+        provider: args.provider,
+        model: args.model,
+        concurrency: args.concurrency.map(usize::from),
+        no_cache: args.no_cache,
+        refresh_cache: args.refresh_cache,
+        layout: args.layout,
+        roster: args.roster,
+    };
+    let paper_sage = PaperSage::new(&args.config, overrides)?;
 
     info!(
         "Loaded configuration: {}",
@@ -42,12 +84,28 @@ async fn main() -> anyhow::Result<()> {
     );
 
     // Process files and generate grades
-    let results = if let Some(resume_path) = args.resume {
+    let outcome = if let Some(resume_path) = args.resume {
         info!("Resuming from: {}", resume_path);
-        paper_sage.resume_grading(&args.input, &resume_path).await?
+        paper_sage.resume_grading(&args.input, &resume_path).await
     } else {
         info!("Starting fresh grading session");
-        paper_sage.grade_submissions(&args.input).await?
+        paper_sage.grade_submissions(&args.input).await
+    };
+    let results = match outcome {
+        Ok(results) => results,
+        Err(mut e) => {
+            // Keep what was graded before the run stopped, so that it can be resumed
+            if let Some(stopped) = e.downcast_mut::<GradingStopped>() {
+                let mut results = std::mem::take(&mut stopped.results);
+                warn!(
+                    "Writing the {} completed results, rerun with --resume results.json to grade the rest",
+                    results.len()
+                );
+                paper_sage.reconcile(&mut results);
+                paper_sage.generate_reports(&results, "results.xlsx")?;
+            }
+            return Err(e);
+        }
     };
 
     // Generate reports
@@ -1,13 +1,207 @@
+use crate::executor::Language;
+use crate::roster::RosterEntry;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub task_description: String,
+    /// Free-form guidance shown to the model alongside the rubric
+    #[serde(default)]
     pub evaluation_criteria: Vec<String>,
     pub teacher_comment: Option<String>,
-    pub grading_strategy: GradingStrategy,
+    /// Scored criteria; the total is the weighted sum of per-criterion percentages
+    #[serde(default)]
+    pub rubric: Vec<Criterion>,
+    /// Legacy fixed weights, converted into a rubric when `rubric` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grading_strategy: Option<GradingStrategy>,
+    /// Instructor test cases run against each submission before grading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution: Option<ExecutionConfig>,
+    /// Which files of a submission are graded
+    #[serde(default)]
+    pub files: FileSelection,
+    /// Scale totals are reported on
+    #[serde(default)]
+    pub scale: ScoreScale,
 }
 
+/// How scores are reported: each criterion in points out of its `max_points`, the weighted
+/// total out of `total`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(from = "ScaleConfig")]
+pub struct ScoreScale {
+    /// Maximum of the total, 100 (the default) for a percentage
+    pub total: f32,
+    /// Scores and totals are rounded to a multiple of this, e.g. 0.5 for half points
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f32>,
+    /// What the total is reported in
+    pub unit: ScoreUnit,
+}
+
+/// Unit of a scale's total
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreUnit {
+    /// A percentage, out of 100
+    Percent,
+    /// Points out of the scale's total
+    Points,
+}
+
+/// `scale` as written in the assignment config: a scale given a `total` is in points unless
+/// its `unit` says otherwise, one without is a percentage
+#[derive(Deserialize)]
+struct ScaleConfig {
+    total: Option<f32>,
+    #[serde(default)]
+    step: Option<f32>,
+    unit: Option<ScoreUnit>,
+}
+
+impl From<ScaleConfig> for ScoreScale {
+    fn from(config: ScaleConfig) -> Self {
+        let unit = config.unit.unwrap_or(match config.total {
+            Some(_) => ScoreUnit::Points,
+            None => ScoreUnit::Percent,
+        });
+        Self {
+            total: config.total.unwrap_or(Self::percent()),
+            step: config.step,
+            unit,
+        }
+    }
+}
+
+impl Default for ScoreScale {
+    fn default() -> Self {
+        Self {
+            total: Self::percent(),
+            step: None,
+            unit: ScoreUnit::Percent,
+        }
+    }
+}
+
+impl ScoreScale {
+    fn percent() -> f32 {
+        100.0
+    }
+
+    /// Round to the configured step, if any
+    pub fn round(&self, value: f32) -> f32 {
+        match self.step {
+            Some(step) => (value / step).round() * step,
+            None => value,
+        }
+    }
+
+    /// Express a percentage on this scale
+    pub fn from_percent(&self, percent: f32) -> f32 {
+        percent * self.total / 100.0
+    }
+
+    /// How totals are annotated in reports: `%`, or `/20` for points
+    pub fn label(&self) -> String {
+        match self.unit {
+            ScoreUnit::Percent => "%".to_string(),
+            ScoreUnit::Points => format!("/{}", self.total),
+        }
+    }
+}
+
+/// Glob patterns matched against paths inside a student's folder (`src/**/*.py`)
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FileSelection {
+    /// When given, only files matching one of these are graded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Files matching one of these are never graded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// A single scored rubric criterion
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Criterion {
+    /// Stable identifier used as the JSON key and report column
+    pub id: String,
+    pub description: String,
+    pub max_points: f32,
+    /// Share of the total score, all weights must sum to 1.0
+    pub weight: f32,
+}
+
+/// How submissions are built and tested in the sandbox
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecutionConfig {
+    /// Language to build with; detected from file extensions when omitted
+    pub language: Option<Language>,
+    /// Path of the program entry point inside the student folder (e.g. `main.py`)
+    pub entry_point: Option<String>,
+    /// Wall-clock limit for each test case
+    pub timeout_secs: Option<u64>,
+    /// Wall-clock limit for the build step
+    pub build_timeout_secs: Option<u64>,
+    /// Address space limit for each test case
+    pub memory_mb: Option<u64>,
+    /// Address space limit for the build step
+    pub build_memory_mb: Option<u64>,
+    /// Refuse to run code when it cannot be isolated from the network and the file system
+    /// (default true)
+    pub require_isolation: Option<bool>,
+    /// Rubric criterion whose score is set from the test pass rate
+    pub criterion: Option<String>,
+    pub test_cases: Vec<TestCase>,
+}
+
+/// A single instructor test case
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TestCase {
+    pub name: String,
+    /// Command-line arguments passed to the student program
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub stdin: Option<String>,
+    /// Expected standard output, compared ignoring trailing whitespace
+    pub expected_stdout: Option<String>,
+    /// Command run instead of the student program, e.g. a unit test runner
+    pub command: Option<Vec<String>>,
+    /// Instructor files copied into the workspace, relative to the config file
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// Pass/fail counts from running the instructor test cases
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub total: usize,
+}
+
+impl TestSummary {
+    pub fn pass_rate(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.passed as f32 / self.total as f32
+        }
+    }
+}
+
+impl std::fmt::Display for TestSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.passed, self.total)
+    }
+}
+
+/// Criterion ids of [`GradingStrategy::to_rubric`], which results from before rubrics
+/// stored as top-level fields
+const LEGACY_DIMENSIONS: [&str; 3] = ["correctness", "style", "edge_cases"];
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GradingStrategy {
     pub correctness_weight: f32,
@@ -15,14 +209,319 @@ pub struct GradingStrategy {
     pub edge_cases_weight: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl GradingStrategy {
+    /// Convert the legacy three-dimension weights into equivalent rubric criteria
+    pub fn to_rubric(&self) -> Vec<Criterion> {
+        vec![
+            Criterion {
+                id: "correctness".to_string(),
+                description: "The submission correctly implements the required functionality"
+                    .to_string(),
+                max_points: 100.0,
+                weight: self.correctness_weight,
+            },
+            Criterion {
+                id: "style".to_string(),
+                description: "Code is readable, well-structured and follows conventions"
+                    .to_string(),
+                max_points: 100.0,
+                weight: self.style_weight,
+            },
+            Criterion {
+                id: "edge_cases".to_string(),
+                description: "Boundary conditions and error cases are handled properly".to_string(),
+                max_points: 100.0,
+                weight: self.edge_cases_weight,
+            },
+        ]
+    }
+}
+
+/// Outcome of grading a submission
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradingStatus {
+    /// Scored by the model
+    #[default]
+    Graded,
+    /// No grade could be obtained, the comment holds the error
+    Failed,
+    /// Pseudo-grade from the mock provider, not an assessment
+    Mock,
+    /// Nothing to grade, e.g. no readable files in the submission
+    Skipped,
+    /// Roster student without a submission
+    Missing,
+    /// Scored by the model, but part of the submission could not be read automatically
+    /// (e.g. scanned PDF pages) and the grade needs a human look
+    NeedsReview,
+}
+
+impl GradingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GradingStatus::Graded => "graded",
+            GradingStatus::Failed => "failed",
+            GradingStatus::Mock => "mock",
+            GradingStatus::Skipped => "skipped",
+            GradingStatus::Missing => "missing",
+            GradingStatus::NeedsReview => "needs_review",
+        }
+    }
+
+    /// Whether the result carries scores and a total, mock grades included
+    pub fn is_scored(&self) -> bool {
+        matches!(
+            self,
+            GradingStatus::Graded | GradingStatus::NeedsReview | GradingStatus::Mock
+        )
+    }
+}
+
+impl std::fmt::Display for GradingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tokens a model request consumed, as reported by the API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// How a grade was produced, for auditing and reproducing it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Provenance {
+    pub provider: String,
+    /// Hash of the prompt template, changes whenever the template is edited
+    pub prompt_template: String,
+    pub temperature: f32,
+    /// When grading the submission started and finished (RFC 3339, UTC)
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub finished_at: String,
+    /// Time spent waiting for the model, retries and rate limit pauses included
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Model responses the grade is based on, more than one for submissions graded in parts
+    #[serde(default)]
+    pub requests: u32,
+    /// How many of those responses came from the response cache
+    #[serde(default)]
+    pub cached: u32,
+    /// Tokens used by all requests, when the API reports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    /// Raw text of the last model response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_response: Option<String>,
+    /// Replies rejected as invalid grades, each followed by a request to correct it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repairs: Vec<Repair>,
+}
+
+/// A model reply that was not a valid grade and the problems sent back to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Repair {
+    pub problems: Vec<String>,
+    pub response: String,
+}
+
+impl Provenance {
+    /// Account for the requests behind another result this one was built from
+    pub fn absorb(&mut self, other: &Provenance) {
+        self.latency_ms += other.latency_ms;
+        self.requests += other.requests;
+        self.cached += other.cached;
+        if let Some(usage) = other.usage {
+            *self.usage.get_or_insert_with(TokenUsage::default) += usage;
+        }
+        self.repairs.extend(other.repairs.iter().cloned());
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GradingResult {
     pub filename: String,
-    pub correctness: f32,
-    pub style: f32,
-    pub edge_cases: f32,
+    /// Stable identifier of the graded submission, for joining results across runs
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub submission_id: String,
+    /// Student identifier from the LMS export, when the layout provides one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<String>,
+    /// Section of the student on the class roster, when the roster lists one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// Whether the scores are a grade, and if not why; results written before it was recorded
+    /// are classified by [`GradingResult::read_all`]
+    #[serde(default)]
+    pub status: GradingStatus,
+    /// Points awarded per rubric criterion id
+    #[serde(default)]
+    pub scores: BTreeMap<String, f32>,
+    /// Weighted total on the assignment's scale, out of `out_of`
     pub total: f32,
+    /// Maximum of `total`; absent for results that carry no grade and for results from
+    /// before it was recorded, whose totals are percentages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_of: Option<f32>,
     pub comment: String,
+    /// Model that produced the grade (absent for legacy results)
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Instructor test results, when the assignment defines test cases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests: Option<TestSummary>,
+    /// Processing notes carried over from the submission
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Document metadata (author, creation date, ...) per file, for integrity checks
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, BTreeMap<String, String>>,
+    /// Provider, prompt and request details behind the grade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// Sort key of a submission or result in the report
+pub type ReportOrder = (String, String, String);
+
+/// Order submissions and results are reported in: by student name ignoring case, ties broken
+/// by the exact name and the stable submission id, so that rows come out the same with and
+/// without a roster
+pub fn report_order(student_name: &str, submission_id: &str) -> ReportOrder {
+    (
+        student_name.to_lowercase(),
+        student_name.to_string(),
+        submission_id.to_string(),
+    )
+}
+
+impl GradingResult {
+    /// Position of the result in the report, see [`report_order`]
+    pub fn report_order(&self) -> ReportOrder {
+        report_order(self.filename.trim_end_matches('/'), &self.submission_id)
+    }
+
+    /// Result recorded when a submission could not be graded
+    pub fn error(filename: String, message: impl std::fmt::Display, model: Option<String>) -> Self {
+        Self {
+            filename,
+            submission_id: String::new(),
+            student_id: None,
+            section: None,
+            status: GradingStatus::Failed,
+            scores: BTreeMap::new(),
+            total: 0.0,
+            out_of: None,
+            comment: format!("Error during grading: {}", message),
+            model,
+            tests: None,
+            notes: Vec::new(),
+            metadata: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+
+    /// Result recorded for a roster student without a submission
+    pub fn missing(student: &RosterEntry) -> Self {
+        Self {
+            filename: format!("{}/", student.display_name()),
+            // Same key a submission from this student would get
+            submission_id: StudentSubmission::stable_id(if student.id.is_empty() {
+                &student.name
+            } else {
+                &student.id
+            }),
+            student_id: (!student.id.is_empty()).then(|| student.id.clone()),
+            section: (!student.section.is_empty()).then(|| student.section.clone()),
+            status: GradingStatus::Missing,
+            scores: BTreeMap::new(),
+            total: 0.0,
+            out_of: None,
+            comment: "No submission found".to_string(),
+            model: None,
+            tests: None,
+            notes: Vec::new(),
+            metadata: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+
+    /// Result recorded for a submission with nothing to grade
+    pub fn skipped(filename: String, reason: impl std::fmt::Display) -> Self {
+        Self {
+            filename,
+            submission_id: String::new(),
+            student_id: None,
+            section: None,
+            status: GradingStatus::Skipped,
+            scores: BTreeMap::new(),
+            total: 0.0,
+            out_of: None,
+            comment: format!("Not graded: {}", reason),
+            model: None,
+            tests: None,
+            notes: Vec::new(),
+            metadata: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+
+    /// Parse a `results.json`. Results from before the status was recorded get one from
+    /// the fields they have: the old `missing` and `needs_review` flags, the error comment,
+    /// and the mock comment or null model of mock fallback grades. Results from before
+    /// rubrics keep their fixed dimensions as the scores of the legacy rubric
+    pub fn read_all(json: &str) -> serde_json::Result<Vec<Self>> {
+        let mut results: Vec<serde_json::Value> = serde_json::from_str(json)?;
+        for result in &mut results {
+            let Some(fields) = result.as_object_mut() else {
+                continue;
+            };
+            if fields.contains_key("status") {
+                continue;
+            }
+            if !fields.contains_key("scores") {
+                let scores: serde_json::Map<String, serde_json::Value> = LEGACY_DIMENSIONS
+                    .iter()
+                    .filter_map(|id| {
+                        let score = fields.get(*id).filter(|v| v.is_number())?;
+                        Some((id.to_string(), score.clone()))
+                    })
+                    .collect();
+                fields.insert("scores".to_string(), scores.into());
+            }
+
+            let flag = |name: &str| fields.get(name).and_then(|v| v.as_bool()) == Some(true);
+            let comment = fields.get("comment").and_then(|v| v.as_str()).unwrap_or("");
+            // Results from before the model was recorded have no `model` at all
+            let status = if flag("missing") {
+                GradingStatus::Missing
+            } else if comment.starts_with("Error during grading") {
+                GradingStatus::Failed
+            } else if comment.starts_with("Mock grading response")
+                || fields.get("model").is_some_and(|v| v.is_null())
+            {
+                GradingStatus::Mock
+            } else if flag("needs_review") {
+                GradingStatus::NeedsReview
+            } else {
+                GradingStatus::Graded
+            };
+            fields.insert("status".to_string(), status.as_str().into());
+        }
+        results.into_iter().map(serde_json::from_value).collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,40 +529,128 @@ pub struct FileContent {
     pub filename: String,
     pub content: String,
     pub file_type: String,
+    /// Document metadata such as author and creation date
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Why the extracted text is incomplete and needs a human look
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<String>,
+    /// How the text was read when that is worth knowing, e.g. the encoding or truncation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// `content` is only the start of the file, which was too large to read whole
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl FileContent {
+    /// The file as it appears in the prompt
+    pub fn render(&self) -> String {
+        let marker = if self.truncated {
+            "\n[... truncated ...]"
+        } else {
+            ""
+        };
+        format!("{}{}{}\n\n", self.header(""), self.content, marker)
+    }
+
+    /// A range of lines of the file, for files split across several prompts
+    pub fn section(&self, first_line: usize, last_line: usize, body: &str) -> String {
+        let range = format!("lines {}-{}", first_line, last_line);
+        format!("{}{}\n\n", self.header(&range), body.trim_end_matches('\n'))
+    }
+
+    /// Heading introducing the file, with an optional label after the name
+    pub fn header(&self, label: &str) -> String {
+        let name = if label.is_empty() {
+            self.filename.clone()
+        } else {
+            format!("{} ({})", self.filename, label)
+        };
+        format!(
+            "=== FILE: {} ===\nType: {}\nContent:\n",
+            name, self.file_type
+        )
+    }
 }
 
 /// Represents a complete student submission with multiple files
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StudentSubmission {
+    /// Stable identifier derived from the LMS id or the student name
+    #[serde(default)]
+    pub submission_id: String,
     pub student_name: String,
+    /// Identifier assigned by the LMS the submissions were exported from
+    #[serde(default)]
+    pub student_id: Option<String>,
     pub files: Vec<FileContent>,
     pub merged_content: String,
+    /// Problems met while reading the submission, such as unreadable documents
+    #[serde(default)]
+    pub notes: Vec<String>,
 }
 
 impl StudentSubmission {
     /// Create a new student submission from a list of files
     pub fn new(student_name: String, files: Vec<FileContent>) -> Self {
-        let merged_content = Self::merge_files(&files);
+        Self::with_notes(student_name, files, Vec::new())
+    }
+
+    /// Create a submission that also records processing notes, which are shown to the model
+    pub fn with_notes(student_name: String, files: Vec<FileContent>, notes: Vec<String>) -> Self {
+        let mut merged_content = Self::merge_files(&files);
+        merged_content.push_str(&Self::render_notes(&notes));
         Self {
+            submission_id: Self::stable_id(&student_name),
             student_name,
+            student_id: None,
             files,
             merged_content,
+            notes,
         }
     }
 
+    /// Identifier that stays the same across runs for the same student key
+    pub fn stable_id(key: &str) -> String {
+        let digest = Sha256::digest(key.as_bytes());
+        format!("{:x}", digest)[..16].to_string()
+    }
+
+    /// Position of the submission in the report, see [`report_order`]
+    pub fn report_order(&self) -> ReportOrder {
+        report_order(&self.student_name, &self.submission_id)
+    }
+
     /// Merge all files into a single content string with folder hierarchy preserved
     fn merge_files(files: &[FileContent]) -> String {
-        let mut merged = String::new();
-
-        for file in files {
-            merged.push_str(&format!("=== FILE: {} ===\n", file.filename));
-            merged.push_str(&format!("Type: {}\n", file.file_type));
-            merged.push_str("Content:\n");
-            merged.push_str(&file.content);
-            merged.push_str("\n\n");
+        files.iter().map(FileContent::render).collect()
+    }
+
+    /// Processing notes as shown to the model after the files
+    pub fn render_notes(notes: &[String]) -> String {
+        if notes.is_empty() {
+            return String::new();
+        }
+        let mut rendered = "=== PROCESSING NOTES ===\n".to_string();
+        for note in notes {
+            rendered.push_str(&format!("- {}\n", note));
         }
+        rendered
+    }
+
+    /// Whether any file could only be partially read and needs manual review
+    pub fn needs_review(&self) -> bool {
+        self.files.iter().any(|file| file.review.is_some())
+    }
 
-        merged
+    /// Metadata of the files that have any, keyed by filename
+    pub fn metadata(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        self.files
+            .iter()
+            .filter(|file| !file.metadata.is_empty())
+            .map(|file| (file.filename.clone(), file.metadata.clone()))
+            .collect()
     }
 
     /// Get the main filename for the submission (usually the student folder name)
@@ -72,23 +659,30 @@ impl StudentSubmission {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GradingRequest {
     pub filename: String,
     pub content: String,
     pub task_description: String,
     pub evaluation_criteria: Vec<String>,
+    pub rubric: Vec<Criterion>,
     pub teacher_comment: Option<String>,
+    /// Rendered test results shown to the model as evidence
+    pub execution_results: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GradingResponse {
-    pub filename: String,
-    pub correctness: f32,
-    pub style: f32,
-    pub edge_cases: f32,
-    pub total: f32,
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// Points per criterion id
+    #[serde(default)]
+    pub scores: HashMap<String, f32>,
+    #[serde(default)]
     pub comment: String,
+    /// Anything else the model emitted, used to recover scores given as top-level keys
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 // OpenAI API structures
@@ -104,11 +698,15 @@ pub struct OpenAIRequest {
     pub messages: Vec<OpenAIMessage>,
     pub temperature: f32,
     pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAIResponse {
     pub choices: Vec<OpenAIChoice>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -120,13 +718,32 @@ pub struct OpenAIChoice {
 #[derive(Debug, Serialize)]
 pub struct OllamaRequest {
     pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
     pub prompt: String,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+    /// JSON schema the response must follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OllamaOptions {
+    /// Context window size, Ollama silently drops the start of longer prompts
+    pub num_ctx: usize,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OllamaResponse {
     pub response: String,
+    /// Tokens in the prompt, absent when Ollama reused a cached prompt
+    #[serde(default)]
+    pub prompt_eval_count: Option<u64>,
+    /// Tokens generated
+    #[serde(default)]
+    pub eval_count: Option<u64>,
 }
 
 impl Default for GradingStrategy {
@@ -138,3 +755,113 @@ impl Default for GradingStrategy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_one(json: &str) -> GradingResult {
+        GradingResult::read_all(&format!("[{}]", json))
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn marks_truncated_files_in_the_prompt_only() {
+        let mut file = FileContent {
+            filename: "alice/main.py".to_string(),
+            content: "print(1)".to_string(),
+            file_type: "py".to_string(),
+            metadata: BTreeMap::new(),
+            review: None,
+            notes: Vec::new(),
+            truncated: false,
+        };
+        assert!(file.render().ends_with("Content:\nprint(1)\n\n"));
+
+        file.truncated = true;
+        assert!(file
+            .render()
+            .ends_with("Content:\nprint(1)\n[... truncated ...]\n\n"));
+        assert_eq!(file.content, "print(1)");
+    }
+
+    #[test]
+    fn reads_results_from_before_rubrics() {
+        let result = read_one(
+            r#"{"filename": "alice/", "correctness": 90.0, "style": 70.0, "edge_cases": 50.0,
+                "total": 76.0, "comment": "Solid work"}"#,
+        );
+        assert_eq!(result.status, GradingStatus::Graded);
+        assert_eq!(result.scores["correctness"], 90.0);
+        assert_eq!(result.scores["style"], 70.0);
+        assert_eq!(result.scores["edge_cases"], 50.0);
+        assert_eq!(result.model, None);
+    }
+
+    #[test]
+    fn infers_the_status_of_older_results() {
+        let mock = read_one(
+            r#"{"filename": "a/", "correctness": 0.8, "style": 0.7, "edge_cases": 0.6,
+                "total": 0.73, "comment": "Mock grading response - AI API was unavailable."}"#,
+        );
+        assert_eq!(mock.status, GradingStatus::Mock);
+
+        let fallback = read_one(
+            r#"{"filename": "a/", "scores": {}, "total": 0, "comment": "", "model": null}"#,
+        );
+        assert_eq!(fallback.status, GradingStatus::Mock);
+
+        let failed = read_one(
+            r#"{"filename": "a/", "scores": {}, "total": 0, "model": "m",
+                "comment": "Error during grading: timeout"}"#,
+        );
+        assert_eq!(failed.status, GradingStatus::Failed);
+
+        let missing = read_one(
+            r#"{"filename": "a/", "scores": {}, "total": 0, "comment": "", "model": null,
+                "missing": true}"#,
+        );
+        assert_eq!(missing.status, GradingStatus::Missing);
+
+        let review = read_one(
+            r#"{"filename": "a/", "scores": {"x": 1}, "total": 10, "comment": "", "model": "m",
+                "needs_review": true}"#,
+        );
+        assert_eq!(review.status, GradingStatus::NeedsReview);
+    }
+
+    #[test]
+    fn keeps_a_recorded_status() {
+        let result = read_one(
+            r#"{"filename": "a/", "status": "failed", "scores": {"x": 1}, "total": 10,
+                "comment": "", "model": null}"#,
+        );
+        assert_eq!(result.status, GradingStatus::Failed);
+        assert_eq!(result.scores["x"], 1.0);
+    }
+
+    fn scale(json: &str) -> ScoreScale {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn labels_scales_by_their_unit() {
+        assert_eq!(ScoreScale::default().label(), "%");
+        assert_eq!(scale(r#"{"step": 0.5}"#).label(), "%");
+        assert_eq!(scale(r#"{"total": 20}"#).label(), "/20");
+        assert_eq!(scale(r#"{"total": 100}"#).label(), "/100");
+        assert_eq!(scale(r#"{"total": 100, "unit": "percent"}"#).label(), "%");
+        assert_eq!(scale(r#"{"unit": "points"}"#).label(), "/100");
+    }
+
+    #[test]
+    fn rounds_to_the_step() {
+        let halves = scale(r#"{"total": 20, "step": 0.5}"#);
+        assert_eq!(halves.round(7.3), 7.5);
+        assert_eq!(halves.round(7.2), 7.0);
+        assert_eq!(halves.from_percent(85.0), 17.0);
+        assert_eq!(scale(r#"{"total": 20}"#).round(7.3), 7.3);
+        assert_eq!(scale(r#"{"step": 5}"#).round(83.0), 85.0);
+    }
+}
@@ -0,0 +1,447 @@
+use crate::models::{GradingResult, GradingStatus};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// Largest edit distance at which a roster name is suggested for an unmatched folder
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+/// Start of the note added to submissions that match no roster entry
+const UNMATCHED_NOTE: &str = "Not on the roster";
+/// Start of the note added to submissions that match a roster entry another submission matched
+const DUPLICATE_NOTE: &str = "Same roster entry as";
+
+/// A student expected to submit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RosterEntry {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub section: String,
+}
+
+impl RosterEntry {
+    /// Normalised forms a folder name may take for this student
+    fn keys(&self) -> Vec<String> {
+        let mut keys = name_keys(&self.name);
+        if let Some((local, _)) = self.email.split_once('@') {
+            keys.extend(name_keys(local));
+        }
+        let id = self.id.trim().to_lowercase();
+        if !id.is_empty() {
+            keys.push(id);
+        }
+        keys
+    }
+
+    /// Label used in report rows and messages
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            &self.id
+        } else {
+            &self.name
+        }
+    }
+
+    /// Name followed by the id, when both are known
+    fn label(&self) -> String {
+        if self.name.is_empty() || self.id.is_empty() {
+            self.display_name().to_string()
+        } else {
+            format!("{} ({})", self.name, self.id)
+        }
+    }
+}
+
+/// The class list submissions are checked against
+#[derive(Debug, Clone, Default)]
+pub struct Roster {
+    pub students: Vec<RosterEntry>,
+}
+
+impl Roster {
+    /// Read a roster CSV with a header row naming its columns; an id or a name column is required,
+    /// email and section are optional and first/last name columns are combined
+    pub fn from_csv(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read roster: {}", path))?;
+        Self::parse(&content).with_context(|| format!("Failed to parse roster: {}", path))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut rows = parse_csv(content.trim_start_matches('\u{feff}')).into_iter();
+        let header = rows.next().context("Roster is empty")?;
+
+        let column = |names: &[&str]| {
+            header.iter().position(|title| {
+                let title: String = title
+                    .chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .collect::<String>()
+                    .to_lowercase();
+                names.contains(&title.as_str())
+            })
+        };
+        let id = column(&[
+            "id",
+            "studentid",
+            "userid",
+            "sisid",
+            "sisuserid",
+            "idnumber",
+        ]);
+        let name = column(&["name", "fullname", "studentname", "student"]);
+        let first = column(&["firstname", "givenname"]);
+        let last = column(&["lastname", "surname", "familyname"]);
+        let email = column(&["email", "emailaddress", "mail"]);
+        let section = column(&["section", "group", "class"]);
+        if id.is_none() && name.is_none() && first.is_none() && last.is_none() {
+            anyhow::bail!("Roster needs an id or a name column");
+        }
+
+        let students = rows
+            .map(|row| {
+                let field = |index: Option<usize>| {
+                    index
+                        .and_then(|i| row.get(i))
+                        .map(|value| value.trim().to_string())
+                        .unwrap_or_default()
+                };
+                let name = match name {
+                    Some(_) => field(name),
+                    None => format!("{} {}", field(first), field(last))
+                        .trim()
+                        .to_string(),
+                };
+                RosterEntry {
+                    id: field(id),
+                    name,
+                    email: field(email),
+                    section: field(section),
+                }
+            })
+            .filter(|entry| !entry.id.is_empty() || !entry.name.is_empty())
+            .collect();
+        Ok(Self { students })
+    }
+
+    /// Match results to roster entries: results get the roster id and section, unmatched
+    /// folders and several folders of one student are noted (with a suggestion for likely
+    /// misspellings), every student without a submission is added as a missing row and the
+    /// rows are sorted by name
+    pub fn reconcile(&self, results: &mut Vec<GradingResult>) {
+        // Missing rows and roster notes from an earlier run are rebuilt below
+        results.retain(|result| result.status != GradingStatus::Missing);
+        for result in results.iter_mut() {
+            result.notes.retain(|note| {
+                !note.starts_with(UNMATCHED_NOTE) && !note.starts_with(DUPLICATE_NOTE)
+            });
+        }
+        // In name order, so that the same one of several submissions of a student is first
+        results.sort_by_cached_key(GradingResult::report_order);
+
+        let keys: Vec<Vec<String>> = self.students.iter().map(RosterEntry::keys).collect();
+        // Roster entry index -> folder of the first submission matching it
+        let mut matched: HashMap<usize, String> = HashMap::new();
+        let mut unmatched = Vec::new();
+        for (i, result) in results.iter_mut().enumerate() {
+            let folder = result.filename.trim_end_matches('/');
+            let by_id = result.student_id.as_ref().and_then(|id| {
+                self.students
+                    .iter()
+                    .position(|student| !student.id.is_empty() && &student.id == id)
+            });
+            let folder_keys = name_keys(folder);
+            let found = by_id.or_else(|| {
+                keys.iter()
+                    .position(|keys| folder_keys.iter().any(|key| keys.contains(key)))
+            });
+            match found {
+                Some(index) => {
+                    let student = &self.students[index];
+                    if result.student_id.is_none() && !student.id.is_empty() {
+                        result.student_id = Some(student.id.clone());
+                    }
+                    if !student.section.is_empty() {
+                        result.section = Some(student.section.clone());
+                    }
+                    match matched.get(&index) {
+                        Some(first) => {
+                            warn!(
+                                "Submissions {} and {} both match roster entry {}",
+                                first,
+                                folder,
+                                student.label()
+                            );
+                            result.notes.push(format!(
+                                "{} {}: {}",
+                                DUPLICATE_NOTE,
+                                first,
+                                student.label()
+                            ));
+                        }
+                        None => {
+                            matched.insert(index, folder.to_string());
+                        }
+                    }
+                }
+                None => unmatched.push(i),
+            }
+        }
+
+        for i in unmatched {
+            let result = &mut results[i];
+            let folder = result.filename.trim_end_matches('/').to_string();
+            let suggestion = self
+                .students
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !matched.contains_key(index))
+                .filter_map(|(_, student)| {
+                    let distance = name_distance(&folder, &student.name)?;
+                    let limit = MAX_SUGGESTION_DISTANCE.min(student.name.chars().count() / 3);
+                    (distance <= limit).then_some((distance, student))
+                })
+                .min_by_key(|(distance, _)| *distance)
+                .map(|(_, student)| student);
+
+            let note = match suggestion {
+                Some(student) => {
+                    warn!(
+                        "Submission {} is not on the roster, did you mean {}?",
+                        folder,
+                        student.label()
+                    );
+                    format!("{}, did you mean {}?", UNMATCHED_NOTE, student.label())
+                }
+                None => {
+                    warn!("Submission {} is not on the roster", folder);
+                    UNMATCHED_NOTE.to_string()
+                }
+            };
+            result.notes.push(note);
+        }
+
+        let missing: Vec<&RosterEntry> = self
+            .students
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !matched.contains_key(index))
+            .map(|(_, student)| student)
+            .collect();
+        info!(
+            "Roster: {} of {} students submitted, {} missing",
+            self.students.len() - missing.len(),
+            self.students.len(),
+            missing.len()
+        );
+        results.extend(missing.into_iter().map(GradingResult::missing));
+        results.sort_by_cached_key(GradingResult::report_order);
+    }
+}
+
+/// Lowercase alphanumeric words of a name, in both orders and as a sorted set, so that
+/// `Smith, Alice`, `alice_smith` and `smithalice` all meet
+fn name_keys(name: &str) -> Vec<String> {
+    let words = words(name);
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = words.clone();
+    sorted.sort();
+    let mut reversed = words.clone();
+    reversed.reverse();
+    vec![sorted.join(" "), words.concat(), reversed.concat()]
+}
+
+fn words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Smallest edit distance between the word orders of two names
+fn name_distance(folder: &str, name: &str) -> Option<usize> {
+    let folder = words(folder);
+    let name = words(name);
+    if folder.is_empty() || name.is_empty() {
+        return None;
+    }
+    let mut reversed = name.clone();
+    reversed.reverse();
+    [name.concat(), reversed.concat()]
+        .iter()
+        .map(|candidate| levenshtein(&folder.concat(), candidate))
+        .min()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Split CSV text into rows of fields, honouring quoted fields with embedded commas,
+/// doubled quotes and line breaks
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|value| !value.trim().is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    row.push(field);
+    if row.iter().any(|value| !value.trim().is_empty()) {
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROSTER: &str = "\u{feff}Student ID,Last Name,First Name,Email,Section\r\n\
+        1001,Smith,Alice,alice@example.edu,A\r\n\
+        1002,Doe,John,jdoe@example.edu,B\r\n\
+        1003,\"O'Neil, Jr.\",Pat,pat@example.edu,B\r\n";
+
+    fn graded(filename: &str) -> GradingResult {
+        let mut result = GradingResult::error(filename.to_string(), "x", None);
+        result.status = GradingStatus::Graded;
+        result
+    }
+
+    fn reconciled(folders: &[&str]) -> Vec<GradingResult> {
+        let roster = Roster::parse(ROSTER).unwrap();
+        let mut results = folders.iter().map(|folder| graded(folder)).collect();
+        roster.reconcile(&mut results);
+        results
+    }
+
+    #[test]
+    fn parses_roster_columns() {
+        let roster = Roster::parse(ROSTER).unwrap();
+        assert_eq!(roster.students.len(), 3);
+        assert_eq!(
+            roster.students[2],
+            RosterEntry {
+                id: "1003".to_string(),
+                name: "Pat O'Neil, Jr.".to_string(),
+                email: "pat@example.edu".to_string(),
+                section: "B".to_string(),
+            }
+        );
+        assert!(Roster::parse("Email\nx@example.edu\n").is_err());
+    }
+
+    #[test]
+    fn matches_names_in_any_order_and_form() {
+        for folder in [
+            "alice_smith/",
+            "Smith, Alice/",
+            "smithalice/",
+            "alice/",
+            "1001/",
+        ] {
+            let results = reconciled(&[folder]);
+            let alice = results.iter().find(|r| r.filename == folder).unwrap();
+            assert_eq!(alice.student_id.as_deref(), Some("1001"), "{}", folder);
+            assert_eq!(alice.section.as_deref(), Some("A"));
+            assert!(alice.notes.is_empty());
+        }
+    }
+
+    #[test]
+    fn suggests_a_likely_misspelling() {
+        let results = reconciled(&["Jonh Doe/"]);
+        let jonh = results.iter().find(|r| r.filename == "Jonh Doe/").unwrap();
+        assert_eq!(jonh.student_id, None);
+        assert_eq!(
+            jonh.notes,
+            vec!["Not on the roster, did you mean John Doe (1002)?"]
+        );
+
+        let results = reconciled(&["Zed Zimmermann/"]);
+        let zed = results.iter().find(|r| r.status == GradingStatus::Graded);
+        assert_eq!(zed.unwrap().notes, vec!["Not on the roster"]);
+    }
+
+    #[test]
+    fn adds_missing_students_in_name_order() {
+        let results = reconciled(&["pat_oneil_jr/", "alice_smith/"]);
+        let rows: Vec<(&str, GradingStatus)> = results
+            .iter()
+            .map(|r| (r.filename.as_str(), r.status))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("alice_smith/", GradingStatus::Graded),
+                ("John Doe/", GradingStatus::Missing),
+                ("pat_oneil_jr/", GradingStatus::Graded),
+            ]
+        );
+        assert_eq!(results[1].section.as_deref(), Some("B"));
+    }
+
+    #[test]
+    fn notes_several_submissions_of_one_student() {
+        let results = reconciled(&["alice_smith/", "smith_alice/"]);
+        let noted: Vec<&GradingResult> = results.iter().filter(|r| !r.notes.is_empty()).collect();
+        assert_eq!(noted.len(), 1);
+        assert_eq!(noted[0].filename, "smith_alice/");
+        assert_eq!(
+            noted[0].notes,
+            vec!["Same roster entry as alice_smith: Alice Smith (1001)"]
+        );
+    }
+
+    #[test]
+    fn reconciling_again_gives_the_same_rows() {
+        let roster = Roster::parse(ROSTER).unwrap();
+        let mut results = vec![
+            graded("Jonh Doe/"),
+            graded("alice/"),
+            graded("alice smith/"),
+        ];
+        roster.reconcile(&mut results);
+        let first: Vec<(String, Vec<String>)> = results
+            .iter()
+            .map(|r| (r.filename.clone(), r.notes.clone()))
+            .collect();
+        roster.reconcile(&mut results);
+        let second: Vec<(String, Vec<String>)> = results
+            .iter()
+            .map(|r| (r.filename.clone(), r.notes.clone()))
+            .collect();
+        assert_eq!(first, second);
+    }
+}